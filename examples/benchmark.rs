@@ -0,0 +1,51 @@
+use std::{env, fs, hint::black_box, time::Instant};
+
+use avr8rs::{atmega328p::DEFAULT_FREQ, instruction::instructions::decode, runner::AVRRunner};
+
+/// Reports the simulation speed (in simulated MHz) of a firmware, with the pre-decoded program
+/// memory and with the instruction decoded again before every step, as before pre-decoding
+///
+/// Usage: cargo run --release --example benchmark [hex file] [simulated seconds]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let hex_file = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("build/stepper.ino.hex");
+    let final_time: f64 = args.get(2).map_or(2.5, |x| x.parse().unwrap());
+
+    let hex = fs::read_to_string(hex_file).unwrap();
+    let n_cycles = (final_time * DEFAULT_FREQ as f64) as u64;
+
+    let decoding = run(&hex, n_cycles, true);
+    let pre_decoded = run(&hex, n_cycles, false);
+    println!(
+        "decoding every step: {:.2} MHz, pre-decoded: {:.2} MHz ({:.2}x)",
+        decoding,
+        pre_decoded,
+        pre_decoded / decoding
+    );
+}
+
+/// Simulates `n_cycles` and returns the speed in simulated MHz
+fn run(hex: &str, n_cycles: u64, decode_every_step: bool) -> f64 {
+    let mut runner = AVRRunner::new(hex);
+    let start = Instant::now();
+    while runner.atmega328p.cpu.cycles < n_cycles {
+        if decode_every_step {
+            // decode the current opcode, as the dispatch did before pre-decoding
+            let cpu = &runner.atmega328p.cpu;
+            if let Some(&opcode) = cpu.prog_mem().get(cpu.pc as usize) {
+                black_box(decode(black_box(opcode)));
+            }
+        }
+        runner.step(None).unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!(
+        "simulated {} cycles in {:.3} s",
+        runner.atmega328p.cpu.cycles, elapsed
+    );
+    runner.atmega328p.cpu.cycles as f64 / elapsed / 1e6
+}
@@ -0,0 +1,53 @@
+use std::{
+    env, fs, thread,
+    time::{Duration, Instant},
+};
+
+use avr8rs::{atmega328p::DEFAULT_FREQ, reset::ResetKind, runner::AVRRunner};
+
+const SYNC_CYCLES: u64 = 16_000; // 1ms at 16MHz
+
+/// Runs a firmware along with its bootloader (e.g. the .with_bootloader.hex exported by
+/// arduino-cli, with Optiboot), so that sketches can be uploaded through a pseudo-terminal:
+///
+/// avrdude -p m328p -c arduino -b 115200 -P [pty] -U flash:w:sketch.hex
+///
+/// As with the auto-reset of an Arduino Uno, the MCU is reset into the bootloader when the host
+/// sends characters while the sketch runs, so the sketch itself can not receive any. The
+/// simulation is kept to real time, for the bootloader to wait for the host as long as it would
+/// on the board.
+///
+/// Usage: cargo run --release --example bootloader [hex file]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let hex_file = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("build/serial.ino.with_bootloader.hex");
+
+    let hex = fs::read_to_string(hex_file).unwrap();
+    let mut runner = AVRRunner::new(&hex);
+    let atmega = &mut runner.atmega328p;
+    atmega.usart.clear_outputs(); // the output goes to the host only
+    atmega.fuses.high = 0xde; // Arduino Uno: BOOTRST, 256 words boot section
+    atmega.reset(ResetKind::PowerOn);
+    runner.open_pty().unwrap();
+
+    let start = Instant::now();
+    let mut next_sync = 0;
+    loop {
+        runner.step(None).unwrap();
+        let atmega = &mut runner.atmega328p;
+        if atmega.usart_pending_input() > 0 && !atmega.in_boot_section(atmega.cpu.pc) {
+            atmega.reset(ResetKind::External);
+        }
+
+        if atmega.cpu.cycles >= next_sync {
+            let simulated = Duration::from_secs_f64(atmega.cpu.cycles as f64 / DEFAULT_FREQ as f64);
+            if let Some(ahead) = simulated.checked_sub(start.elapsed()) {
+                thread::sleep(ahead);
+            }
+            next_sync = atmega.cpu.cycles + SYNC_CYCLES;
+        }
+    }
+}
@@ -29,7 +29,7 @@ fn main() {
     let mut s = 0;
     while s < n_steps {
         let cycles = runner.atmega328p.cpu.cycles;
-        runner.step(Some(&mut i2c_bus));
+        runner.step(Some(&mut i2c_bus)).unwrap();
         let delta_cycles = (runner.atmega328p.cpu.cycles - cycles) as usize;
 
         encoder.step(&mut i2c_bus);
@@ -0,0 +1,31 @@
+use std::{env, fs};
+
+use avr8rs::{atmega328p::DEFAULT_FREQ, runner::AVRRunner};
+
+/// Runs a firmware with its serial port on a pseudo-terminal or a TCP port, for the Serial
+/// Monitor or host scripts to open
+///
+/// Usage: cargo run --release --example serial [hex file] [pty | TCP address] [simulated seconds]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let hex_file = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("build/serial.ino.hex");
+    let port = args.get(2).map(String::as_str).unwrap_or("pty");
+    let final_time: Option<f64> = args.get(3).map(|x| x.parse().unwrap());
+
+    let hex = fs::read_to_string(hex_file).unwrap();
+    let mut runner = AVRRunner::new(&hex);
+    runner.atmega328p.usart.clear_outputs(); // the output goes to the host only
+    if port == "pty" {
+        runner.open_pty().unwrap();
+    } else {
+        runner.listen_tcp(port).unwrap(); // e.g. 127.0.0.1:5000
+    }
+
+    let n_cycles = final_time.map(|t| (t * DEFAULT_FREQ as f64) as u64);
+    while n_cycles.is_none_or(|n| runner.atmega328p.cpu.cycles < n) {
+        runner.step(None).unwrap();
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
+    cell::RefCell,
     fs::File,
     io::{BufReader, Read},
+    rc::Rc,
 };
 
 use avr8rs::{
     Float,
     atmega328p::DEFAULT_FREQ,
+    peripheral::port::PinState,
     plot::plot,
     runner::AVRRunner,
     stepper::{StepperMotor, driver::StepperDriver},
@@ -19,7 +22,17 @@ fn main() {
 
     let mut runner = AVRRunner::new(&buf);
 
-    let mut driver = StepperDriver::new(4);
+    // The driver follows the STEP (PD3) and DIR (PD2) pins as they change
+    let driver = Rc::new(RefCell::new(StepperDriver::new(4)));
+    let listener_driver = driver.clone();
+    runner.atmega328p.add_gpio_listener(
+        "D",
+        Box::new(move |_, value, ddr, _| {
+            let step_pin = PinState::from_gpio(value, ddr, 3);
+            let dir_pin = PinState::from_gpio(value, ddr, 2);
+            listener_driver.borrow_mut().step(step_pin, &dir_pin);
+        }),
+    );
     let mut stepper = StepperMotor::new();
 
     let mut data: Vec<Float> = vec![];
@@ -37,17 +50,11 @@ fn main() {
         // print!("cycle: {} ", s);
 
         let cycles = runner.atmega328p.cpu.cycles;
-        runner.step(None);
+        runner.step(None).unwrap();
         let delta_cycles = (runner.atmega328p.cpu.cycles - cycles) as usize;
 
-        for _ in 0..delta_cycles {
-            let step_pin = runner.atmega328p.port_pin_state("D", 3);
-            let dir_pin = runner.atmega328p.port_pin_state("D", 2);
-            driver.step(step_pin, &dir_pin);
-        }
-
         if s % 100 == 0 {
-            let currents = driver.currents();
+            let currents = driver.borrow().currents();
             stepper.step(
                 dt * (s - motor_s) as Float,
                 currents.0,
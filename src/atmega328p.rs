@@ -2,19 +2,28 @@ use std::collections::HashMap;
 
 use crate::{
     cpu::CPU,
+    error::{HexError, SimError, StepOutcome},
+    fuses::Fuses,
     instruction::avr_instruction,
     interrupt::avr_interrupt,
     peripheral::{
-        eeprom::{AVREEPROM, AVREEPROMConfig, EEPROM_CONFIG},
-        i2c::{AVRI2C, TWI_CONFIG, TWIConfig, bus::I2CBus},
-        port::{AVRIOPort, PORTB_CONFIG, PORTC_CONFIG, PORTD_CONFIG},
-        timer::{AVRTimer, TIMER_0_CONFIG},
+        adc::{ADC_CONFIG, AVRADC},
+        eeprom::{AVREEPROM, EEPROM_CONFIG},
+        flash::{AVRFlash, FLASH_CONFIG},
+        i2c::{AVRI2C, TWI_CONFIG, bus::I2CBus},
+        port::{self, AVRIOPort, PORTB_CONFIG, PORTC_CONFIG, PORTD_CONFIG},
+        spi::{AVRSPI, SPI_CONFIG},
+        timer::{AVRTimer, TIMER_0_CONFIG, TIMER_1_CONFIG, TIMER_2_CONFIG},
         usart::{AVRUSART, USART0_CONFIG},
+        watchdog::{AVRWatchdog, WATCHDOG_CONFIG},
     },
     program::load_hex,
+    reset::ResetKind,
+    sleep::SleepMode,
 };
 
 pub const DEFAULT_FREQ: usize = 16_000_000; // 16Mhz
+pub const FLASH_SIZE: usize = 0x8000; // 32KB
 
 pub type PeripheralMemoryReadHook = Box<dyn Fn(&mut ATMega328P, u16) -> u8>;
 pub type PeripheralMemoryWriteHook = Box<dyn Fn(&mut ATMega328P, u8, u8, u16, u8) -> bool>;
@@ -23,11 +32,19 @@ pub struct ATMega328P {
     pub cpu: CPU,
 
     // peripherals
-    pub timer0: AVRTimer,
+    pub timers: [AVRTimer; 3], // 0, 1, 2
     pub usart: AVRUSART,
     pub ports: [AVRIOPort; 3], // B, C, D
     pub i2c: AVRI2C,
+    pub spi: AVRSPI,
+    pub adc: AVRADC,
     pub eeprom: AVREEPROM,
+    pub watchdog: AVRWatchdog,
+    pub flash: AVRFlash,
+
+    pub fuses: Fuses, // applied on reset, except for the clock fuses
+    pub sleep_mode: Option<SleepMode>, // set while the CPU sleeps
+    pub brown_out: bool, // set while the brown-out detector holds the MCU in reset
 
     // data hooks
     pub read_hooks: HashMap<u16, PeripheralMemoryReadHook>,
@@ -35,31 +52,82 @@ pub struct ATMega328P {
 }
 
 impl ATMega328P {
+    /// Creates the MCU with the default fuses
+    ///
+    /// # Panics
+    ///
+    /// If the program is not a valid Intel HEX image, see `try_new` to handle the error instead.
     pub fn new(hex: &str, freq_hz: usize) -> Self {
-        let prog = load_hex(&hex);
+        Self::with_fuses(hex, freq_hz, Fuses::default())
+    }
+
+    /// Like `new`, with the given fuses
+    ///
+    /// # Panics
+    ///
+    /// If the program is not a valid Intel HEX image, see `try_new` to handle the error instead.
+    pub fn with_fuses(hex: &str, freq_hz: usize, fuses: Fuses) -> Self {
+        Self::try_new(hex, freq_hz, fuses).unwrap_or_else(|err| panic!("invalid hex: {}", err))
+    }
+
+    /// Creates the MCU with the given fuses, or returns the line-numbered error of an invalid
+    /// Intel HEX program. The CPU clock is derived from the frequency of the external crystal or
+    /// clock by the clock fuses.
+    pub fn try_new(hex: &str, freq_hz: usize, fuses: Fuses) -> Result<Self, HexError> {
+        let prog = load_hex(hex, FLASH_SIZE)?;
+        Ok(Self::with_program(prog, freq_hz, fuses))
+    }
+
+    /// Creates the MCU with a program memory image, e.g. loaded by `program::load_hex`
+    pub fn with_program(prog: Vec<u8>, freq_hz: usize, fuses: Fuses) -> Self {
+        assert_eq!(
+            prog.len(),
+            FLASH_SIZE,
+            "program memory must be {} bytes",
+            FLASH_SIZE
+        );
+        let freq_hz = fuses.clock_hz(freq_hz);
         let mut cpu = CPU::new(prog);
 
-        let timer0 = AVRTimer::new(TIMER_0_CONFIG);
+        let timers = [
+            AVRTimer::new(TIMER_0_CONFIG, freq_hz),
+            AVRTimer::new(TIMER_1_CONFIG, freq_hz),
+            AVRTimer::new(TIMER_2_CONFIG, freq_hz),
+        ];
         let usart = AVRUSART::new(USART0_CONFIG, freq_hz);
         let port_b = AVRIOPort::new(PORTB_CONFIG);
         let port_c = AVRIOPort::new(PORTC_CONFIG);
         let port_d = AVRIOPort::new(PORTD_CONFIG);
         let i2c = AVRI2C::new(TWI_CONFIG, freq_hz, &mut cpu);
+        let spi = AVRSPI::new(SPI_CONFIG);
+        let adc = AVRADC::new(ADC_CONFIG);
         let eeprom = AVREEPROM::new(EEPROM_CONFIG, 1024);
+        let watchdog = AVRWatchdog::new(WATCHDOG_CONFIG, freq_hz);
+        let flash = AVRFlash::new(FLASH_CONFIG, freq_hz);
 
         let mut read_hooks: HashMap<u16, PeripheralMemoryReadHook> = HashMap::new();
 
         let mut write_hooks: HashMap<u16, PeripheralMemoryWriteHook> = HashMap::new();
 
-        // Timer
-        timer0.add_TCNT_read_hook(&mut read_hooks);
-        timer0.add_TCNT_write_hook(&mut write_hooks);
-        timer0.add_TCCRB_write_hook(&mut write_hooks);
-        timer0.add_TIMSK_write_hook(&mut write_hooks);
+        // Timers
+        for (timer_id, timer) in timers.iter().enumerate() {
+            timer.add_TCNT_read_hook(&mut read_hooks, timer_id);
+            timer.add_TCNT_write_hook(&mut write_hooks, timer_id);
+            timer.add_OCR_write_hooks(&mut write_hooks, timer_id);
+            timer.add_16_bit_hooks(&mut read_hooks, &mut write_hooks, timer_id);
+            timer.add_TCCRA_write_hook(&mut write_hooks, timer_id);
+            timer.add_TCCRB_write_hook(&mut write_hooks, timer_id);
+            timer.add_TCCRC_write_hook(&mut write_hooks, timer_id);
+            timer.add_TIFR_write_hook(&mut write_hooks, timer_id);
+            timer.add_TIMSK_write_hook(&mut write_hooks, timer_id);
+            timer.add_ASSR_write_hook(&mut write_hooks, timer_id);
+        }
 
         // Universal Synchronous/Asynchronous Receiver Transmitter
+        usart.add_ucsra_handler(&mut write_hooks);
         usart.add_ucsrb_handler(&mut write_hooks);
         usart.add_udr_handler(&mut write_hooks);
+        usart.add_udr_read_handler(&mut read_hooks);
 
         // GPIO Ports
         port_b.add_ddr_handler(&mut write_hooks, 0);
@@ -71,26 +139,52 @@ impl ATMega328P {
         port_d.add_ddr_handler(&mut write_hooks, 2);
         port_d.add_port_handler(&mut write_hooks, 2);
 
+        port::add_interrupt_hooks(&mut write_hooks);
+
         let ports = [port_b, port_c, port_d];
 
         // I2C interface
         i2c.add_TWCR_write_hook(&mut write_hooks);
 
+        // Serial Peripheral Interface
+        spi.add_SPCR_write_hook(&mut write_hooks);
+        spi.add_SPSR_write_hook(&mut write_hooks);
+        spi.add_SPDR_write_hook(&mut write_hooks);
+        spi.add_SPDR_read_hook(&mut read_hooks);
+
+        // Analog to Digital Converter
+        adc.add_ADCSRA_write_hook(&mut write_hooks);
+        adc.add_ADCSRB_write_hook(&mut write_hooks);
+        adc.add_ADC_read_hooks(&mut read_hooks);
+
         // EEPROM
         eeprom.add_EECR_write_hook(&mut write_hooks);
 
-        let atmega328p = Self {
+        // Watchdog Timer
+        watchdog.add_WDTCSR_write_hook(&mut write_hooks);
+
+        // Self-programming
+        flash.add_SPMCSR_write_hook(&mut write_hooks);
+
+        let mut atmega = Self {
             cpu,
-            timer0,
+            timers,
             usart,
             ports,
             i2c,
+            spi,
+            adc,
             eeprom,
+            watchdog,
+            flash,
+            fuses,
+            sleep_mode: None,
+            brown_out: false,
             read_hooks,
             write_hooks,
         };
-
-        atmega328p
+        atmega.reset(ResetKind::PowerOn);
+        atmega
     }
 
     pub fn read_data(&mut self, addr: u16) -> u8 {
@@ -120,32 +214,62 @@ impl ATMega328P {
         self.cpu.set_data(addr, data);
     }
 
-    pub fn step(&mut self, i2c_bus: Option<&mut I2CBus>) {
-        avr_instruction(self);
+    /// Executes one instruction, then services the clock events and interrupts that are due
+    pub fn step(&mut self, i2c_bus: Option<&mut I2CBus>) -> Result<StepOutcome, SimError> {
+        self.step_until(i2c_bus, u64::MAX)
+    }
+
+    /// Like `step`, but a sleeping CPU, or one held in reset, is fast-forwarded no further than
+    /// `max_cycles`
+    pub fn step_until(
+        &mut self,
+        i2c_bus: Option<&mut I2CBus>,
+        max_cycles: u64,
+    ) -> Result<StepOutcome, SimError> {
+        if self.brown_out {
+            self.cpu.cycles = if max_cycles == u64::MAX {
+                self.cpu.cycles + 1
+            } else {
+                max_cycles.max(self.cpu.cycles + 1)
+            };
+            return Ok(StepOutcome::Reset);
+        }
+        if self.sleep_mode.is_some() {
+            self.sleep_until(max_cycles);
+            self.tick(i2c_bus);
+            return Ok(StepOutcome::Sleep);
+        }
+        // a fault raised in tick() (e.g. an interrupt overflowing the stack) is reported at the
+        // instruction executed just before it
+        let pc = self.cpu.pc;
+        let cycles = self.cpu.cycles;
+        let outcome = avr_instruction(self)?;
+
         self.tick(i2c_bus);
+        if let Some(kind) = self.cpu.take_fault() {
+            return Err(SimError { kind, pc, cycles });
+        }
+
+        Ok(outcome)
     }
 
     pub fn tick(&mut self, i2c_bus: Option<&mut I2CBus>) {
-        if let Some(event) = self.cpu.next_clock_event.take() {
-            // println!(
-            //     "event cycles: {}, cpu cycles: {}",
-            //     event.cycles, self.cycles
-            // );
-            if event.cycles <= self.cpu.cycles {
-                self.cpu.next_clock_event = event.next;
-                (event.callback)(self, i2c_bus, true, false);
-            } else {
-                self.cpu.next_clock_event = Some(event);
-            }
+        if let Some(callback) = self.cpu.clock_events.pop_due(self.cpu.cycles) {
+            callback(self, i2c_bus, true, false);
         }
 
         let next_interrupt = self.cpu.next_interrupt;
-        if self.cpu.interrupts_enabled() && next_interrupt >= 0 {
+        // A pending interrupt wakes the CPU up even if the interrupts are disabled, it then
+        // resumes after SLEEP. Once awake, the interrupt with the highest priority is executed
+        // first.
+        if next_interrupt >= 0 && self.wake_up() && self.cpu.interrupts_enabled() {
             assert!(self.cpu.pending_interrupts[next_interrupt as usize].is_some());
             let interrupt = self.cpu.pending_interrupts[next_interrupt as usize].unwrap();
             // println!("interrupt: {}", next_interrupt);
             avr_interrupt(&mut self.cpu, interrupt.address);
-            self.cpu.clear_interrupt(&interrupt, true);
+            if !interrupt.constant {
+                self.cpu.clear_interrupt(&interrupt, true);
+            }
         }
     }
 }
@@ -1,19 +1,22 @@
-use crate::{atmega328p::ATMega328P, cpu::CPU, peripheral::i2c::bus::I2CBus};
+use crate::{atmega328p::ATMega328P, peripheral::i2c::bus::I2CBus};
 
-#[derive(Clone)]
+/// Source of a clock event. Each peripheral instance has its own kind, so that
+/// clearing the events of one peripheral leaves the others alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AVRClockEventType {
-    Count,
-    USART,
-    I2C,
-    EEPROMFinish,        // TODO(EEPROM): better naming
-    EEPROMWriteComplete, // TODO(EEPROM): better naming
+    Timer0Count,
+    Timer1Count,
+    Timer2Count,
+    USART0,
+    USART0Receive, // a character shifted in by the receiver
+    TWI,
+    SPI,
+    ADC,
+    EEPROMWriteEnableTimeout, // EEMPE is cleared 4 cycles after being set
+    EEPROMWriteComplete,
+    Watchdog,         // watchdog time-out
+    SPMEnableTimeout, // SPMEN is cleared 4 cycles after being set, if no SPM is executed
+    SPMComplete,      // page erase or write of the RWW section
 }
 
 pub type AVRClockEventCallback = Box<dyn Fn(&mut ATMega328P, Option<&mut I2CBus>, bool, bool)>;
-
-pub struct AVRClockEventEntry {
-    pub cycles: u32,
-    pub callback: AVRClockEventCallback,
-    pub event_type: AVRClockEventType,
-    pub next: Option<Box<AVRClockEventEntry>>,
-}
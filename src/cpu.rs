@@ -1,6 +1,11 @@
+use std::cell::Cell;
+
 use crate::{
-    clock::{AVRClockEventCallback, AVRClockEventEntry, AVRClockEventType},
+    clock::{AVRClockEventCallback, AVRClockEventType},
+    error::SimErrorKind,
+    instruction::instructions::{Instruction, Operands, decode},
     interrupt::{AVRInterruptConfig, MAX_INTERRUPTS},
+    scheduler::{EventHandle, Scheduler},
 };
 
 const SRAM_BYTES: usize = 8192;
@@ -9,24 +14,27 @@ const SREG: usize = 95;
 
 pub struct CPU {
     pub data: Vec<u8>,
-    pub prog_mem: Vec<u16>,
-    pub prog_bytes: Vec<u8>,
-    pub pc: u32,     // program counter
-    pub cycles: u32, // clock cycle counter
+    prog_mem: Vec<u16>,
+    prog_bytes: Vec<u8>,
+    decoded: Vec<Option<(Instruction, Operands)>>, // decoded from each word of prog_mem, None if illegal
+    pub pc: u32,                                   // program counter
+    pub cycles: u64,                               // clock cycle counter
 
     pub pending_interrupts: [Option<AVRInterruptConfig>; MAX_INTERRUPTS], // TODO: optimize this data structure for space
-    pub next_clock_event: Option<Box<AVRClockEventEntry>>,
+    pub clock_events: Scheduler,
 
     pub pc_22_bits: bool, // Whether the program counter (PC) can address 22 bits (the default is 16)
 
     pub next_interrupt: i16,
     max_interrupt: i16,
+
+    fault: Cell<Option<SimErrorKind>>, // fault raised by the instruction being executed
 }
 
 impl CPU {
     pub fn new(prog_bytes: Vec<u8>) -> Self {
         // convert to Vec<u16>
-        let prog_mem = prog_bytes
+        let prog_mem: Vec<u16> = prog_bytes
             .chunks(2)
             .map(|chunk| {
                 let lo = chunk[0] as u16;
@@ -34,19 +42,23 @@ impl CPU {
                 (hi << 8) | lo // little endian
             })
             .collect();
+        // decode once, so that executing an instruction does not go through decode again
+        let decoded = prog_mem.iter().map(|opcode| decode(*opcode)).collect();
         let pc_22_bits = prog_bytes.len() > 0x20000;
 
         let mut cpu = Self {
             data: vec![0; SRAM_BYTES + REGISTER_SPACE],
             prog_mem,
             prog_bytes,
+            decoded,
             pc: 0,
             cycles: 0,
             pending_interrupts: [None; MAX_INTERRUPTS],
-            next_clock_event: None,
+            clock_events: Scheduler::new(),
             pc_22_bits,
             next_interrupt: -1,
             max_interrupt: 0,
+            fault: Cell::new(None),
         };
 
         cpu.reset();
@@ -54,136 +66,164 @@ impl CPU {
         cpu
     }
 
-    fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) {
         self.set_sp((self.data.len() - 1) as u16);
         self.pc = 0;
         self.pending_interrupts = [None; MAX_INTERRUPTS];
         self.next_interrupt = -1;
-        self.next_clock_event = None;
+        self.clock_events.clear();
+        self.fault.set(None);
     }
 
     pub fn set_sp(&mut self, data: u16) {
         self.set_data_u16(93, data);
     }
 
+    pub fn sp(&self) -> u16 {
+        self.get_data_u16(93)
+    }
+
+    /// Reads a byte from the data space. Out of range reads return 0 and raise a fault.
     pub fn get_data(&self, addr: u16) -> u8 {
-        self.data[addr as usize]
+        match self.data.get(addr as usize) {
+            Some(value) => *value,
+            None => {
+                self.fault
+                    .set(Some(SimErrorKind::DataOutOfRange(addr as u32)));
+                0
+            }
+        }
     }
 
+    /// Writes a byte to the data space. Out of range writes are dropped and raise a fault.
     pub fn set_data(&mut self, addr: u16, data: u8) {
-        self.data[addr as usize] = data
+        match self.data.get_mut(addr as usize) {
+            Some(value) => *value = data,
+            None => {
+                self.fault
+                    .set(Some(SimErrorKind::DataOutOfRange(addr as u32)));
+            }
+        }
     }
 
     /// get u16 from consecutive two u8, w/ little-endian order
     pub fn get_data_u16(&self, addr: u16) -> u16 {
-        let bytes: [u8; 2] = [self.get_data(addr), self.get_data(addr + 1)];
+        let bytes: [u8; 2] = [self.get_data(addr), self.get_data(addr.wrapping_add(1))];
         u16::from_le_bytes(bytes)
     }
 
     pub fn set_data_u16(&mut self, addr: u16, data: u16) {
         let bytes = data.to_le_bytes();
         self.set_data(addr, bytes[0]);
-        self.set_data(addr + 1, bytes[1]);
+        self.set_data(addr.wrapping_add(1), bytes[1]);
     }
 
-    pub fn add_clock_event(
-        &mut self,
-        callback: AVRClockEventCallback,
-        cycles: u32,
-        event_type: AVRClockEventType,
-    ) {
-        // println!("add clock event, cycles: {}", cycles);
-        let cycles = self.cycles + cycles.max(1);
-        let mut entry = AVRClockEventEntry {
-            cycles,
-            callback,
-            event_type,
-            next: None,
-        };
-        if self
-            .next_clock_event
-            .as_ref()
-            .is_none_or(|x| x.cycles >= cycles)
-        {
-            entry.next = self.next_clock_event.take();
-            self.next_clock_event = Some(Box::new(entry));
-        } else {
-            let mut last_item = &mut self.next_clock_event;
-            loop {
-                assert!(last_item.is_some());
-                let clock_event = last_item.as_mut().unwrap();
-                assert!(clock_event.cycles < cycles);
-                if clock_event.next.as_ref().is_some_and(|x| x.cycles < cycles) {
-                    last_item = &mut clock_event.next;
-                    continue;
-                }
-                // last_item is the last one whose cycles < cycles
-                entry.next = clock_event.next.take();
-                clock_event.next = Some(Box::new(entry));
-                break;
+    /// Reads a byte from the program memory. Out of range reads return 0xff and raise a fault.
+    pub fn get_prog_byte(&self, addr: u32) -> u8 {
+        match self.prog_bytes.get(addr as usize) {
+            Some(value) => *value,
+            None => {
+                self.fault.set(Some(SimErrorKind::FlashOutOfRange(addr)));
+                0xff
             }
         }
+    }
 
-        if let Some(x) = &self.next_clock_event {
-            // println!("next clock event, cycles: {}", x.cycles);
+    /// Reads a word from the program memory. Out of range reads return 0xffff and raise a fault.
+    pub fn get_prog_word(&self, addr: u32) -> u16 {
+        match self.prog_mem.get(addr as usize) {
+            Some(value) => *value,
+            None => {
+                self.fault
+                    .set(Some(SimErrorKind::FlashOutOfRange(2 * addr)));
+                0xffff
+            }
         }
     }
 
-    pub fn clear_clock_event(&mut self, event_type: AVRClockEventType) -> bool {
-        if self.next_clock_event.is_none() {
-            return false;
-        }
+    /// Program memory, as words
+    pub fn prog_mem(&self) -> &[u16] {
+        &self.prog_mem
+    }
 
-        let mut ret_value = false; // whether any event type match
-        while self
-            .next_clock_event
-            .as_ref()
-            .is_some_and(|x| matches!(&x.event_type, event_type))
-        {
-            let event = self.next_clock_event.take();
-            self.next_clock_event = event.unwrap().next;
-            ret_value = true;
-        }
+    /// Program memory, as bytes
+    pub fn prog_bytes(&self) -> &[u8] {
+        &self.prog_bytes
+    }
 
-        let mut last_item = &mut self.next_clock_event;
-        if last_item.is_none() {
-            return ret_value;
-        }
-        let mut clock_event = last_item.as_mut().unwrap();
-        loop {
-            assert!(!matches!(&clock_event.event_type, event_type));
-            if clock_event.next.is_none() {
-                break;
-            }
-            if clock_event
-                .next
-                .as_ref()
-                .is_some_and(|x| matches!(&x.event_type, event_type))
-            {
-                let next = clock_event.next.take();
-                clock_event.next = next.unwrap().next;
-                ret_value = true;
-            } else {
-                last_item = &mut clock_event.next;
-                clock_event = last_item.as_mut().unwrap();
-            }
+    /// Instructions and operands decoded from the words of the program memory, None if illegal
+    pub fn decoded(&self) -> &[Option<(Instruction, Operands)>] {
+        &self.decoded
+    }
+
+    /// Writes a word to the program memory, keeping the pre-decoded instruction up to date.
+    /// Program memory must only be modified through this (e.g. by SPM).
+    pub fn set_prog_word(&mut self, addr: u32, value: u16) {
+        let addr = addr as usize;
+        self.prog_mem[addr] = value;
+        let bytes = value.to_le_bytes();
+        self.prog_bytes[2 * addr] = bytes[0];
+        self.prog_bytes[2 * addr + 1] = bytes[1];
+        self.decoded[addr] = decode(value);
+    }
+
+    /// Raises a fault if the stack pointer left the SRAM after a push or pop
+    pub fn check_stack(&self) {
+        let sp = self.sp();
+        if sp as usize >= self.data.len() {
+            self.fault.set(Some(SimErrorKind::StackUnderflow(sp)));
+        } else if sp < REGISTER_SPACE as u16 - 1 {
+            self.fault.set(Some(SimErrorKind::StackOverflow(sp)));
         }
-        ret_value
     }
 
+    /// Returns and clears the fault raised since the last call, if any
+    pub fn take_fault(&self) -> Option<SimErrorKind> {
+        self.fault.take()
+    }
+
+    /// Schedules the callback to fire in `cycles` clock cycles (at least 1)
+    pub fn add_clock_event(
+        &mut self,
+        callback: AVRClockEventCallback,
+        cycles: u64,
+        event_type: AVRClockEventType,
+    ) -> EventHandle {
+        let cycles = self.cycles + cycles.max(1);
+        self.clock_events.schedule(cycles, callback, event_type)
+    }
+
+    /// Cancels all the events of the given type. Returns whether any was cancelled.
+    pub fn clear_clock_event(&mut self, event_type: AVRClockEventType) -> bool {
+        self.clock_events.cancel_type(event_type)
+    }
+
+    /// Replaces the events of the given type by the callback, if there was any
     pub fn update_clock_event(
         &mut self,
         callback: AVRClockEventCallback,
         event_type: AVRClockEventType,
-        cycles: u32,
+        cycles: u64,
     ) -> bool {
-        if self.clear_clock_event(event_type.clone()) {
+        if self.clear_clock_event(event_type) {
             self.add_clock_event(callback, cycles, event_type);
             return true;
         }
         false
     }
 
+    /// Cancels the event. Returns false if it already fired or was cancelled.
+    pub fn cancel_clock_event(&mut self, handle: EventHandle) -> bool {
+        self.clock_events.cancel(handle)
+    }
+
+    /// Moves the event to fire in `cycles` clock cycles (at least 1).
+    /// Returns false if it already fired or was cancelled.
+    pub fn reschedule_clock_event(&mut self, handle: EventHandle, cycles: u64) -> bool {
+        let cycles = self.cycles + cycles.max(1);
+        self.clock_events.reschedule(handle, cycles)
+    }
+
     pub fn set_interrupt_flag(&mut self, interrupt: AVRInterruptConfig) {
         let flag_register = interrupt.flag_register;
         let flag_mask = interrupt.flag_mask;
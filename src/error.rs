@@ -0,0 +1,89 @@
+use std::fmt;
+
+use crate::util::to_binary_str;
+
+/// Kinds of faults that stop the simulation of a firmware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimErrorKind {
+    IllegalOpcode(u16),   // opcode that could not be decoded
+    DataOutOfRange(u32),  // data space address accessed
+    FlashOutOfRange(u32), // program memory byte address read
+    StackOverflow(u16),   // stack pointer after the push
+    StackUnderflow(u16),  // stack pointer after the pop
+    PcOutOfFlash,
+}
+
+/// Error returned when the simulated firmware crashes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimError {
+    pub kind: SimErrorKind,
+    pub pc: u32,     // program counter of the faulting instruction
+    pub cycles: u64, // clock cycle count when the fault happened
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            SimErrorKind::IllegalOpcode(opcode) => {
+                write!(f, "illegal opcode {}", to_binary_str(opcode))?
+            }
+            SimErrorKind::DataOutOfRange(addr) => {
+                write!(f, "data space access out of range: {:#06x}", addr)?
+            }
+            SimErrorKind::FlashOutOfRange(addr) => {
+                write!(f, "program memory read out of range: {:#06x}", addr)?
+            }
+            SimErrorKind::StackOverflow(sp) => write!(f, "stack overflow, SP: {:#06x}", sp)?,
+            SimErrorKind::StackUnderflow(sp) => write!(f, "stack underflow, SP: {:#06x}", sp)?,
+            SimErrorKind::PcOutOfFlash => write!(f, "program counter out of flash")?,
+        }
+        write!(f, " (pc: {:#06x}, cycles: {})", self.pc, self.cycles)
+    }
+}
+
+impl std::error::Error for SimError {}
+
+/// Kinds of faults in an Intel HEX image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HexErrorKind {
+    MalformedRecord,           // missing start code, or odd number of characters
+    InvalidDigit,              // character that is not a hex digit
+    WrongLength,               // byte count not matching the record, or its type
+    WrongChecksum,             // sum of the record bytes not zero
+    UnsupportedRecordType(u8), // record type past 05
+    OutOfRange(u32),           // end address of the data, past the memory
+}
+
+/// Error returned when an Intel HEX image can not be parsed or loaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HexError {
+    pub kind: HexErrorKind,
+    pub line: usize, // line number of the record, from 1
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match self.kind {
+            HexErrorKind::MalformedRecord => write!(f, "malformed record"),
+            HexErrorKind::InvalidDigit => write!(f, "invalid hex digit"),
+            HexErrorKind::WrongLength => write!(f, "wrong record length"),
+            HexErrorKind::WrongChecksum => write!(f, "wrong checksum"),
+            HexErrorKind::UnsupportedRecordType(record_type) => {
+                write!(f, "unsupported record type {:02x}", record_type)
+            }
+            HexErrorKind::OutOfRange(addr) => write!(f, "data out of range: {:#06x}", addr),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+/// Result of a successful simulation step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    Executed,
+    Break, // a BREAK instruction was executed
+    Sleep, // the CPU is sleeping, and was fast-forwarded instead of executing an instruction
+    Reset, // the MCU is held in reset by the brown-out detector
+}
@@ -0,0 +1,348 @@
+use crate::{
+    Float,
+    atmega328p::{ATMega328P, FLASH_SIZE},
+    reset::ResetKind,
+};
+
+// Low fuse bits, programmed when 0
+pub const LFUSE_CKDIV8: u8 = 0x80; // Divide clock by 8
+pub const LFUSE_CKSEL: u8 = 0xf; // Select Clock source 3:0
+
+// High fuse bits
+pub const HFUSE_WDTON: u8 = 0x10; // Watchdog Timer Always On
+pub const HFUSE_BOOTRST: u8 = 0x1; // Select Reset Vector
+pub const HFUSE_BOOTSZ: u8 = 0x6; // Select Boot Size 1:0
+
+// Extended fuse bits
+pub const EFUSE_BODLEVEL: u8 = 0x7; // Brown-out Detector trigger level 2:0
+
+// Lock bits
+pub const LOCK_BLB: u8 = 0x3c; // Boot Lock Bits, the only ones SPM can program
+
+const CLKPR: usize = 0x61; // Clock Prescale Register
+const CLKPR_CKDIV8: u8 = 0x3; // CLKPS = 0011, division factor of 8
+
+const INTERNAL_RC_HZ: usize = 8_000_000;
+const INTERNAL_128KHZ_HZ: usize = 128_000;
+
+const BOD_HYSTERESIS: Float = 0.05;
+
+const FLASH_WORDS: u32 = (FLASH_SIZE / 2) as u32;
+
+/// Fuse and lock bytes of the MCU. A bit is programmed when it is 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fuses {
+    pub low: u8,
+    pub high: u8,
+    pub extended: u8,
+    pub lock: u8,
+}
+
+impl Default for Fuses {
+    /// Unprogrammed low fuse (external crystal, no clock division) and factory high fuse: the
+    /// reset vector is at 0 and the boot section is 2048 words
+    fn default() -> Self {
+        Self {
+            low: 0xff,
+            high: 0xd9,
+            extended: 0xff,
+            lock: 0xff,
+        }
+    }
+}
+
+impl Fuses {
+    /// Frequency of the CPU clock from CKSEL and CKDIV8, given the frequency of the external
+    /// crystal or clock
+    pub fn clock_hz(&self, external_hz: usize) -> usize {
+        let source_hz = match self.low & LFUSE_CKSEL {
+            0b0010 => INTERNAL_RC_HZ,
+            0b0011 => INTERNAL_128KHZ_HZ,
+            _ => external_hz,
+        };
+        if self.clock_divided() {
+            source_hz / 8
+        } else {
+            source_hz
+        }
+    }
+
+    /// Whether the clock is divided by 8 on start-up (CKDIV8 programmed)
+    pub fn clock_divided(&self) -> bool {
+        self.low & LFUSE_CKDIV8 == 0
+    }
+
+    /// Whether the watchdog is always on in system reset mode (WDTON programmed)
+    pub fn watchdog_always_on(&self) -> bool {
+        self.high & HFUSE_WDTON == 0
+    }
+
+    /// Whether the reset vector is at the start of the boot section (BOOTRST programmed)
+    pub fn boot_reset(&self) -> bool {
+        self.high & HFUSE_BOOTRST == 0
+    }
+
+    /// Word address of the start of the boot section, from BOOTSZ
+    pub fn boot_start(&self) -> u32 {
+        let boot_size = 256 << (3 - ((self.high & HFUSE_BOOTSZ) >> 1));
+        FLASH_WORDS - boot_size
+    }
+
+    /// Word address the program starts from after a reset
+    pub fn reset_vector(&self) -> u32 {
+        if self.boot_reset() {
+            self.boot_start()
+        } else {
+            0
+        }
+    }
+
+    /// Brown-out detection level from BODLEVEL, None if disabled
+    pub fn bod_level(&self) -> Option<Float> {
+        match self.extended & EFUSE_BODLEVEL {
+            0b110 => Some(1.8),
+            0b101 => Some(2.7),
+            0b100 => Some(4.3),
+            _ => None, // disabled, or reserved
+        }
+    }
+
+    /// The byte read by LPM after setting BLBSET and SPMEN, selected by Z as in avr-libc's
+    /// boot_lock_fuse_bits_get
+    pub fn read(&self, z: u32) -> u8 {
+        match z & 0x3 {
+            0 => self.low,
+            1 => self.lock,
+            2 => self.extended,
+            _ => self.high,
+        }
+    }
+}
+
+impl ATMega328P {
+    /// Whether the word address is in the boot section, where SPM can be executed from
+    pub fn in_boot_section(&self, addr: u32) -> bool {
+        addr >= self.fuses.boot_start()
+    }
+
+    /// Initial value of CLKPR, set by CKDIV8
+    pub(crate) fn reset_clock_prescaler(&mut self) {
+        self.cpu.data[CLKPR] = if self.fuses.clock_divided() {
+            CLKPR_CKDIV8
+        } else {
+            0
+        };
+    }
+
+    /// Sets the supply voltage (VCC and AVCC). The brown-out detector holds the MCU in reset for
+    /// as long as VCC is below the BODLEVEL fuse level, with some hysteresis.
+    pub fn set_supply_voltage(&mut self, volts: Float) {
+        self.adc.avcc = volts;
+        let Some(level) = self.fuses.bod_level() else {
+            return;
+        };
+        if !self.brown_out && volts < level - BOD_HYSTERESIS / 2. {
+            self.reset(ResetKind::BrownOut);
+            self.brown_out = true;
+        } else if self.brown_out && volts > level + BOD_HYSTERESIS / 2. {
+            self.brown_out = false;
+            self.reset(ResetKind::BrownOut);
+        }
+    }
+}
+
+#[cfg(test)]
+mod fuses_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        error::StepOutcome,
+        fuses::{CLKPR, Fuses},
+        peripheral::{
+            flash::{FLASH_CONFIG, SPMCSR_BLBSET, SPMCSR_SPMEN},
+            watchdog::{MCUSR_WDRF, WATCHDOG_CONFIG, WDTCSR_WDCE, WDTCSR_WDE, WDTCSR_WDIE},
+        },
+        reset::{MCUSR_BORF, ResetKind},
+    };
+
+    const MCUSR: usize = WATCHDOG_CONFIG.MCUSR as usize;
+    const WDTCSR: u16 = WATCHDOG_CONFIG.WDTCSR as u16;
+    const SPMCSR: u16 = FLASH_CONFIG.SPMCSR as u16;
+
+    fn run_until(atmega: &mut ATMega328P, cycles: u64) {
+        while atmega.cpu.cycles < cycles {
+            atmega.cpu.cycles += 1;
+            atmega.tick(None);
+        }
+    }
+
+    #[test]
+    fn boot_section_sizes() {
+        for (high, boot_start) in [
+            (0xde, 0x3f00),
+            (0xdc, 0x3e00),
+            (0xda, 0x3c00),
+            (0xd8, 0x3800),
+        ] {
+            // Arrange
+            let fuses = Fuses {
+                high,
+                ..Default::default()
+            };
+
+            // Act/Assert
+            assert!(fuses.boot_reset());
+            assert_eq!(fuses.boot_start(), boot_start);
+            assert_eq!(fuses.reset_vector(), boot_start);
+        }
+    }
+
+    #[test]
+    fn factory_reset_vector() {
+        // Arrange/Act
+        let atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Assert
+        assert!(!atmega.fuses.boot_reset());
+        assert_eq!(atmega.cpu.pc, 0);
+    }
+
+    #[test]
+    fn boot_reset_starts_bootloader() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.fuses.high = 0xde; // Arduino Uno, 256 words boot section
+
+        // Act
+        atmega.reset(ResetKind::External);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 0x3f00);
+    }
+
+    #[test]
+    fn clock_fuses() {
+        for (low, freq_hz, clkpr) in [
+            (0xff, 16_000_000, 0),  // external crystal
+            (0x7f, 2_000_000, 0x3), // external crystal, CKDIV8
+            (0x62, 1_000_000, 0x3), // factory: internal RC oscillator, CKDIV8
+            (0xe3, 128_000, 0),     // internal 128kHz oscillator
+        ] {
+            // Arrange
+            let fuses = Fuses {
+                low,
+                ..Default::default()
+            };
+
+            // Act
+            let atmega = ATMega328P::with_fuses("", DEFAULT_FREQ, fuses);
+
+            // Assert
+            assert_eq!(fuses.clock_hz(DEFAULT_FREQ), freq_hz);
+            assert_eq!(atmega.usart.freq_hz, freq_hz);
+            assert_eq!(atmega.cpu.data[CLKPR], clkpr);
+        }
+    }
+
+    #[test]
+    fn wdton_keeps_watchdog_in_reset_mode() {
+        // Arrange
+        let fuses = Fuses {
+            high: 0xc9, // WDTON
+            ..Default::default()
+        };
+        let mut atmega = ATMega328P::with_fuses("", DEFAULT_FREQ, fuses);
+        atmega.cpu.data[MCUSR] = 0;
+        assert_eq!(atmega.cpu.data[WDTCSR as usize], WDTCSR_WDE);
+
+        // Act
+        atmega.write_data(WDTCSR, WDTCSR_WDCE | WDTCSR_WDE);
+        atmega.write_data(WDTCSR, WDTCSR_WDIE);
+        run_until(&mut atmega, 256_000); // 16ms time-out
+
+        // Assert
+        assert_eq!(atmega.cpu.data[MCUSR], MCUSR_WDRF);
+        assert_eq!(atmega.cpu.data[WDTCSR as usize], WDTCSR_WDE);
+    }
+
+    #[test]
+    fn brown_out_holds_in_reset() {
+        // Arrange
+        let fuses = Fuses {
+            extended: 0xfd, // 2.7V
+            ..Default::default()
+        };
+        let mut atmega = ATMega328P::with_fuses("", DEFAULT_FREQ, fuses);
+        atmega.cpu.data[MCUSR] = 0;
+        atmega.cpu.pc = 0x100;
+
+        // Act
+        atmega.set_supply_voltage(2.5);
+        let outcome = atmega.step(None).unwrap();
+        atmega.set_supply_voltage(2.72); // within hysteresis
+        let held = atmega.brown_out;
+        atmega.set_supply_voltage(3.3);
+
+        // Assert
+        assert_eq!(outcome, StepOutcome::Reset);
+        assert!(held);
+        assert!(!atmega.brown_out);
+        assert_eq!(atmega.cpu.data[MCUSR], MCUSR_BORF);
+        assert_eq!(atmega.cpu.pc, 0);
+        assert_eq!(atmega.adc.avcc, 3.3);
+    }
+
+    #[test]
+    fn bod_disabled_by_default() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.set_supply_voltage(1.);
+
+        // Assert
+        assert!(!atmega.brown_out);
+    }
+
+    #[test]
+    fn lpm_reads_fuse_and_lock_bits() {
+        // Arrange
+        let fuses = Fuses {
+            low: 0xf7,
+            high: 0xde,
+            extended: 0xfd,
+            lock: 0xcf,
+        };
+        let mut atmega = ATMega328P::with_fuses("", DEFAULT_FREQ, fuses);
+        atmega.cpu.set_prog_word(0, 0x95c8); // LPM
+        atmega.cpu.set_prog_word(1, 0x1234);
+
+        for (z, value) in [(0, 0xf7), (1, 0xcf), (2, 0xfd), (3, 0xde)] {
+            // Act
+            atmega.cpu.pc = 0;
+            atmega.cpu.set_data_u16(30, z);
+            atmega.write_data(SPMCSR, SPMCSR_BLBSET | SPMCSR_SPMEN);
+            atmega.step(None).unwrap();
+
+            // Assert
+            assert_eq!(atmega.cpu.data[0], value);
+            assert_eq!(atmega.cpu.data[SPMCSR as usize], 0);
+        }
+    }
+
+    #[test]
+    fn lpm_reads_flash_after_3_cycles() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.set_prog_word(0, 0x95c8); // LPM
+        atmega.cpu.set_prog_word(1, 0x1234);
+        atmega.cpu.set_data_u16(30, 2);
+        atmega.write_data(SPMCSR, SPMCSR_BLBSET | SPMCSR_SPMEN);
+        atmega.cpu.cycles += 3;
+
+        // Act
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[0], 0x34);
+    }
+}
@@ -1,6 +1,4 @@
-use crate::util::to_binary_str;
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Instruction {
     ADC,
@@ -13,6 +11,7 @@ pub enum Instruction {
     BLD,
     BRBC,
     BRBS,
+    BREAK,
     BSET,
     BST,
     CALL,
@@ -23,8 +22,15 @@ pub enum Instruction {
     CPSE,
     CPI,
     DEC,
+    EICALL,
+    EIJMP,
+    ELPM,
+    ELPM_REG,
     ELPM_INC,
     EOR,
+    FMUL,
+    FMULS,
+    FMULSU,
     ICALL,
     IJMP,
     IN,
@@ -34,19 +40,26 @@ pub enum Instruction {
     LDS,
     LDX,
     LDX_INC,
+    LDX_DEC,
     LDY,
     LDY_INC,
+    LDY_DEC,
     LDDY,
     LDZ,
     LDZ_INC,
+    LDZ_DEC,
     LDDZ,
+    LPM,
     LPM_REG,
     LPM_INC,
     LSR,
     MOV,
     MOVW,
     MUL,
+    MULS,
+    MULSU,
     NEG,
+    NOP,
     OR,
     OUT,
     POP,
@@ -65,183 +78,386 @@ pub enum Instruction {
     SBR,
     SBRC,
     SBRS,
+    SLEEP,
+    SPM,
     STDY,
     STS,
     STX,
     STX_INC,
     STX_DEC,
     STY,
+    STY_INC,
+    STY_DEC,
     STZ,
     STZ_INC,
     STZ_DEC,
     STDZ,
     SUB,
     SUBI,
+    SWAP,
+    WDR,
+}
+
+/// Operands of an instruction, extracted from its opcode once when the program memory is
+/// decoded, and named as in the instruction set manual. Only the fields of the instruction are
+/// set.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[allow(non_snake_case)]
+pub struct Operands {
+    pub d: u16, // destination register, or first register of a pair
+    pub r: u16, // source register, or first register of a pair
+    pub K: u16, // constant
+    pub k: u32, // relative address (wrapping), or bits 21:16 of an absolute address
+    pub q: u16, // displacement
+    pub A: u16, // I/O register, as a data space address
+    pub b: u8,  // bit in a register, or in SREG
 }
 
-pub fn decode(opcode: u16) -> Instruction {
-    if opcode & 0xfc00 == 0x1c00 {
+#[allow(non_snake_case)]
+impl Operands {
+    /// xxxx xxrd dddd rrrr
+    fn rd(opcode: u16) -> Self {
+        Self {
+            d: (opcode & 0x1f0) >> 4,
+            r: (opcode & 0xf) | ((opcode & 0x200) >> 5),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx dddd rrrr, register pairs
+    fn word_rd(opcode: u16) -> Self {
+        Self {
+            d: 2 * ((opcode & 0xf0) >> 4),
+            r: 2 * (opcode & 0xf),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx dddd rrrr, r16 to r31
+    fn high_rd(opcode: u16) -> Self {
+        Self {
+            d: ((opcode & 0xf0) >> 4) + 16,
+            r: (opcode & 0xf) + 16,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx xddd xrrr, r16 to r23
+    fn fmul_rd(opcode: u16) -> Self {
+        Self {
+            d: ((opcode & 0x70) >> 4) + 16,
+            r: (opcode & 7) + 16,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxd dddd xxxx
+    fn d(opcode: u16) -> Self {
+        Self {
+            d: (opcode & 0x1f0) >> 4,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx KKKK dddd KKKK, r16 to r31
+    fn dK(opcode: u16) -> Self {
+        Self {
+            d: ((opcode & 0xf0) >> 4) + 16,
+            K: (opcode & 0xf) | ((opcode & 0xf00) >> 4),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx KKdd KKKK, register pairs r24 to r30
+    fn word_dK(opcode: u16) -> Self {
+        Self {
+            d: 2 * ((opcode & 0x30) >> 4) + 24,
+            K: (opcode & 0xf) | ((opcode & 0xc0) >> 2),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxd dddd xbbb
+    fn db(opcode: u16) -> Self {
+        Self {
+            d: (opcode & 0x1f0) >> 4,
+            b: (opcode & 7) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx xsss xxxx, SREG bit
+    fn s(opcode: u16) -> Self {
+        Self {
+            b: ((opcode & 0x70) >> 4) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxkk kkkk ksss, 7 bits signed offset
+    fn branch(opcode: u16) -> Self {
+        Self {
+            k: (((opcode & 0x1f8) >> 3) as u32).wrapping_sub(if opcode & 0x200 != 0 {
+                0x40
+            } else {
+                0
+            }),
+            b: (opcode & 7) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx kkkk kkkk kkkk, 12 bits signed offset
+    fn relative_k(opcode: u16) -> Self {
+        Self {
+            k: ((opcode & 0x7ff) as u32).wrapping_sub(if opcode & 0x800 != 0 { 0x800 } else { 0 }),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxk kkkk xxxk, followed by the 16 low bits of the address
+    fn long_k(opcode: u16) -> Self {
+        Self {
+            k: ((opcode as u32 & 1) << 16) | ((opcode as u32 & 0x1f0) << 13),
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xxxx AAAA Abbb
+    fn Ab(opcode: u16) -> Self {
+        Self {
+            A: ((opcode & 0xf8) >> 3) + 32,
+            b: (opcode & 7) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// xxxx xAAd dddd AAAA
+    fn io(opcode: u16) -> Self {
+        Self {
+            d: (opcode & 0x1f0) >> 4,
+            A: ((opcode & 0xf) | ((opcode & 0x600) >> 5)) + 32,
+            ..Default::default()
+        }
+    }
+
+    /// xxqx qqxd dddd xqqq
+    fn dq(opcode: u16) -> Self {
+        Self {
+            d: (opcode & 0x1f0) >> 4,
+            q: (opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8),
+            ..Default::default()
+        }
+    }
+}
+
+/// Decodes an opcode into its instruction and operands, None if illegal
+pub fn decode(opcode: u16) -> Option<(Instruction, Operands)> {
+    let instruction = if opcode & 0xfc00 == 0x1c00 {
         /* ADC, 0001 11rd dddd rrrr */
-        Instruction::ADC
+        (Instruction::ADC, Operands::rd(opcode))
     } else if opcode & 0xfc00 == 0xc00 {
         /* ADD, 0000 11rd dddd rrrr */
-        Instruction::ADD
+        (Instruction::ADD, Operands::rd(opcode))
     } else if opcode & 0xff00 == 0x9600 {
         /* ADIW, 1001 0110 KKdd KKKK */
-        Instruction::ADIW
+        (Instruction::ADIW, Operands::word_dK(opcode))
     } else if opcode & 0xfc00 == 0x2000 {
-        Instruction::AND
+        (Instruction::AND, Operands::rd(opcode))
     } else if opcode & 0xf000 == 0x7000 {
-        Instruction::ANDI
+        (Instruction::ANDI, Operands::dK(opcode))
     } else if opcode & 0xfe0f == 0x9405 {
-        Instruction::ASR
+        (Instruction::ASR, Operands::d(opcode))
     } else if opcode & 0xff8f == 0x9488 {
-        Instruction::BCLR
+        (Instruction::BCLR, Operands::s(opcode))
     } else if opcode & 0xfe08 == 0xf800 {
-        Instruction::BLD
+        (Instruction::BLD, Operands::db(opcode))
     } else if opcode & 0xfc00 == 0xf400 {
-        Instruction::BRBC
+        (Instruction::BRBC, Operands::branch(opcode))
     } else if opcode & 0xfc00 == 0xf000 {
-        Instruction::BRBS
+        (Instruction::BRBS, Operands::branch(opcode))
+    } else if opcode == 0x9598 {
+        (Instruction::BREAK, Operands::default())
     } else if opcode & 0xff8f == 0x9408 {
-        Instruction::BSET
+        (Instruction::BSET, Operands::s(opcode))
     } else if opcode & 0xfe08 == 0xfa00 {
-        Instruction::BST
+        (Instruction::BST, Operands::db(opcode))
     } else if opcode & 0xfe0e == 0x940e {
-        Instruction::CALL
+        (Instruction::CALL, Operands::long_k(opcode))
     } else if opcode & 0xff00 == 0x9800 {
-        Instruction::CBI
+        (Instruction::CBI, Operands::Ab(opcode))
     } else if opcode & 0xfe0f == 0x9400 {
-        Instruction::COM
+        (Instruction::COM, Operands::d(opcode))
     } else if opcode & 0xfc00 == 0x1400 {
-        Instruction::CP
+        (Instruction::CP, Operands::rd(opcode))
     } else if opcode & 0xfc00 == 0x400 {
-        Instruction::CPC
+        (Instruction::CPC, Operands::rd(opcode))
     } else if opcode & 0xfc00 == 0x1000 {
-        Instruction::CPSE
+        (Instruction::CPSE, Operands::rd(opcode))
     } else if opcode & 0xf000 == 0x3000 {
-        Instruction::CPI
+        (Instruction::CPI, Operands::dK(opcode))
     } else if opcode & 0xfe0f == 0x940a {
-        Instruction::DEC
+        (Instruction::DEC, Operands::d(opcode))
+    } else if opcode == 0x9519 {
+        (Instruction::EICALL, Operands::default())
+    } else if opcode == 0x9419 {
+        (Instruction::EIJMP, Operands::default())
+    } else if opcode == 0x95d8 {
+        (Instruction::ELPM, Operands::default())
+    } else if opcode & 0xfe0f == 0x9006 {
+        (Instruction::ELPM_REG, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9007 {
-        Instruction::ELPM_INC
+        (Instruction::ELPM_INC, Operands::d(opcode))
     } else if opcode & 0xfc00 == 0x2400 {
-        Instruction::EOR
+        (Instruction::EOR, Operands::rd(opcode))
+    } else if opcode & 0xff88 == 0x308 {
+        (Instruction::FMUL, Operands::fmul_rd(opcode))
+    } else if opcode & 0xff88 == 0x380 {
+        (Instruction::FMULS, Operands::fmul_rd(opcode))
+    } else if opcode & 0xff88 == 0x388 {
+        (Instruction::FMULSU, Operands::fmul_rd(opcode))
     } else if opcode == 0x9509 {
-        Instruction::ICALL
+        (Instruction::ICALL, Operands::default())
     } else if opcode == 0x9409 {
-        Instruction::IJMP
+        (Instruction::IJMP, Operands::default())
     } else if opcode & 0xf800 == 0xb000 {
-        Instruction::IN
+        (Instruction::IN, Operands::io(opcode))
     } else if opcode & 0xfe0f == 0x9403 {
-        Instruction::INC
+        (Instruction::INC, Operands::d(opcode))
     } else if opcode & 0xfe0e == 0x940c {
-        Instruction::JMP
+        (Instruction::JMP, Operands::long_k(opcode))
     } else if opcode & 0xf000 == 0xe000 {
-        Instruction::LDI
+        (Instruction::LDI, Operands::dK(opcode))
     } else if opcode & 0xfe0f == 0x9000 {
-        Instruction::LDS
+        (Instruction::LDS, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x900c {
-        Instruction::LDX
+        (Instruction::LDX, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x900d {
-        Instruction::LDX_INC
+        (Instruction::LDX_INC, Operands::d(opcode))
+    } else if opcode & 0xfe0f == 0x900e {
+        (Instruction::LDX_DEC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x8008 {
-        Instruction::LDY
+        (Instruction::LDY, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9009 {
-        Instruction::LDY_INC
+        (Instruction::LDY_INC, Operands::d(opcode))
+    } else if opcode & 0xfe0f == 0x900a {
+        (Instruction::LDY_DEC, Operands::d(opcode))
     } else if opcode & 0xd208 == 0x8008
         && (opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8) != 0
     {
-        Instruction::LDDY
+        (Instruction::LDDY, Operands::dq(opcode))
     } else if opcode & 0xfe0f == 0x8000 {
-        Instruction::LDZ
+        (Instruction::LDZ, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9001 {
-        Instruction::LDZ_INC
+        (Instruction::LDZ_INC, Operands::d(opcode))
+    } else if opcode & 0xfe0f == 0x9002 {
+        (Instruction::LDZ_DEC, Operands::d(opcode))
     } else if opcode & 0xd208 == 0x8000
         && (opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8) != 0
     {
-        Instruction::LDDZ
+        (Instruction::LDDZ, Operands::dq(opcode))
+    } else if opcode == 0x95c8 {
+        (Instruction::LPM, Operands::default())
     } else if opcode & 0xfe0f == 0x9004 {
-        Instruction::LPM_REG
+        (Instruction::LPM_REG, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9005 {
-        Instruction::LPM_INC
+        (Instruction::LPM_INC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9406 {
-        Instruction::LSR
+        (Instruction::LSR, Operands::d(opcode))
     } else if opcode & 0xfc00 == 0x2c00 {
-        Instruction::MOV
+        (Instruction::MOV, Operands::rd(opcode))
     } else if opcode & 0xff00 == 0x100 {
-        Instruction::MOVW
+        (Instruction::MOVW, Operands::word_rd(opcode))
     } else if opcode & 0xfc00 == 0x9c00 {
-        Instruction::MUL
+        (Instruction::MUL, Operands::rd(opcode))
+    } else if opcode & 0xff00 == 0x200 {
+        (Instruction::MULS, Operands::high_rd(opcode))
+    } else if opcode & 0xff88 == 0x300 {
+        (Instruction::MULSU, Operands::fmul_rd(opcode))
     } else if opcode & 0xfe0f == 0x9401 {
-        Instruction::NEG
+        (Instruction::NEG, Operands::d(opcode))
+    } else if opcode == 0 {
+        (Instruction::NOP, Operands::default())
     } else if opcode & 0xfc00 == 0x2800 {
-        Instruction::OR
+        (Instruction::OR, Operands::rd(opcode))
     } else if opcode & 0xf800 == 0xb800 {
-        Instruction::OUT
+        (Instruction::OUT, Operands::io(opcode))
     } else if opcode & 0xfe0f == 0x900f {
-        Instruction::POP
+        (Instruction::POP, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x920f {
-        Instruction::PUSH
+        (Instruction::PUSH, Operands::d(opcode))
     } else if opcode & 0xf000 == 0xd000 {
-        Instruction::RCALL
+        (Instruction::RCALL, Operands::relative_k(opcode))
     } else if opcode == 0x9508 {
-        Instruction::RET
+        (Instruction::RET, Operands::default())
     } else if opcode == 0x9518 {
-        Instruction::RETI
+        (Instruction::RETI, Operands::default())
     } else if opcode & 0xf000 == 0xc000 {
-        Instruction::RJMP
+        (Instruction::RJMP, Operands::relative_k(opcode))
     } else if opcode & 0xfe0f == 0x9407 {
-        Instruction::ROR
+        (Instruction::ROR, Operands::d(opcode))
     } else if opcode & 0xfc00 == 0x800 {
-        Instruction::SBC
+        (Instruction::SBC, Operands::rd(opcode))
     } else if opcode & 0xf000 == 0x4000 {
-        Instruction::SBCI
+        (Instruction::SBCI, Operands::dK(opcode))
     } else if opcode & 0xff00 == 0x9a00 {
-        Instruction::SBI
+        (Instruction::SBI, Operands::Ab(opcode))
     } else if opcode & 0xff00 == 0x9900 {
-        Instruction::SBIC
+        (Instruction::SBIC, Operands::Ab(opcode))
     } else if opcode & 0xff00 == 0x9b00 {
-        Instruction::SBIS
+        (Instruction::SBIS, Operands::Ab(opcode))
     } else if opcode & 0xff00 == 0x9700 {
-        Instruction::SBIW
+        (Instruction::SBIW, Operands::word_dK(opcode))
     } else if opcode & 0xf000 == 0x6000 {
-        Instruction::SBR
+        (Instruction::SBR, Operands::dK(opcode))
     } else if opcode & 0xfe08 == 0xfc00 {
-        Instruction::SBRC
+        (Instruction::SBRC, Operands::db(opcode))
     } else if opcode & 0xfe08 == 0xfe00 {
-        Instruction::SBRS
+        (Instruction::SBRS, Operands::db(opcode))
+    } else if opcode == 0x9588 {
+        (Instruction::SLEEP, Operands::default())
+    } else if opcode == 0x95e8 {
+        (Instruction::SPM, Operands::default())
     } else if opcode & 0xd208 == 0x8208
         && (opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8) != 0
     {
-        Instruction::STDY
+        (Instruction::STDY, Operands::dq(opcode))
     } else if opcode & 0xfe0f == 0x9200 {
-        Instruction::STS
+        (Instruction::STS, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x920c {
-        Instruction::STX
+        (Instruction::STX, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x920d {
-        Instruction::STX_INC
+        (Instruction::STX_INC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x920e {
-        Instruction::STX_DEC
+        (Instruction::STX_DEC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x8208 {
-        Instruction::STY
+        (Instruction::STY, Operands::d(opcode))
+    } else if opcode & 0xfe0f == 0x9209 {
+        (Instruction::STY_INC, Operands::d(opcode))
+    } else if opcode & 0xfe0f == 0x920a {
+        (Instruction::STY_DEC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x8200 {
-        Instruction::STZ
+        (Instruction::STZ, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9201 {
-        Instruction::STZ_INC
+        (Instruction::STZ_INC, Operands::d(opcode))
     } else if opcode & 0xfe0f == 0x9202 {
-        Instruction::STZ_DEC
+        (Instruction::STZ_DEC, Operands::d(opcode))
     } else if (opcode & 0xd208) == 0x8200
         && (opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8) != 0
     {
-        Instruction::STDZ
+        (Instruction::STDZ, Operands::dq(opcode))
     } else if opcode & 0xfc00 == 0x1800 {
-        Instruction::SUB
+        (Instruction::SUB, Operands::rd(opcode))
     } else if opcode & 0xf000 == 0x5000 {
-        Instruction::SUBI
+        (Instruction::SUBI, Operands::dK(opcode))
+    } else if opcode & 0xfe0f == 0x9402 {
+        (Instruction::SWAP, Operands::d(opcode))
+    } else if opcode == 0x95a8 {
+        (Instruction::WDR, Operands::default())
     } else {
-        panic!("instruction not implemented: {}", to_binary_str(opcode))
-    }
+        return None;
+    };
+    Some(instruction)
 }
 
 pub fn is_two_word_instruction(opcode: u16) -> bool {
@@ -1,14 +1,25 @@
 use crate::{
     atmega328p::ATMega328P,
-    instruction::instructions::{decode, is_two_word_instruction},
+    error::{SimError, SimErrorKind, StepOutcome},
+    instruction::instructions::is_two_word_instruction,
     ternary,
 };
 
 pub mod instructions;
 
-pub fn avr_instruction(atmega: &mut ATMega328P) {
-    let opcode = atmega.cpu.prog_mem[atmega.cpu.pc as usize];
-    let instruction = decode(opcode);
+pub fn avr_instruction(atmega: &mut ATMega328P) -> Result<StepOutcome, SimError> {
+    let pc = atmega.cpu.pc;
+    let cycles = atmega.cpu.cycles;
+    let error = |kind| SimError { kind, pc, cycles };
+
+    let Some(&opcode) = atmega.cpu.prog_mem().get(pc as usize) else {
+        return Err(error(SimErrorKind::PcOutOfFlash));
+    };
+    // the instruction and its operands were decoded along with the program memory
+    let Some((instruction, op)) = atmega.cpu.decoded()[pc as usize] else {
+        return Err(error(SimErrorKind::IllegalOpcode(opcode)));
+    };
+    let mut outcome = StepOutcome::Executed;
 
     // println!(
     //     "ins: {:?}, opcode: {:04b} {:04b} {:04b} {:04b}",
@@ -22,13 +33,11 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
     match instruction {
         instructions::Instruction::ADC => {
             // ADC, 0001 11rd dddd rrrr
-            let d = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let r = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5));
+            let d = atmega.cpu.get_data(op.d);
+            let r = atmega.cpu.get_data(op.r);
             let sum = d as u16 + r as u16 + (atmega.cpu.data[95] as u16 & 1);
             let R = (sum & 255) as u8;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -40,12 +49,10 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::ADD => {
             // ADD, 0000 11rd dddd rrrr
-            let d = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
-            let r = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as i32;
+            let d = atmega.cpu.get_data(op.d) as i32;
+            let r = atmega.cpu.get_data(op.r) as i32;
             let R = (d + r) & 255;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R as u8);
+            atmega.cpu.set_data(op.d, R as u8);
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -57,9 +64,9 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::ADIW => {
             /* ADIW, 1001 0110 KKdd KKKK */
-            let addr = 2 * ((opcode & 0x30) >> 4) + 24;
+            let addr = op.d;
             let value = atmega.cpu.get_data_u16(addr);
-            let R = (value.wrapping_add((opcode & 0xf) | ((opcode & 0xc0) >> 2))) & 0xffff;
+            let R = value.wrapping_add(op.K);
             atmega.cpu.set_data_u16(addr, R);
             let mut sreg = atmega.cpu.data[95] & 0xe0;
             sreg |= ternary!(R, 0, 2);
@@ -72,11 +79,8 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::AND => {
             /* AND, 0010 00rd dddd rrrr */
-            let R = atmega.cpu.get_data((opcode & 0x1f0) >> 4)
-                & atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            let R = atmega.cpu.get_data(op.d) & atmega.cpu.get_data(op.r);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -85,9 +89,8 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::ANDI => {
             /* ANDI, 0111 KKKK dddd KKKK */
-            let R = atmega.cpu.get_data(((opcode & 0xf0) >> 4) + 16)
-                & ((opcode & 0xf) | ((opcode & 0xf00) >> 4)) as u8;
-            atmega.cpu.set_data(((opcode & 0xf0) >> 4) + 16, R);
+            let R = atmega.cpu.get_data(op.d) & (op.K) as u8;
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -96,9 +99,9 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::ASR => {
             /* ASR, 1001 010d dddd 0101 */
-            let value = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
+            let value = atmega.cpu.get_data(op.d);
             let R = (value >> 1) | (128 & value);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe0;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -108,12 +111,12 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             atmega.cpu.data[95] = sreg;
         }
         instructions::Instruction::BCLR => {
-            atmega.cpu.data[95] &= !(1 << ((opcode & 0x70) >> 4));
+            atmega.cpu.data[95] &= !(1 << op.b);
         }
         instructions::Instruction::BLD => {
             /* BLD, 1111 100d dddd 0bbb */
-            let b = opcode & 7;
-            let d = (opcode & 0x1f0) >> 4;
+            let b = op.b;
+            let d = op.d;
             atmega.cpu.set_data(
                 d,
                 (!(1 << b) & atmega.cpu.get_data(d)) | (((atmega.cpu.data[95] >> 6) & 1) << b),
@@ -121,60 +124,60 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::BRBC => {
             /* BRBC, 1111 01kk kkkk ksss */
-            if (atmega.cpu.data[95] & (1 << (opcode & 7))) == 0 {
-                atmega.cpu.pc = (atmega.cpu.pc + ((opcode & 0x1f8) >> 3) as u32)
-                    - ternary!(opcode & 0x200, 0x40, 0);
+            if (atmega.cpu.data[95] & (1 << op.b)) == 0 {
+                atmega.cpu.pc = atmega.cpu.pc.wrapping_add(op.k);
                 atmega.cpu.cycles += 1;
             }
         }
         instructions::Instruction::BRBS => {
             /* BRBS, 1111 00kk kkkk ksss */
-            if atmega.cpu.data[95] & (1 << (opcode & 7)) != 0 {
-                atmega.cpu.pc = atmega.cpu.pc + ((opcode & 0x1f8) >> 3) as u32
-                    - ternary!(opcode & 0x200, 0x40, 0);
+            if atmega.cpu.data[95] & (1 << op.b) != 0 {
+                atmega.cpu.pc = atmega.cpu.pc.wrapping_add(op.k);
                 atmega.cpu.cycles += 1;
             }
         }
+        instructions::Instruction::BREAK => {
+            /* BREAK, 1001 0101 1001 1000 */
+            // On-chip debugging is not simulated, so BREAK is reported to the caller
+            outcome = StepOutcome::Break;
+        }
         instructions::Instruction::BSET => {
             /* BSET, 1001 0100 0sss 1000 */
-            atmega.cpu.data[95] |= 1 << ((opcode & 0x70) >> 4);
+            atmega.cpu.data[95] |= 1 << op.b;
         }
         instructions::Instruction::BST => {
             /* BST, 1111 101d dddd 0bbb */
-            let d = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let b = opcode & 7;
+            let d = atmega.cpu.get_data(op.d);
+            let b = op.b;
             atmega.cpu.data[95] = (atmega.cpu.data[95] & 0xbf) | (ternary!((d >> b) & 1, 0x40, 0));
         }
         instructions::Instruction::CALL => {
             /* CALL, 1001 010k kkkk 111k kkkk kkkk kkkk kkkk */
-            let k = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize] as u32
-                | (((opcode & 1) as u32) << 16)
-                | (((opcode & 0x1f0) as u32) << 13);
+            let k = atmega.cpu.get_prog_word(atmega.cpu.pc + 1) as u32 | op.k;
             let ret = atmega.cpu.pc + 2;
             let sp = atmega.cpu.get_data_u16(93);
             let pc_22_bits = atmega.cpu.pc_22_bits;
-            atmega.cpu.set_data(sp, 255 & ret as u8);
-            atmega.cpu.set_data(sp - 1, (ret >> 8) as u8 & 255);
+            atmega.cpu.set_data(sp, ret as u8);
+            atmega.cpu.set_data(sp.wrapping_sub(1), (ret >> 8) as u8);
             if pc_22_bits {
-                atmega.cpu.set_data(sp - 2, (ret >> 16) as u8 & 255);
+                atmega.cpu.set_data(sp.wrapping_sub(2), (ret >> 16) as u8);
             }
             atmega
                 .cpu
-                .set_data_u16(93, sp - (if pc_22_bits { 3 } else { 2 }));
-            atmega.cpu.pc = k - 1;
+                .set_data_u16(93, sp.wrapping_sub(if pc_22_bits { 3 } else { 2 }));
+            atmega.cpu.pc = k.wrapping_sub(1);
             atmega.cpu.cycles += if pc_22_bits { 4 } else { 3 };
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::CBI => {
             /* CBI, 1001 1000 AAAA Abbb */
-            let A = opcode & 0xf8;
-            let b = opcode & 7;
-            let R = atmega.read_data((A >> 3) + 32);
-            let mask = 1 << b;
-            atmega.write_data_with_mask((A >> 3) + 32, R & !mask, mask);
+            let R = atmega.read_data(op.A);
+            let mask = 1 << op.b;
+            atmega.write_data_with_mask(op.A, R & !mask, mask);
         }
         instructions::Instruction::COM => {
             /* COM, 1001 010d dddd 0000 */
-            let d = (opcode & 0x1f0) >> 4;
+            let d = op.d;
             let R = 255 - atmega.cpu.get_data(d);
             atmega.cpu.set_data(d, R);
             let mut sreg = (atmega.cpu.data[95] & 0xe1) | 1;
@@ -185,10 +188,8 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::CP => {
             /* CP, 0001 01rd dddd rrrr */
-            let val1 = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
-            let val2 = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as i32;
+            let val1 = atmega.cpu.get_data(op.d) as i32;
+            let val2 = atmega.cpu.get_data(op.r) as i32;
             let R = val1 - val2;
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(R, 0, 2);
@@ -205,10 +206,8 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::CPC => {
             /* CPC, 0000 01rd dddd rrrr */
-            let arg1 = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
-            let arg2 = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as i32;
+            let arg1 = atmega.cpu.get_data(op.d) as i32;
+            let arg2 = atmega.cpu.get_data(op.r) as i32;
             let mut sreg = atmega.cpu.data[95];
             let r = arg1 - arg2 - (sreg as i32 & 1);
 
@@ -228,25 +227,21 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::CPSE => {
             /* CPSE, 0001 00rd dddd rrrr */
-            if atmega.cpu.get_data((opcode & 0x1f0) >> 4)
-                == atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5))
-            {
-                let next_opcode = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            if atmega.cpu.get_data(op.d) == atmega.cpu.get_data(op.r) {
+                let next_opcode = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
                 let skip_size = if is_two_word_instruction(next_opcode) {
                     2
                 } else {
                     1
                 };
                 atmega.cpu.pc += skip_size;
-                atmega.cpu.cycles += skip_size;
+                atmega.cpu.cycles += skip_size as u64;
             }
         }
         instructions::Instruction::CPI => {
             /* CPI, 0011 KKKK dddd KKKK */
-            let arg1 = atmega.cpu.get_data(((opcode & 0xf0) >> 4) + 16) as i32;
-            let arg2 = ((opcode & 0xf) | ((opcode & 0xf00) >> 4)) as i32;
+            let arg1 = atmega.cpu.get_data(op.d) as i32;
+            let arg2 = (op.K) as i32;
             let r = arg1 - arg2;
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(r, 0, 2);
@@ -259,9 +254,9 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::DEC => {
             /* DEC, 1001 010d dddd 1010 */
-            let value = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
+            let value = atmega.cpu.get_data(op.d) as i32;
             let R = value - 1;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R as u8);
+            atmega.cpu.set_data(op.d, R as u8);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -269,65 +264,143 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             sreg |= ternary!(((sreg >> 2) & 1) ^ ((sreg >> 3) & 1), 0x10, 0);
             atmega.cpu.data[95] = sreg;
         }
+        instructions::Instruction::EICALL => {
+            /* EICALL, 1001 0101 0001 1001 */
+            let ret_addr = atmega.cpu.pc + 1;
+            let sp = atmega.cpu.get_data_u16(93);
+            let eind = atmega.cpu.data[0x5c] as u32;
+            atmega.cpu.set_data(sp, (ret_addr & 255) as u8);
+            atmega
+                .cpu
+                .set_data(sp.wrapping_sub(1), ((ret_addr >> 8) & 255) as u8);
+            atmega
+                .cpu
+                .set_data(sp.wrapping_sub(2), ((ret_addr >> 16) & 255) as u8);
+            atmega.cpu.set_data_u16(93, sp.wrapping_sub(3));
+            atmega.cpu.pc = ((eind << 16) | atmega.cpu.get_data_u16(30) as u32).wrapping_sub(1);
+            atmega.cpu.cycles += 3;
+            atmega.cpu.check_stack();
+        }
+        instructions::Instruction::EIJMP => {
+            /* EIJMP, 1001 0100 0001 1001 */
+            let eind = atmega.cpu.data[0x5c] as u32;
+            atmega.cpu.pc = ((eind << 16) | atmega.cpu.get_data_u16(30) as u32).wrapping_sub(1);
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::ELPM => {
+            /* ELPM, 1001 0101 1101 1000 */
+            let rampz = atmega.cpu.data[0x5b] as u32;
+            let i = atmega.cpu.get_data_u16(30) as u32;
+            atmega
+                .cpu
+                .set_data(0, atmega.cpu.get_prog_byte((rampz << 16) | i));
+            atmega.cpu.cycles += 2;
+        }
+        instructions::Instruction::ELPM_REG => {
+            /* ELPM(REG), 1001 000d dddd 0110 */
+            let rampz = atmega.cpu.data[0x5b] as u32;
+            let i = atmega.cpu.get_data_u16(30) as u32;
+            atmega
+                .cpu
+                .set_data(op.d, atmega.cpu.get_prog_byte((rampz << 16) | i));
+            atmega.cpu.cycles += 2;
+        }
         instructions::Instruction::ELPM_INC => {
             /* ELPM(INC), 1001 000d dddd 0111 */
             let rampz = atmega.cpu.data[0x5b] as u32;
             let i = atmega.cpu.get_data_u16(30);
-            atmega.cpu.set_data(
-                (opcode & 0x1f0) >> 4,
-                atmega.cpu.prog_bytes[((rampz << 16) | (i as u32)) as usize],
-            );
-            atmega.cpu.set_data_u16(30, i + 1);
+            atmega
+                .cpu
+                .set_data(op.d, atmega.cpu.get_prog_byte((rampz << 16) | (i as u32)));
+            atmega.cpu.set_data_u16(30, i.wrapping_add(1));
             if i == 0xffff {
-                atmega.cpu.data[0x5b] =
-                    ((rampz + 1) % ((atmega.cpu.prog_bytes.len() >> 16) as u32)) as u8;
+                let rampz_pages = (atmega.cpu.prog_bytes().len() >> 16) as u32;
+                atmega.cpu.data[0x5b] = (rampz + 1).checked_rem(rampz_pages).unwrap_or(0) as u8;
             }
             atmega.cpu.cycles += 2;
         }
         instructions::Instruction::EOR => {
             /* EOR, 0010 01rd dddd rrrr */
-            let R = atmega.cpu.get_data((opcode & 0x1f0) >> 4)
-                ^ atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            let R = atmega.cpu.get_data(op.d) ^ atmega.cpu.get_data(op.r);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
             sreg |= ternary!(((sreg >> 2) & 1) ^ ((sreg >> 3) & 1), 0x10, 0);
             atmega.cpu.data[95] = sreg;
         }
+        instructions::Instruction::FMUL => {
+            /* FMUL, 0000 0011 0ddd 1rrr */
+            let v1 = atmega.cpu.get_data(op.d) as u16;
+            let v2 = atmega.cpu.get_data(op.r) as u16;
+            let product = v1 * v2;
+            let R = product << 1;
+            atmega.cpu.set_data_u16(0, R);
+            atmega.cpu.data[95] = (atmega.cpu.data[95] & 0xfc)
+                | (ternary!(R, 0, 2))
+                | (ternary!(0x8000 & product, 1, 0));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::FMULS => {
+            /* FMULS, 0000 0011 1ddd 0rrr */
+            let v1 = atmega.cpu.get_data(op.d) as i8 as i16;
+            let v2 = atmega.cpu.get_data(op.r) as i8 as i16;
+            let product = (v1 * v2) as u16;
+            let R = product << 1;
+            atmega.cpu.set_data_u16(0, R);
+            atmega.cpu.data[95] = (atmega.cpu.data[95] & 0xfc)
+                | (ternary!(R, 0, 2))
+                | (ternary!(0x8000 & product, 1, 0));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::FMULSU => {
+            /* FMULSU, 0000 0011 1ddd 1rrr */
+            let v1 = atmega.cpu.get_data(op.d) as i8 as i16;
+            let v2 = atmega.cpu.get_data(op.r) as i16;
+            let product = (v1 * v2) as u16;
+            let R = product << 1;
+            atmega.cpu.set_data_u16(0, R);
+            atmega.cpu.data[95] = (atmega.cpu.data[95] & 0xfc)
+                | (ternary!(R, 0, 2))
+                | (ternary!(0x8000 & product, 1, 0));
+            atmega.cpu.cycles += 1;
+        }
         instructions::Instruction::ICALL => {
             /* ICALL, 1001 0101 0000 1001 */
             let ret_addr = atmega.cpu.pc + 1;
             let sp = atmega.cpu.get_data_u16(93);
             let pc_22_bits = atmega.cpu.pc_22_bits;
             atmega.cpu.set_data(sp, (ret_addr & 255) as u8);
-            atmega.cpu.set_data(sp - 1, ((ret_addr >> 8) & 255) as u8);
+            atmega
+                .cpu
+                .set_data(sp.wrapping_sub(1), ((ret_addr >> 8) & 255) as u8);
             if pc_22_bits {
-                atmega.cpu.set_data(sp - 2, ((ret_addr >> 16) & 255) as u8);
+                atmega
+                    .cpu
+                    .set_data(sp.wrapping_sub(2), ((ret_addr >> 16) & 255) as u8);
             }
             atmega
                 .cpu
-                .set_data_u16(93, sp - if pc_22_bits { 3 } else { 2 });
-            atmega.cpu.pc = atmega.cpu.get_data_u16(30) as u32 - 1;
+                .set_data_u16(93, sp.wrapping_sub(if pc_22_bits { 3 } else { 2 }));
+            atmega.cpu.pc = (atmega.cpu.get_data_u16(30) as u32).wrapping_sub(1);
             atmega.cpu.cycles += if pc_22_bits { 3 } else { 2 };
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::IJMP => {
             /* IJMP, 1001 0100 0000 1001 */
-            atmega.cpu.pc = atmega.cpu.get_data_u16(30) as u32 - 1;
+            atmega.cpu.pc = (atmega.cpu.get_data_u16(30) as u32).wrapping_sub(1);
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::IN => {
             /* IN, 1011 0AAd dddd AAAA */
-            let i = atmega.read_data(((opcode & 0xf) | ((opcode & 0x600) >> 5)) + 32);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, i);
+            let i = atmega.read_data(op.A);
+            atmega.cpu.set_data(op.d, i);
         }
         instructions::Instruction::INC => {
             /* INC, 1001 010d dddd 0011 */
-            let d = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let r = (d.wrapping_add(1)) & 255;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, r);
+            let d = atmega.cpu.get_data(op.d);
+            let r = d.wrapping_add(1);
+            atmega.cpu.set_data(op.d, r);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(r, 0, 2);
             sreg |= ternary!(128 & r, 4, 0);
@@ -337,110 +410,130 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::JMP => {
             /* JMP, 1001 010k kkkk 110k kkkk kkkk kkkk kkkk */
-            atmega.cpu.pc = (atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize] as u32
-                | ((opcode as u32 & 1) << 16)
-                | ((opcode as u32 & 0x1f0) << 13))
-                - 1;
+            atmega.cpu.pc =
+                (atmega.cpu.get_prog_word(atmega.cpu.pc + 1) as u32 | op.k).wrapping_sub(1);
             atmega.cpu.cycles += 2;
         }
         instructions::Instruction::LDI => {
             /* LDI, 1110 KKKK dddd KKKK */
             // println!(
             //     "LDI: {:08b}",
-            //     (opcode & 0xf) as u8 | ((opcode & 0xf00) >> 4) as u8
+            //     op.K as u8
             // );
-            atmega.cpu.set_data(
-                ((opcode & 0xf0) >> 4) + 16,
-                (opcode & 0xf) as u8 | ((opcode & 0xf00) >> 4) as u8,
-            );
+            atmega.cpu.set_data(op.d, op.K as u8);
         }
         instructions::Instruction::LDS => {
             /* LDS, 1001 000d dddd 0000 kkkk kkkk kkkk kkkk */
             atmega.cpu.cycles += 1;
-            let value = atmega.read_data(atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize]);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, value);
+            let value = atmega.read_data(atmega.cpu.get_prog_word(atmega.cpu.pc + 1));
+            atmega.cpu.set_data(op.d, value);
             atmega.cpu.pc += 1;
         }
         instructions::Instruction::LDX => {
             /* LDX, 1001 000d dddd 1100 */
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(atmega.cpu.get_data_u16(26));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDX_INC => {
             /* LDX(INC), 1001 000d dddd 1101 */
             let x = atmega.cpu.get_data_u16(26);
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(x);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
-            atmega.cpu.set_data_u16(26, x + 1);
+            atmega.cpu.set_data(op.d, data);
+            atmega.cpu.set_data_u16(26, x.wrapping_add(1));
+        }
+        instructions::Instruction::LDX_DEC => {
+            /* LDX(DEC), 1001 000d dddd 1110 */
+            let x = atmega.cpu.get_data_u16(26).wrapping_sub(1);
+            atmega.cpu.set_data_u16(26, x);
+            atmega.cpu.cycles += 2;
+            let data = atmega.read_data(x);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDY => {
             /* LDY, 1000 000d dddd 1000 */
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(atmega.cpu.get_data_u16(28));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDY_INC => {
             /* LDY(INC), 1001 000d dddd 1001 */
             let y = atmega.cpu.get_data_u16(28);
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(y);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
-            atmega.cpu.set_data_u16(28, y + 1);
+            atmega.cpu.set_data(op.d, data);
+            atmega.cpu.set_data_u16(28, y.wrapping_add(1));
+        }
+        instructions::Instruction::LDY_DEC => {
+            /* LDY(DEC), 1001 000d dddd 1010 */
+            let y = atmega.cpu.get_data_u16(28).wrapping_sub(1);
+            atmega.cpu.set_data_u16(28, y);
+            atmega.cpu.cycles += 2;
+            let data = atmega.read_data(y);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDDY => {
             /* LDDY, 10q0 qq0d dddd 1qqq */
             atmega.cpu.cycles += 1;
-            let addr = atmega.cpu.get_data_u16(28)
-                + ((opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8));
+            let addr = atmega.cpu.get_data_u16(28).wrapping_add(op.q);
             let data = atmega.read_data(addr);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDZ => {
             /* LDZ, 1000 000d dddd 0000 */
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(atmega.cpu.get_data_u16(30));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDZ_INC => {
             /* LDZ(INC), 1001 000d dddd 0001 */
             let z = atmega.cpu.get_data_u16(30);
             atmega.cpu.cycles += 1;
             let data = atmega.read_data(z);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
-            atmega.cpu.set_data_u16(30, z + 1);
+            atmega.cpu.set_data(op.d, data);
+            atmega.cpu.set_data_u16(30, z.wrapping_add(1));
+        }
+        instructions::Instruction::LDZ_DEC => {
+            /* LDZ(DEC), 1001 000d dddd 0010 */
+            let z = atmega.cpu.get_data_u16(30).wrapping_sub(1);
+            atmega.cpu.set_data_u16(30, z);
+            atmega.cpu.cycles += 2;
+            let data = atmega.read_data(z);
+            atmega.cpu.set_data(op.d, data);
         }
         instructions::Instruction::LDDZ => {
             /* LDDZ, 10q0 qq0d dddd 0qqq */
             atmega.cpu.cycles += 1;
-            let addr = atmega.cpu.get_data_u16(30)
-                + ((opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8));
+            let addr = atmega.cpu.get_data_u16(30).wrapping_add(op.q);
             let data = atmega.read_data(addr);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, data);
+            atmega.cpu.set_data(op.d, data);
+        }
+        instructions::Instruction::LPM => {
+            /* LPM, 1001 0101 1100 1000 */
+            let data = atmega.lpm(atmega.cpu.get_data_u16(30) as u32);
+            atmega.cpu.set_data(0, data);
+            atmega.cpu.cycles += 2;
         }
         instructions::Instruction::LPM_REG => {
             /* LPM(REG), 1001 000d dddd 0100 */
-            atmega.cpu.set_data(
-                (opcode & 0x1f0) >> 4,
-                atmega.cpu.prog_bytes[atmega.cpu.get_data_u16(30) as usize],
-            );
+            let data = atmega.lpm(atmega.cpu.get_data_u16(30) as u32);
+            atmega.cpu.set_data(op.d, data);
             atmega.cpu.cycles += 2;
         }
         instructions::Instruction::LPM_INC => {
             /* LPM(INC), 1001 000d dddd 0101 */
             let i = atmega.cpu.get_data_u16(30);
-            atmega
-                .cpu
-                .set_data((opcode & 0x1f0) >> 4, atmega.cpu.prog_bytes[i as usize]);
-            atmega.cpu.set_data_u16(30, i + 1);
+            let data = atmega.lpm(i as u32);
+            atmega.cpu.set_data(op.d, data);
+            atmega.cpu.set_data_u16(30, i.wrapping_add(1));
             atmega.cpu.cycles += 2;
         }
         instructions::Instruction::LSR => {
             /* LSR, 1001 010d dddd 0110 */
-            let value = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
+            let value = atmega.cpu.get_data(op.d);
             let R = value >> 1;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe0;
             sreg |= ternary!(R, 0, 2);
             sreg |= value & 1;
@@ -450,35 +543,42 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::MOV => {
             /* MOV, 0010 11rd dddd rrrr */
-            atmega.cpu.set_data(
-                (opcode & 0x1f0) >> 4,
-                atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)),
-            );
+            atmega.cpu.set_data(op.d, atmega.cpu.get_data(op.r));
         }
         instructions::Instruction::MOVW => {
             /* MOVW, 0000 0001 dddd rrrr */
-            let r2 = 2 * (opcode & 0xf);
-            let d2 = 2 * ((opcode & 0xf0) >> 4);
-            atmega.cpu.set_data(d2, atmega.cpu.get_data(r2));
-            atmega.cpu.set_data(d2 + 1, atmega.cpu.get_data(r2 + 1));
+            atmega.cpu.set_data(op.d, atmega.cpu.get_data(op.r));
+            atmega.cpu.set_data(op.d + 1, atmega.cpu.get_data(op.r + 1));
         }
         instructions::Instruction::MUL => {
             /* MUL, 1001 11rd dddd rrrr */
-            let R = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as u16
-                * atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as u16;
+            let R = atmega.cpu.get_data(op.d) as u16 * atmega.cpu.get_data(op.r) as u16;
             atmega.cpu.set_data_u16(0, R);
-            atmega.cpu.data[95] = (atmega.cpu.data[95] & 0xfc)
-                | (ternary!(0xffff & R, 0, 2))
-                | (ternary!(0x8000 & R, 1, 0));
+            atmega.cpu.data[95] =
+                (atmega.cpu.data[95] & 0xfc) | (ternary!(R, 0, 2)) | (ternary!(0x8000 & R, 1, 0));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::MULS => {
+            /* MULS, 0000 0010 dddd rrrr */
+            let R = (atmega.cpu.get_data(op.d) as i8 as i16
+                * atmega.cpu.get_data(op.r) as i8 as i16) as u16;
+            atmega.cpu.set_data_u16(0, R);
+            atmega.cpu.data[95] =
+                (atmega.cpu.data[95] & 0xfc) | (ternary!(R, 0, 2)) | (ternary!(0x8000 & R, 1, 0));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::MULSU => {
+            /* MULSU, 0000 0011 0ddd 0rrr */
+            let R =
+                (atmega.cpu.get_data(op.d) as i8 as i16 * atmega.cpu.get_data(op.r) as i16) as u16;
+            atmega.cpu.set_data_u16(0, R);
+            atmega.cpu.data[95] =
+                (atmega.cpu.data[95] & 0xfc) | (ternary!(R, 0, 2)) | (ternary!(0x8000 & R, 1, 0));
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::NEG => {
             /* NEG, 1001 010d dddd 0001 */
-            let d = (opcode & 0x1f0) >> 4;
+            let d = op.d;
             let value = atmega.cpu.get_data(d) as i32;
             let R = 0 - value;
             atmega.cpu.set_data(d, R as u8);
@@ -491,13 +591,11 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             sreg |= ternary!(1 & (R | value), 0x20, 0);
             atmega.cpu.data[95] = sreg;
         }
+        instructions::Instruction::NOP => { /* NOP, 0000 0000 0000 0000 */ }
         instructions::Instruction::OR => {
             /* OR, 0010 10rd dddd rrrr */
-            let R = atmega.cpu.get_data((opcode & 0x1f0) >> 4)
-                | atmega
-                    .cpu
-                    .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5));
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R);
+            let R = atmega.cpu.get_data(op.d) | atmega.cpu.get_data(op.r);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -505,85 +603,92 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             atmega.cpu.data[95] = sreg;
         }
         instructions::Instruction::OUT => {
-            atmega.write_data_with_mask(
-                ((opcode & 0xf) | ((opcode & 0x600) >> 5)) + 32,
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
-                0xff,
-            );
+            atmega.write_data_with_mask(op.A, atmega.cpu.get_data(op.d), 0xff);
         }
         instructions::Instruction::POP => {
             /* POP, 1001 000d dddd 1111 */
-            let value = atmega.cpu.get_data_u16(93) + 1;
+            let value = atmega.cpu.get_data_u16(93).wrapping_add(1);
             atmega.cpu.set_data_u16(93, value);
-            atmega
-                .cpu
-                .set_data((opcode & 0x1f0) >> 4, atmega.cpu.get_data(value));
+            atmega.cpu.set_data(op.d, atmega.cpu.get_data(value));
             atmega.cpu.cycles += 1;
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::PUSH => {
             /* PUSH, 1001 001d dddd 1111 */
             let value = atmega.cpu.get_data_u16(93);
-            atmega
-                .cpu
-                .set_data(value, atmega.cpu.get_data((opcode & 0x1f0) >> 4));
-            atmega.cpu.set_data_u16(93, value - 1);
+            atmega.cpu.set_data(value, atmega.cpu.get_data(op.d));
+            atmega.cpu.set_data_u16(93, value.wrapping_sub(1));
             atmega.cpu.cycles += 1;
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::RCALL => {
             /* RCALL, 1101 kkkk kkkk kkkk */
-            let k = (opcode & 0x7ff) as i32 - ternary!(opcode & 0x800, 0x800, 0) as i32;
             let ret_addr = atmega.cpu.pc + 1;
             let sp = atmega.cpu.get_data_u16(93);
             let pc_22_bits = atmega.cpu.pc_22_bits;
             atmega.cpu.set_data(sp, (255 & ret_addr) as u8);
-            atmega.cpu.set_data(sp - 1, ((ret_addr >> 8) & 255) as u8);
+            atmega
+                .cpu
+                .set_data(sp.wrapping_sub(1), ((ret_addr >> 8) & 255) as u8);
             if pc_22_bits {
-                atmega.cpu.set_data(sp - 2, ((ret_addr >> 16) & 255) as u8);
+                atmega
+                    .cpu
+                    .set_data(sp.wrapping_sub(2), ((ret_addr >> 16) & 255) as u8);
             }
             atmega
                 .cpu
-                .set_data_u16(93, sp - (if pc_22_bits { 3 } else { 2 }));
-            atmega.cpu.pc = atmega.cpu.pc.wrapping_add(k as u32);
+                .set_data_u16(93, sp.wrapping_sub(if pc_22_bits { 3 } else { 2 }));
+            atmega.cpu.pc = atmega.cpu.pc.wrapping_add(op.k);
             // atmega328p.cpu.pc = (atmega328p.cpu.pc as i64 + k as i64) as u32;
             atmega.cpu.cycles += if pc_22_bits { 3 } else { 2 };
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::RET => {
             /* RET, 1001 0101 0000 1000 */
             let pc_22_bits = atmega.cpu.pc_22_bits;
-            let i = atmega.cpu.get_data_u16(93) + if pc_22_bits { 3 } else { 2 };
+            let i = atmega
+                .cpu
+                .get_data_u16(93)
+                .wrapping_add(if pc_22_bits { 3 } else { 2 });
             atmega.cpu.set_data_u16(93, i);
-            atmega.cpu.pc =
-                ((atmega.cpu.get_data(i - 1) as u32) << 8) + atmega.cpu.get_data(i) as u32 - 1;
+            atmega.cpu.pc = (((atmega.cpu.get_data(i.wrapping_sub(1)) as u32) << 8)
+                + atmega.cpu.get_data(i) as u32)
+                .wrapping_sub(1);
             if pc_22_bits {
-                atmega.cpu.pc |= (atmega.cpu.get_data(i - 2) as u32) << 16;
+                atmega.cpu.pc |= (atmega.cpu.get_data(i.wrapping_sub(2)) as u32) << 16;
             }
             atmega.cpu.cycles += if pc_22_bits { 4 } else { 3 };
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::RETI => {
             /* RETI, 1001 0101 0001 1000 */
             let pc_22_bits = atmega.cpu.pc_22_bits;
-            let i = atmega.cpu.get_data_u16(93) + (if pc_22_bits { 3 } else { 2 });
+            let i = atmega
+                .cpu
+                .get_data_u16(93)
+                .wrapping_add(if pc_22_bits { 3 } else { 2 });
             atmega.cpu.set_data_u16(93, i);
-            atmega.cpu.pc =
-                ((atmega.cpu.get_data(i - 1) as u32) << 8) + atmega.cpu.get_data(i) as u32 - 1;
+            atmega.cpu.pc = (((atmega.cpu.get_data(i.wrapping_sub(1)) as u32) << 8)
+                + atmega.cpu.get_data(i) as u32)
+                .wrapping_sub(1);
             if pc_22_bits {
-                atmega.cpu.pc |= (atmega.cpu.get_data(i - 2) as u32) << 16;
+                atmega.cpu.pc |= (atmega.cpu.get_data(i.wrapping_sub(2)) as u32) << 16;
             }
             atmega.cpu.cycles += if pc_22_bits { 4 } else { 3 };
             atmega.cpu.data[95] |= 0x80; // Enable interrupts
+            atmega.cpu.check_stack();
         }
         instructions::Instruction::RJMP => {
             /* RJMP, 1100 kkkk kkkk kkkk */
-            atmega.cpu.pc =
-                atmega.cpu.pc + (opcode & 0x7ff) as u32 - ternary!(opcode & 0x800, 0x800, 0);
+            atmega.cpu.pc = atmega.cpu.pc.wrapping_add(op.k);
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::ROR => {
             /* ROR, 1001 010d dddd 0111 */
 
-            let d = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
+            let d = atmega.cpu.get_data(op.d);
             let r = (d >> 1) | ((atmega.cpu.data[95] & 1) << 7);
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, r);
+            atmega.cpu.set_data(op.d, r);
             let mut sreg = atmega.cpu.data[95] & 0xe0;
             sreg |= ternary!(r, 0, 2);
             sreg |= ternary!(128 & r, 4, 0);
@@ -594,14 +699,12 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SBC => {
             /* SBC, 0000 10rd dddd rrrr */
-            let val1 = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
-            let val2 = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as i32;
+            let val1 = atmega.cpu.get_data(op.d) as i32;
+            let val2 = atmega.cpu.get_data(op.r) as i32;
             let mut sreg = atmega.cpu.data[95];
             // let R = val1.wrapping_sub(val2).wrapping_sub(sreg & 1);
             let R = val1 - val2 - (sreg & 1) as i32;
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R as u8);
+            atmega.cpu.set_data(op.d, R as u8);
             sreg = (sreg & 0xc0)
                 | (if R == 0 && (sreg >> 1) & 1 != 0 { 2 } else { 0 })
                 | (if val2 as u16 + (sreg as u16 & 1) > val1 as u16 {
@@ -617,12 +720,12 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SBCI => {
             /* SBCI, 0100 KKKK dddd KKKK */
-            let val1 = atmega.cpu.get_data(((opcode & 0xf0) >> 4) + 16) as i32;
-            let val2 = ((opcode & 0xf) | ((opcode & 0xf00) >> 4)) as i32;
+            let val1 = atmega.cpu.get_data(op.d) as i32;
+            let val2 = (op.K) as i32;
             let mut sreg = atmega.cpu.data[95];
             // let R = val1.wrapping_sub(val2).wrapping_sub(sreg & 1);
             let R = val1 - val2 - (sreg & 1) as i32;
-            atmega.cpu.set_data(((opcode & 0xf0) >> 4) + 16, R as u8);
+            atmega.cpu.set_data(op.d, R as u8);
             sreg = (sreg & 0xc0)
                 | (if R == 0 && (sreg >> 1) & 1 != 0 { 2 } else { 0 })
                 | (if val2 as u16 + (sreg as u16 & 1) > val1 as u16 {
@@ -638,45 +741,45 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SBI => {
             /* SBI, 1001 1010 AAAA Abbb */
-            let target = ((opcode & 0xf8) >> 3) + 32;
-            let mask = 1 << (opcode & 7);
+            let target = op.A;
+            let mask = 1 << op.b;
             let data = atmega.read_data(target) | mask;
             atmega.write_data_with_mask(target, data, mask);
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::SBIC => {
             /* SBIC, 1001 1001 AAAA Abbb */
-            let value = atmega.read_data(((opcode & 0xf8) >> 3) + 32);
-            if value & (1 << (opcode & 7)) == 0 {
-                let next_opcode = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            let value = atmega.read_data(op.A);
+            if value & (1 << op.b) == 0 {
+                let next_opcode = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
                 let skip_size = if is_two_word_instruction(next_opcode) {
                     2
                 } else {
                     1
                 };
-                atmega.cpu.cycles += skip_size;
+                atmega.cpu.cycles += skip_size as u64;
                 atmega.cpu.pc += skip_size;
             }
         }
         instructions::Instruction::SBIS => {
             /* SBIS, 1001 1011 AAAA Abbb */
-            let value = atmega.read_data(((opcode & 0xf8) >> 3) + 32);
-            if value & (1 << (opcode & 7)) != 0 {
-                let next_opcode = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            let value = atmega.read_data(op.A);
+            if value & (1 << op.b) != 0 {
+                let next_opcode = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
                 let skip_size = if is_two_word_instruction(next_opcode) {
                     2
                 } else {
                     1
                 };
-                atmega.cpu.cycles += skip_size;
+                atmega.cpu.cycles += skip_size as u64;
                 atmega.cpu.pc += skip_size;
             }
         }
         instructions::Instruction::SBIW => {
             /* SBIW, 1001 0111 KKdd KKKK */
-            let i = 2 * ((opcode & 0x30) >> 4) + 24;
+            let i = op.d;
             let a = atmega.cpu.get_data_u16(i) as i32;
-            let l = ((opcode & 0xf) | ((opcode & 0xc0) >> 2)) as i32;
+            let l = (op.K) as i32;
             let R = a - l;
             atmega.cpu.set_data_u16(i, R as u16);
             let mut sreg = atmega.cpu.data[95] & 0xc0;
@@ -691,9 +794,8 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SBR => {
             /* SBR, 0110 KKKK dddd KKKK */
-            let R = atmega.cpu.get_data(((opcode & 0xf0) >> 4) + 16)
-                | ((opcode & 0xf) as u8 | ((opcode & 0xf00) >> 4) as u8);
-            atmega.cpu.set_data(((opcode & 0xf0) >> 4) + 16, R);
+            let R = atmega.cpu.get_data(op.d) | (op.K as u8);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xe1;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -702,44 +804,51 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SBRC => {
             /* SBRC, 1111 110r rrrr 0bbb */
-            if atmega.cpu.get_data((opcode & 0x1f0) >> 4) & (1 << (opcode & 7)) == 0 {
-                let next_opcode = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            if atmega.cpu.get_data(op.d) & (1 << op.b) == 0 {
+                let next_opcode = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
                 let skip_size = if is_two_word_instruction(next_opcode) {
                     2
                 } else {
                     1
                 };
-                atmega.cpu.cycles += skip_size;
+                atmega.cpu.cycles += skip_size as u64;
                 atmega.cpu.pc += skip_size;
             }
         }
         instructions::Instruction::SBRS => {
             /* SBRS, 1111 111r rrrr 0bbb */
-            if atmega.cpu.get_data((opcode & 0x1f0) >> 4) & (1 << (opcode & 7)) != 0 {
-                let next_opcode = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            if atmega.cpu.get_data(op.d) & (1 << op.b) != 0 {
+                let next_opcode = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
                 let skip_size = if is_two_word_instruction(next_opcode) {
                     2
                 } else {
                     1
                 };
-                atmega.cpu.cycles += skip_size;
+                atmega.cpu.cycles += skip_size as u64;
                 atmega.cpu.pc += skip_size;
             }
         }
+        instructions::Instruction::SLEEP => {
+            /* SLEEP, 1001 0101 1000 1000 */
+            atmega.sleep();
+        }
+        instructions::Instruction::SPM => {
+            /* SPM, 1001 0101 1110 1000 */
+            atmega.spm();
+        }
         instructions::Instruction::STDY => {
             /* STDY, 10q0 qq1r rrrr 1qqq */
             atmega.write_data_with_mask(
-                atmega.cpu.get_data_u16(28)
-                    + ((opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8)),
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
+                atmega.cpu.get_data_u16(28).wrapping_add(op.q),
+                atmega.cpu.get_data(op.d),
                 0xff,
             );
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::STS => {
             /* STS, 1001 001d dddd 0000 kkkk kkkk kkkk kkkk */
-            let value = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let addr = atmega.cpu.prog_mem[(atmega.cpu.pc + 1) as usize];
+            let value = atmega.cpu.get_data(op.d);
+            let addr = atmega.cpu.get_prog_word(atmega.cpu.pc + 1);
             atmega.write_data_with_mask(addr, value, 0xff);
             atmega.cpu.pc += 1;
             atmega.cpu.cycles += 1;
@@ -748,7 +857,7 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             /* STX, 1001 001r rrrr 1100 */
             atmega.write_data_with_mask(
                 atmega.cpu.get_data_u16(26),
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
+                atmega.cpu.get_data(op.d),
                 0xff,
             );
             atmega.cpu.cycles += 1;
@@ -756,31 +865,43 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         instructions::Instruction::STX_INC => {
             /* STX(INC), 1001 001r rrrr 1101 */
             let x = atmega.cpu.get_data_u16(26);
-            atmega.write_data_with_mask(x, atmega.cpu.get_data((opcode & 0x1f0) >> 4), 0xff);
-            atmega.cpu.set_data_u16(26, x + 1);
+            atmega.write_data_with_mask(x, atmega.cpu.get_data(op.d), 0xff);
+            atmega.cpu.set_data_u16(26, x.wrapping_add(1));
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::STX_DEC => {
             /* STX(DEC), 1001 001r rrrr 1110 */
-            let i = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let x = atmega.cpu.get_data_u16(26) - 1;
+            let i = atmega.cpu.get_data(op.d);
+            let x = atmega.cpu.get_data_u16(26).wrapping_sub(1);
             atmega.cpu.set_data_u16(26, x);
             atmega.write_data_with_mask(x, i, 0xff);
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::STY => {
             /* STY, 1000 001r rrrr 1000 */
-            atmega.write_data(
-                atmega.cpu.get_data_u16(28),
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
-            );
+            atmega.write_data(atmega.cpu.get_data_u16(28), atmega.cpu.get_data(op.d));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::STY_INC => {
+            /* STY(INC), 1001 001r rrrr 1001 */
+            let y = atmega.cpu.get_data_u16(28);
+            atmega.write_data(y, atmega.cpu.get_data(op.d));
+            atmega.cpu.set_data_u16(28, y.wrapping_add(1));
+            atmega.cpu.cycles += 1;
+        }
+        instructions::Instruction::STY_DEC => {
+            /* STY(DEC), 1001 001r rrrr 1010 */
+            let i = atmega.cpu.get_data(op.d);
+            let y = atmega.cpu.get_data_u16(28).wrapping_sub(1);
+            atmega.cpu.set_data_u16(28, y);
+            atmega.write_data(y, i);
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::STZ => {
             /* STZ, 1000 001r rrrr 0000 */
             atmega.write_data_with_mask(
                 atmega.cpu.get_data_u16(30),
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
+                atmega.cpu.get_data(op.d),
                 0xff,
             );
             atmega.cpu.cycles += 1;
@@ -789,14 +910,14 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             /* STZ(INC), 1001 001r rrrr 0001 */
             let cpu = &atmega.cpu;
             let z = atmega.cpu.get_data_u16(30);
-            atmega.write_data(z, cpu.get_data((opcode & 0x1f0) >> 4));
-            atmega.cpu.set_data_u16(30, z + 1);
+            atmega.write_data(z, cpu.get_data(op.d));
+            atmega.cpu.set_data_u16(30, z.wrapping_add(1));
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::STZ_DEC => {
             /* STZ(DEC), 1001 001r rrrr 0010 */
-            let i = atmega.cpu.get_data((opcode & 0x1f0) >> 4);
-            let z = atmega.cpu.get_data_u16(30) - 1;
+            let i = atmega.cpu.get_data(op.d);
+            let z = atmega.cpu.get_data_u16(30).wrapping_sub(1);
             atmega.cpu.set_data_u16(30, z);
             atmega.write_data(z, i);
             atmega.cpu.cycles += 1;
@@ -804,22 +925,19 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         instructions::Instruction::STDZ => {
             /* STDZ, 10q0 qq1r rrrr 0qqq */
             atmega.write_data_with_mask(
-                atmega.cpu.get_data_u16(30)
-                    + ((opcode & 7) | ((opcode & 0xc00) >> 7) | ((opcode & 0x2000) >> 8)),
-                atmega.cpu.get_data((opcode & 0x1f0) >> 4),
+                atmega.cpu.get_data_u16(30).wrapping_add(op.q),
+                atmega.cpu.get_data(op.d),
                 0xff,
             );
             atmega.cpu.cycles += 1;
         }
         instructions::Instruction::SUB => {
             /* SUB, 0001 10rd dddd rrrr */
-            let val1 = atmega.cpu.get_data((opcode & 0x1f0) >> 4) as i32;
-            let val2 = atmega
-                .cpu
-                .get_data((opcode & 0xf) | ((opcode & 0x200) >> 5)) as i32;
+            let val1 = atmega.cpu.get_data(op.d) as i32;
+            let val2 = atmega.cpu.get_data(op.r) as i32;
             let R = val1 - val2;
 
-            atmega.cpu.set_data((opcode & 0x1f0) >> 4, R as u8);
+            atmega.cpu.set_data(op.d, R as u8);
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -831,10 +949,10 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
         }
         instructions::Instruction::SUBI => {
             /* SUBI, 0101 KKKK dddd KKKK */
-            let val1 = atmega.cpu.get_data(((opcode & 0xf0) >> 4) + 16) as u8;
-            let val2 = ((opcode & 0xf) | ((opcode & 0xf00) >> 4)) as u8;
+            let val1 = atmega.cpu.get_data(op.d);
+            let val2 = (op.K) as u8;
             let R = val1.wrapping_sub(val2);
-            atmega.cpu.set_data(((opcode & 0xf0) >> 4) + 16, R);
+            atmega.cpu.set_data(op.d, R);
             let mut sreg = atmega.cpu.data[95] & 0xc0;
             sreg |= ternary!(R, 0, 2);
             sreg |= ternary!(128 & R, 4, 0);
@@ -844,8 +962,607 @@ pub fn avr_instruction(atmega: &mut ATMega328P) {
             sreg |= ternary!(1 & ((!val1 & val2) | (val2 & R) | (R & !val1)), 0x20, 0);
             atmega.cpu.data[95] = sreg;
         }
+        instructions::Instruction::SWAP => {
+            /* SWAP, 1001 010d dddd 0010 */
+            let d = op.d;
+            let i = atmega.cpu.get_data(d);
+            atmega.cpu.set_data(d, ((15 & i) << 4) | ((240 & i) >> 4));
+        }
+        instructions::Instruction::WDR => {
+            /* WDR, 1001 0101 1010 1000 */
+            atmega.watchdog_restart();
+        }
     }
 
-    atmega.cpu.pc = (atmega.cpu.pc + 1) % atmega.cpu.prog_mem.len() as u32;
+    atmega.cpu.pc = atmega.cpu.pc.wrapping_add(1);
     atmega.cpu.cycles += 1;
+
+    match atmega.cpu.take_fault() {
+        Some(kind) => Err(error(kind)),
+        None => Ok(outcome),
+    }
+}
+
+#[cfg(test)]
+mod instruction_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        error::{SimError, SimErrorKind, StepOutcome},
+        instruction::{
+            avr_instruction,
+            instructions::{Instruction, Operands, decode},
+        },
+        interrupt::AVRInterruptConfig,
+    };
+
+    const SREG: usize = 95;
+    const SREG_C: u8 = 1 << 0;
+    const SREG_Z: u8 = 1 << 1;
+
+    /// Load the given instruction words at the beginning of the program memory
+    fn load_program(atmega: &mut ATMega328P, words: &[u16]) {
+        for (i, word) in words.iter().enumerate() {
+            atmega.cpu.set_prog_word(i as u32, *word);
+        }
+    }
+
+    #[test]
+    fn nop() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0000]);
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.cycles, 1);
+    }
+
+    #[test]
+    fn swap() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9522]); // SWAP r18
+        atmega.cpu.data[18] = 0xa5;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[18], 0x5a);
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.cycles, 1);
+    }
+
+    #[test]
+    fn muls() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0223]); // MULS r18, r19
+        atmega.cpu.data[18] = (-5i8) as u8;
+        atmega.cpu.data[19] = 100;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), (-500i16) as u16);
+        assert_eq!(atmega.cpu.data[SREG], SREG_C); // negative result sets carry (bit 15)
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn mulsu() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0345]); // MULSU r20, r21
+        atmega.cpu.data[20] = (-2i8) as u8;
+        atmega.cpu.data[21] = 200;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), (-400i16) as u16);
+        assert_eq!(atmega.cpu.data[SREG], SREG_C);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn fmul() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0329]); // FMUL r18, r17
+        atmega.cpu.data[18] = 0x80; // 1.0 in 1.7 format
+        atmega.cpu.data[17] = 0xc0; // 1.5 in 1.7 format
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), 0xc000); // 1.5 in 1.15 format
+        assert_eq!(atmega.cpu.data[SREG], 0);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn fmul_sets_carry_and_zero() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0329]); // FMUL r18, r17
+        atmega.cpu.data[18] = 0x80;
+        atmega.cpu.data[17] = 0x00;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), 0);
+        assert_eq!(atmega.cpu.data[SREG], SREG_Z);
+    }
+
+    #[test]
+    fn fmuls() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x03b1]); // FMULS r19, r17
+        atmega.cpu.data[19] = 0xc0; // -0.5 in 1.7 format
+        atmega.cpu.data[17] = 0x40; // 0.5 in 1.7 format
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), 0xe000); // -0.25 in 1.15 format
+        assert_eq!(atmega.cpu.data[SREG], SREG_C);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn fmulsu() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x03c9]); // FMULSU r20, r17
+        atmega.cpu.data[20] = 0xc0; // -0.5 in 1.7 format
+        atmega.cpu.data[17] = 0x80; // 1.0 in 1.7 format (unsigned)
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.get_data_u16(0), 0xc000); // -0.5 in 1.15 format
+        assert_eq!(atmega.cpu.data[SREG], SREG_C);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn ldx_dec() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x90ae]); // LD r10, -X
+        atmega.cpu.set_data_u16(26, 0x99);
+        atmega.cpu.data[0x98] = 0x22;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[10], 0x22);
+        assert_eq!(atmega.cpu.get_data_u16(26), 0x98);
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn ldy_dec() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x90aa]); // LD r10, -Y
+        atmega.cpu.set_data_u16(28, 0x99);
+        atmega.cpu.data[0x98] = 0x33;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[10], 0x33);
+        assert_eq!(atmega.cpu.get_data_u16(28), 0x98);
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn ldz_dec() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x90a2]); // LD r10, -Z
+        atmega.cpu.set_data_u16(30, 0x99);
+        atmega.cpu.data[0x98] = 0x44;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[10], 0x44);
+        assert_eq!(atmega.cpu.get_data_u16(30), 0x98);
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn sty_inc() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x92a9]); // ST Y+, r10
+        atmega.cpu.data[10] = 0x55;
+        atmega.cpu.set_data_u16(28, 0x90);
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[0x90], 0x55);
+        assert_eq!(atmega.cpu.get_data_u16(28), 0x91);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn sty_dec() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x92aa]); // ST -Y, r10
+        atmega.cpu.data[10] = 0x66;
+        atmega.cpu.set_data_u16(28, 0x90);
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[0x8f], 0x66);
+        assert_eq!(atmega.cpu.get_data_u16(28), 0x8f);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn lpm() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x95c8, 0xa55a]); // LPM, data word
+        atmega.cpu.set_data_u16(30, 0x3);
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[0], 0xa5);
+        assert_eq!(atmega.cpu.get_data_u16(30), 0x3); // Z is not incremented
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn elpm() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x95d8, 0xa55a]); // ELPM, data word
+        atmega.cpu.set_data_u16(30, 0x2);
+        atmega.cpu.data[0x5b] = 0; // RAMPZ
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[0], 0x5a);
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn elpm_reg() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9116, 0xa55a]); // ELPM r17, Z
+        atmega.cpu.set_data_u16(30, 0x3);
+        atmega.cpu.data[0x5b] = 0; // RAMPZ
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.data[17], 0xa5);
+        assert_eq!(atmega.cpu.get_data_u16(30), 0x3);
+        assert_eq!(atmega.cpu.cycles, 3);
+    }
+
+    #[test]
+    fn eicall() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9519]); // EICALL
+        atmega.cpu.set_sp(0x200);
+        atmega.cpu.set_data_u16(30, 0x1234);
+        atmega.cpu.data[0x5c] = 0; // EIND
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 0x1234);
+        assert_eq!(atmega.cpu.get_data_u16(93), 0x1fd);
+        assert_eq!(atmega.cpu.data[0x200], 1); // return address
+        assert_eq!(atmega.cpu.cycles, 4);
+    }
+
+    #[test]
+    fn eijmp() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9419]); // EIJMP
+        atmega.cpu.set_data_u16(30, 0x1040);
+        atmega.cpu.data[0x5c] = 0; // EIND
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 0x1040);
+        assert_eq!(atmega.cpu.cycles, 2);
+    }
+
+    #[test]
+    fn break_is_reported() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9598]); // BREAK
+
+        // Act
+        let outcome = avr_instruction(&mut atmega);
+
+        // Assert
+        assert_eq!(outcome, Ok(StepOutcome::Break));
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.cycles, 1);
+    }
+
+    #[test]
+    fn illegal_opcode() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0000, 0xffff]); // NOP, erased flash
+        avr_instruction(&mut atmega).unwrap();
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SimError {
+                kind: SimErrorKind::IllegalOpcode(0xffff),
+                pc: 1,
+                cycles: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn pc_out_of_flash() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9409]); // IJMP
+        atmega.cpu.set_data_u16(30, 0x8000); // beyond the 16K words of flash
+        avr_instruction(&mut atmega).unwrap();
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SimError {
+                kind: SimErrorKind::PcOutOfFlash,
+                pc: 0x8000,
+                cycles: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn data_out_of_range() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x91a0, 0xfff0]); // LDS r26, 0xfff0
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, SimErrorKind::DataOutOfRange(0xfff0));
+        assert_eq!(error.pc, 0);
+        assert_eq!(error.cycles, 0);
+    }
+
+    #[test]
+    fn flash_out_of_range() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x95c8]); // LPM
+        atmega.cpu.set_data_u16(30, 0x9000); // beyond the 32KB of flash
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        assert_eq!(
+            result.unwrap_err().kind,
+            SimErrorKind::FlashOutOfRange(0x9000)
+        );
+    }
+
+    #[test]
+    fn stack_underflow() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x9508]); // RET with an empty stack
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        let sp = atmega.cpu.data.len() as u16 + 1;
+        assert_eq!(result.unwrap_err().kind, SimErrorKind::StackUnderflow(sp));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x920f, 0x920f]); // PUSH r0, PUSH r0
+        atmega.cpu.set_sp(0x100);
+        avr_instruction(&mut atmega).unwrap(); // pushes into the last SRAM byte
+
+        // Act
+        let result = avr_instruction(&mut atmega);
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SimError {
+                kind: SimErrorKind::StackOverflow(0xfe),
+                pc: 1,
+                cycles: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn interrupt_stack_overflow_reports_the_last_instruction() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0000, 0x0000]); // NOP, NOP
+        atmega.cpu.set_sp(0x100);
+        atmega.cpu.set_sreg(0x80);
+        atmega.step(None).unwrap();
+        atmega.cpu.queue_interrupt(AVRInterruptConfig {
+            address: 0x1a,
+            enable_register: 0,
+            enable_mask: 0,
+            flag_register: 0,
+            flag_mask: 0,
+            inverse_flag: false,
+            constant: false,
+        });
+
+        // Act
+        let result = atmega.step(None);
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SimError {
+                kind: SimErrorKind::StackOverflow(0xfe),
+                pc: 1,
+                cycles: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rewritten_flash_is_decoded_again() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0xffff]); // illegal opcode
+        atmega.cpu.data[18] = 0xa5;
+
+        // Act
+        atmega.cpu.set_prog_word(0, 0x9522); // SWAP r18
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.prog_bytes()[0..2], [0x22, 0x95]);
+        assert_eq!(atmega.cpu.data[18], 0x5a);
+    }
+
+    #[test]
+    fn operands_are_decoded_with_the_instruction() {
+        // Arrange
+        let cases = [
+            // RJMP .-2
+            (
+                0xcfff,
+                Instruction::RJMP,
+                Operands {
+                    k: u32::MAX,
+                    ..Default::default()
+                },
+            ),
+            // BRNE .-4
+            (
+                0xf7f1,
+                Instruction::BRBC,
+                Operands {
+                    k: u32::MAX - 1,
+                    b: 1,
+                    ..Default::default()
+                },
+            ),
+            // CALL, bit 16 of the address
+            (
+                0x940f,
+                Instruction::CALL,
+                Operands {
+                    k: 1 << 16,
+                    ..Default::default()
+                },
+            ),
+            // IN r16, SREG
+            (
+                0xb70f,
+                Instruction::IN,
+                Operands {
+                    d: 16,
+                    A: 0x5f,
+                    ..Default::default()
+                },
+            ),
+            // LDD r24, Y+63
+            (
+                0xad8f,
+                Instruction::LDDY,
+                Operands {
+                    d: 24,
+                    q: 63,
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        for (opcode, instruction, operands) in cases {
+            // Act
+            let decoded = decode(opcode);
+
+            // Assert
+            assert_eq!(
+                decoded,
+                Some((instruction, operands)),
+                "opcode {:#06x}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn cycles_do_not_wrap_at_u32() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        load_program(&mut atmega, &[0x0000, 0x0000]); // NOP, NOP
+        atmega.cpu.cycles = u32::MAX as u64;
+
+        // Act
+        avr_instruction(&mut atmega).unwrap();
+        avr_instruction(&mut atmega).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, (1 << 32) + 1);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{cpu::CPU, peripheral::timer::AVRTimerConfig};
+use crate::cpu::CPU;
 
 pub const MAX_INTERRUPTS: usize = 128; // Enough for ATMega2560
 
@@ -9,18 +9,19 @@ pub struct AVRInterruptConfig {
     pub enable_mask: u8,
     pub flag_register: u16,
     pub flag_mask: u8,
-    // TODO(EEPROM): add constant flag
     pub inverse_flag: bool,
+    pub constant: bool, // the flag stays set and the interrupt pending when it is serviced
 }
 
 pub fn avr_interrupt(cpu: &mut CPU, addr: u8) {
     let sp = cpu.get_data_u16(93);
     cpu.set_data(sp, (cpu.pc & 0xff) as u8);
-    cpu.set_data(sp - 1, ((cpu.pc >> 8) & 0xff) as u8);
+    cpu.set_data(sp.wrapping_sub(1), ((cpu.pc >> 8) & 0xff) as u8);
     if cpu.pc_22_bits {
-        cpu.set_data(sp - 2, ((cpu.pc >> 16) & 0xff) as u8);
+        cpu.set_data(sp.wrapping_sub(2), ((cpu.pc >> 16) & 0xff) as u8);
     }
-    cpu.set_data_u16(93, sp - (if cpu.pc_22_bits { 3 } else { 2 }));
+    cpu.set_data_u16(93, sp.wrapping_sub(if cpu.pc_22_bits { 3 } else { 2 }));
+    cpu.check_stack();
     cpu.data[95] &= 0x7f; // clear global interrupt flag
     cpu.cycles += 2;
     cpu.pc = addr as u32;
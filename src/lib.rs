@@ -5,11 +5,17 @@ pub mod atmega328p;
 pub mod clock;
 pub mod cpu;
 pub mod encoder;
+pub mod error;
+pub mod fuses;
 pub mod instruction;
 pub mod interrupt;
 pub mod peripheral;
 pub mod program;
+pub mod reset;
 pub mod runner;
+pub mod scheduler;
+pub mod serial;
+pub mod sleep;
 pub mod stepper;
 pub mod util;
 
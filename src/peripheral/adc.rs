@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+
+use crate::{
+    Float,
+    atmega328p::{ATMega328P, PeripheralMemoryReadHook, PeripheralMemoryWriteHook},
+    clock::AVRClockEventType,
+    interrupt::AVRInterruptConfig,
+    peripheral::timer::{TIMER_0_CONFIG, TIMER_1_CONFIG},
+};
+
+// ADMUX bits
+pub const ADMUX_REFS1: u8 = 0x80; // Reference Selection Bit 1
+pub const ADMUX_REFS0: u8 = 0x40; // Reference Selection Bit 0
+pub const ADMUX_ADLAR: u8 = 0x20; // ADC Left Adjust Result
+const ADMUX_MUX_MASK: u8 = 0xf; // Analog Channel Selection Bits
+
+// ADCSRA bits
+pub const ADCSRA_ADEN: u8 = 0x80; // ADC Enable
+pub const ADCSRA_ADSC: u8 = 0x40; // ADC Start Conversion
+pub const ADCSRA_ADATE: u8 = 0x20; // ADC Auto Trigger Enable
+pub const ADCSRA_ADIF: u8 = 0x10; // ADC Interrupt Flag
+pub const ADCSRA_ADIE: u8 = 0x8; // ADC Interrupt Enable
+const ADCSRA_ADPS_MASK: u8 = 0x7; // ADC Prescaler Select Bits
+
+// ADCSRB bits
+const ADCSRB_ADTS_MASK: u8 = 0x7; // ADC Auto Trigger Source
+
+// Analog channels, besides ADC0-7
+pub const CHANNEL_TEMPERATURE: u8 = 8;
+pub const CHANNEL_BANDGAP: u8 = 14; // internal 1.1V reference
+pub const CHANNEL_GND: u8 = 15;
+
+const BANDGAP_VOLTAGE: Float = 1.1;
+const FIRST_CONVERSION_CLOCKS: u64 = 25; // the first conversion also initializes the analog circuitry
+const CONVERSION_CLOCKS: u64 = 13;
+
+#[derive(Clone)]
+#[allow(non_snake_case)]
+pub struct ADCConfig {
+    pub adc_interrupt: u8, // interrupt hander address on conversion complete
+
+    pub ADCL: u8,   // data register (low byte) address
+    pub ADCH: u8,   // data register (high byte) address
+    pub ADCSRA: u8, // control and status register A address
+    pub ADCSRB: u8, // control and status register B address
+    pub ADMUX: u8,  // multiplexer selection register address
+
+    // Interrupt flags whose rising edge triggers a conversion, as (flag register, flag mask),
+    // indexed by ADTS. None for free running mode.
+    pub auto_trigger_sources: [Option<(u16, u8)>; 8],
+}
+
+pub const ADC_CONFIG: ADCConfig = ADCConfig {
+    adc_interrupt: 0x2a,
+    ADCL: 0x78,
+    ADCH: 0x79,
+    ADCSRA: 0x7a,
+    ADCSRB: 0x7b,
+    ADMUX: 0x7c,
+    auto_trigger_sources: [
+        None,                                                    // free running mode
+        Some((0x50, 0x10)),                                      // analog comparator (ACI)
+        Some((0x3c, 0x01)),                                      // external interrupt 0 (INTF0)
+        Some((TIMER_0_CONFIG.TIFR as u16, TIMER_0_CONFIG.OCFA)), // timer 0 compare match A
+        Some((TIMER_0_CONFIG.TIFR as u16, TIMER_0_CONFIG.TOV)),  // timer 0 overflow
+        Some((TIMER_1_CONFIG.TIFR as u16, TIMER_1_CONFIG.OCFB)), // timer 1 compare match B
+        Some((TIMER_1_CONFIG.TIFR as u16, TIMER_1_CONFIG.TOV)),  // timer 1 overflow
+        Some((TIMER_1_CONFIG.TIFR as u16, TIMER_1_CONFIG.ICF)),  // timer 1 capture event
+    ],
+};
+
+/// Analog to Digital Converter
+pub struct AVRADC {
+    pub config: ADCConfig,
+
+    adc: AVRInterruptConfig,
+
+    pub voltages: [Float; 8], // voltages of the ADC0-7 pins
+    pub avcc: Float,          // analog supply voltage, used as reference with REFS = 01
+    pub aref: Float,          // voltage of the AREF pin, used as reference with REFS = 00
+    pub temperature: Float,   // in Celsius, measured by the internal temperature sensor
+
+    pub converting: bool,
+    auto_trigger: Option<(u16, u8)>, // interrupt flag whose rising edge triggers a conversion
+    first_conversion: bool,          // the next conversion is the first since the ADC was enabled
+    admux: u8,                       // ADMUX when the conversion in progress started
+    locked: bool, // ADCL was read, ADCH is not yet: the result registers are not updated
+}
+
+impl AVRADC {
+    pub fn new(config: ADCConfig) -> Self {
+        let adc = AVRInterruptConfig {
+            address: config.adc_interrupt,
+            flag_register: config.ADCSRA as u16,
+            flag_mask: ADCSRA_ADIF,
+            enable_register: config.ADCSRA as u16,
+            enable_mask: ADCSRA_ADIE,
+            inverse_flag: false,
+            constant: false,
+        };
+        Self {
+            config,
+            adc,
+            voltages: [0.; 8],
+            avcc: 5.,
+            aref: 5.,
+            temperature: 25.,
+            converting: false,
+            auto_trigger: None,
+            first_conversion: true,
+            admux: 0,
+            locked: false,
+        }
+    }
+
+    /// Puts the ADC back in its reset state, keeping the analog voltages and temperature
+    pub fn reset(&mut self) {
+        *self = Self {
+            voltages: self.voltages,
+            avcc: self.avcc,
+            aref: self.aref,
+            temperature: self.temperature,
+            ..Self::new(self.config.clone())
+        };
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_ADCSRA_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.ADCSRA as u16,
+            Box::new(|atmega, value, old_value, addr, _| {
+                let adc = atmega.adc.adc;
+                atmega.cpu.clear_interrupt_by_flag(&adc, value);
+                // ADIF is cleared by writing a one, and ADSC can only be cleared by the hardware
+                let adsc = old_value & ADCSRA_ADSC;
+                let adif = atmega.cpu.data[addr as usize] & ADCSRA_ADIF;
+                atmega
+                    .cpu
+                    .set_data(addr, (value & !ADCSRA_ADIF) | adsc | adif);
+                atmega.cpu.update_interrupt_enable(adc, value);
+
+                if value & ADCSRA_ADEN == 0 {
+                    // disabling the ADC aborts the conversion in progress
+                    atmega.cpu.clear_clock_event(AVRClockEventType::ADC);
+                    atmega.adc.converting = false;
+                    atmega.adc.first_conversion = true;
+                    atmega.cpu.data[addr as usize] &= !ADCSRA_ADSC;
+                } else if value & ADCSRA_ADSC != 0 {
+                    atmega.adc_start_conversion();
+                }
+                atmega.adc_update_auto_trigger();
+                true
+            }),
+        );
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_ADCSRB_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.ADCSRB as u16,
+            Box::new(|atmega, value, _, addr, _| {
+                atmega.cpu.set_data(addr, value);
+                atmega.adc_update_auto_trigger();
+                true
+            }),
+        );
+    }
+
+    /// Reading ADCL locks the result registers until ADCH is read
+    #[allow(non_snake_case)]
+    pub fn add_ADC_read_hooks(&self, read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>) {
+        read_hooks.insert(
+            self.config.ADCL as u16,
+            Box::new(|atmega, addr| {
+                atmega.adc.locked = true;
+                atmega.cpu.get_data(addr)
+            }),
+        );
+        read_hooks.insert(
+            self.config.ADCH as u16,
+            Box::new(|atmega, addr| {
+                atmega.adc.locked = false;
+                atmega.cpu.get_data(addr)
+            }),
+        );
+    }
+
+    /// Ratio of the CPU clock to the ADC clock
+    pub fn prescaler(&self, data: &[u8]) -> u64 {
+        match data[self.config.ADCSRA as usize] & ADCSRA_ADPS_MASK {
+            0 | 1 => 2,
+            adps => 1 << adps,
+        }
+    }
+
+    /// Voltage of the conversion reference selected by REFS
+    pub fn reference_voltage(&self, admux: u8) -> Float {
+        match admux & (ADMUX_REFS1 | ADMUX_REFS0) {
+            0 => self.aref,
+            ADMUX_REFS0 => self.avcc,
+            _ => BANDGAP_VOLTAGE, // REFS = 10 is reserved
+        }
+    }
+
+    /// Voltage of the channel selected by MUX
+    pub fn channel_voltage(&self, admux: u8) -> Float {
+        match admux & ADMUX_MUX_MASK {
+            channel @ 0..=7 => self.voltages[channel as usize],
+            // 314mV at 25°C, rising by about 1mV/°C
+            CHANNEL_TEMPERATURE => 0.314 + (self.temperature - 25.) * 0.001,
+            CHANNEL_BANDGAP => BANDGAP_VOLTAGE,
+            _ => 0., // GND and the reserved channels
+        }
+    }
+
+    /// 10-bit result of converting the channel selected by ADMUX
+    pub fn convert(&self, admux: u8) -> u16 {
+        let ratio = self.channel_voltage(admux) / self.reference_voltage(admux);
+        (ratio * 1024.).clamp(0., 1023.) as u16
+    }
+}
+
+impl ATMega328P {
+    /// Sets the voltage of an ADC0-7 pin, e.g. from a potentiometer
+    pub fn set_analog_voltage(&mut self, channel: usize, volts: Float) {
+        self.adc.voltages[channel] = volts;
+    }
+
+    /// Starts converting the selected channel, which completes after 13 ADC clock cycles (25 for
+    /// the first conversion). Changes to ADMUX take effect for the next conversion.
+    pub fn adc_start_conversion(&mut self) {
+        if self.adc.converting {
+            return;
+        }
+        let adcsra = self.adc.config.ADCSRA as usize;
+        self.cpu.data[adcsra] |= ADCSRA_ADSC;
+        self.adc.converting = true;
+        self.adc.admux = self.cpu.data[self.adc.config.ADMUX as usize];
+        let clocks = if self.adc.first_conversion {
+            FIRST_CONVERSION_CLOCKS
+        } else {
+            CONVERSION_CLOCKS
+        };
+        self.adc.first_conversion = false;
+        self.cpu.add_clock_event(
+            Box::new(|atmega: &mut ATMega328P, _, _, _| atmega.adc_complete_conversion()),
+            clocks * self.adc.prescaler(&self.cpu.data),
+            AVRClockEventType::ADC,
+        );
+    }
+
+    fn adc_complete_conversion(&mut self) {
+        self.adc.converting = false;
+        let config = &self.adc.config;
+        if !self.adc.locked {
+            let sample = self.adc.convert(self.adc.admux);
+            let value = if self.adc.admux & ADMUX_ADLAR != 0 {
+                sample << 6
+            } else {
+                sample
+            };
+            self.cpu.data[config.ADCL as usize] = (value & 0xff) as u8;
+            self.cpu.data[config.ADCH as usize] = (value >> 8) as u8;
+        }
+        let adcsra = self.cpu.data[config.ADCSRA as usize];
+        let free_running = adcsra & ADCSRA_ADATE != 0 && self.adc_trigger_source().is_none();
+        if !free_running {
+            self.cpu.data[config.ADCSRA as usize] &= !ADCSRA_ADSC;
+        }
+        self.cpu.set_interrupt_flag(self.adc.adc);
+        if free_running {
+            self.adc_start_conversion();
+        }
+    }
+
+    fn adc_trigger_source(&self) -> Option<(u16, u8)> {
+        let adts = self.cpu.data[self.adc.config.ADCSRB as usize] & ADCSRB_ADTS_MASK;
+        self.adc.config.auto_trigger_sources[adts as usize]
+    }
+
+    /// Watches the interrupt flag selected as auto trigger source
+    fn adc_update_auto_trigger(&mut self) {
+        let adcsra = self.cpu.data[self.adc.config.ADCSRA as usize];
+        self.adc.auto_trigger =
+            if adcsra & (ADCSRA_ADEN | ADCSRA_ADATE) == ADCSRA_ADEN | ADCSRA_ADATE {
+                self.adc_trigger_source()
+            } else {
+                None
+            };
+    }
+
+    /// Whether the interrupt flag selected as auto trigger source is set
+    pub(crate) fn adc_trigger_flag(&self) -> bool {
+        self.adc
+            .auto_trigger
+            .is_some_and(|(register, mask)| self.cpu.data[register as usize] & mask != 0)
+    }
+
+    /// Called by the peripherals whose interrupt flags are auto trigger sources, after updating
+    /// their flags, with the state of the trigger flag before: a rising edge starts a conversion
+    pub(crate) fn adc_notify_trigger(&mut self, was_set: bool) {
+        if !was_set && self.adc_trigger_flag() {
+            self.adc_start_conversion();
+        }
+    }
+}
+
+#[cfg(test)]
+mod adc_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::{
+            adc::{
+                ADC_CONFIG, ADCSRA_ADATE, ADCSRA_ADEN, ADCSRA_ADIE, ADCSRA_ADIF, ADCSRA_ADSC,
+                ADMUX_ADLAR, ADMUX_REFS0, ADMUX_REFS1, CHANNEL_BANDGAP, CHANNEL_TEMPERATURE,
+            },
+            port::PinInput,
+            timer::{CS00, TIMER_0_CONFIG},
+        },
+    };
+
+    const ADPS_128: u8 = 0x7;
+
+    fn adcsra(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[ADC_CONFIG.ADCSRA as usize]
+    }
+
+    /// Reads the result the way analogRead() does, low byte first
+    fn read_result(atmega: &mut ATMega328P) -> u16 {
+        let lo = atmega.read_data(ADC_CONFIG.ADCL as u16) as u16;
+        let hi = atmega.read_data(ADC_CONFIG.ADCH as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    #[test]
+    fn first_conversion_takes_25_adc_clocks() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_analog_voltage(2, 2.5);
+        atmega.write_data(ADC_CONFIG.ADMUX as u16, ADMUX_REFS0 | 2); // AVCC reference, ADC2
+
+        // Act & Assert
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADPS_128,
+        );
+        atmega.cpu.cycles = 25 * 128 - 1;
+        atmega.tick(None);
+        assert_eq!(adcsra(&atmega) & ADCSRA_ADSC, ADCSRA_ADSC);
+
+        atmega.cpu.cycles = 25 * 128;
+        atmega.tick(None);
+        assert_eq!(adcsra(&atmega) & (ADCSRA_ADSC | ADCSRA_ADIF), ADCSRA_ADIF);
+        assert_eq!(read_result(&mut atmega), 512);
+    }
+
+    #[test]
+    fn next_conversions_take_13_adc_clocks() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_analog_voltage(0, 5.);
+        atmega.write_data(ADC_CONFIG.ADMUX as u16, ADMUX_REFS0);
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADPS_128,
+        );
+        atmega.cpu.cycles = 25 * 128;
+        atmega.tick(None);
+
+        // Act
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADCSRA_ADIF | ADPS_128,
+        );
+        atmega.cpu.cycles += 13 * 128;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(adcsra(&atmega) & (ADCSRA_ADSC | ADCSRA_ADIF), ADCSRA_ADIF);
+        assert_eq!(read_result(&mut atmega), 1023); // clamped
+    }
+
+    /// The internal 1.1V reference, with the result left adjusted
+    #[test]
+    fn internal_reference_left_adjusted() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_analog_voltage(1, 0.55);
+        atmega.write_data(
+            ADC_CONFIG.ADMUX as u16,
+            ADMUX_REFS1 | ADMUX_REFS0 | ADMUX_ADLAR | 1,
+        );
+
+        // Act
+        atmega.write_data(ADC_CONFIG.ADCSRA as u16, ADCSRA_ADEN | ADCSRA_ADSC);
+        atmega.cpu.cycles = 25 * 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.read_data(ADC_CONFIG.ADCH as u16), 0x80); // 512 >> 2
+    }
+
+    #[test]
+    fn bandgap_and_temperature_channels() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.adc.temperature = 85.;
+
+        // Assert
+        assert_eq!(atmega.adc.convert(ADMUX_REFS0 | CHANNEL_BANDGAP), 225); // 1.1V / 5V * 1024
+        assert_eq!(
+            atmega
+                .adc
+                .convert(ADMUX_REFS1 | ADMUX_REFS0 | CHANNEL_TEMPERATURE),
+            348 // 374mV / 1.1V * 1024
+        );
+    }
+
+    #[test]
+    fn conversion_complete_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADCSRA_ADIE,
+        );
+        atmega.cpu.cycles = 25 * 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, ADC_CONFIG.adc_interrupt as u32);
+        assert_eq!(adcsra(&atmega) & ADCSRA_ADIF, 0); // cleared by the interrupt
+    }
+
+    /// In free running mode, a conversion starts as soon as the previous one completes
+    #[test]
+    fn free_running_mode() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(ADC_CONFIG.ADMUX as u16, ADMUX_REFS0);
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADCSRA_ADATE,
+        );
+        atmega.cpu.cycles = 25 * 2;
+        atmega.tick(None);
+        assert_eq!(read_result(&mut atmega), 0);
+        assert_eq!(adcsra(&atmega) & ADCSRA_ADSC, ADCSRA_ADSC);
+
+        // Act
+        atmega.set_analog_voltage(0, 1.25);
+        atmega.cpu.cycles += 13 * 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(read_result(&mut atmega), 256);
+    }
+
+    /// The result registers are not updated between reading ADCL and ADCH
+    #[test]
+    fn result_locked_by_reading_adcl() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(ADC_CONFIG.ADMUX as u16, ADMUX_REFS0);
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADCSRA_ADATE,
+        );
+        atmega.cpu.cycles = 25 * 2;
+        atmega.tick(None);
+
+        // Act
+        atmega.read_data(ADC_CONFIG.ADCL as u16);
+        atmega.set_analog_voltage(0, 5.);
+        atmega.cpu.cycles += 13 * 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.read_data(ADC_CONFIG.ADCH as u16), 0);
+    }
+
+    /// A timer 0 overflow triggers a conversion
+    #[test]
+    fn auto_trigger_on_timer0_overflow() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(ADC_CONFIG.ADCSRB as u16, 4); // timer 0 overflow
+        atmega.write_data(ADC_CONFIG.ADCSRA as u16, ADCSRA_ADEN | ADCSRA_ADATE);
+        atmega.write_data(TIMER_0_CONFIG.TCNT as u16, 0xff);
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        assert!(!atmega.adc.converting);
+
+        // Act
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert!(atmega.adc.converting);
+        assert_eq!(adcsra(&atmega) & ADCSRA_ADSC, ADCSRA_ADSC);
+    }
+
+    /// The rising edge of INTF0 triggers a conversion, a flag that stays set does not
+    #[test]
+    fn auto_trigger_on_int0() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(0x69, 0x01); // EICRA: INT0 on any change
+        atmega.write_data(ADC_CONFIG.ADCSRB as u16, 2); // external interrupt 0
+        atmega.write_data(ADC_CONFIG.ADCSRA as u16, ADCSRA_ADEN | ADCSRA_ADATE);
+
+        // Act & Assert
+        atmega.set_pin_input("D", 2, PinInput::High);
+        assert!(atmega.adc.converting);
+
+        atmega.cpu.cycles = 25 * 2;
+        atmega.tick(None);
+        assert!(!atmega.adc.converting);
+        atmega.set_pin_input("D", 2, PinInput::Low); // INTF0 is still set
+        assert!(!atmega.adc.converting);
+    }
+}
@@ -1,18 +1,21 @@
-use std::{collections::HashMap, mem};
-
-use crate::{
-    atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
-    interrupt::AVRInterruptConfig,
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
 };
 
-const EERE: u8 = 1 << 0;
+use crate::{atmega328p::PeripheralMemoryWriteHook, flog, interrupt::AVRInterruptConfig, program};
+
+const EERE: u8 = 1 << 0; // Read Enable
 const EEPE: u8 = 1 << 1; // Write Enable
 const EEMPE: u8 = 1 << 2; // Master Write Enable
-const EERIE: u8 = 1 << 3;
+const EERIE: u8 = 1 << 3; // Ready Interrupt Enable
 const EEPM0: u8 = 1 << 4;
 const EEPM1: u8 = 1 << 5;
-const EECR_WRITE_MASK: u8 = EEPE | EEMPE | EERIE | EEPM0 | EEPM1;
+const EECR_WRITE_MASK: u8 = EEMPE | EERIE | EEPM0 | EEPM1; // EEPE is set while writing
 
+#[derive(Clone)]
 pub struct AVREEPROMConfig {
     eepromReadyInterrupt: u8,
 
@@ -22,9 +25,9 @@ pub struct AVREEPROMConfig {
     EEARH: u8,
 
     /** The amount of clock cycles erase takes */
-    erase_cycles: u32,
+    erase_cycles: u64,
     /** The amount of clock cycles a write takes */
-    write_cycles: u32,
+    write_cycles: u64,
 }
 
 pub const EEPROM_CONFIG: AVREEPROMConfig = AVREEPROMConfig {
@@ -41,10 +44,11 @@ pub struct AVREEPROM {
     pub config: AVREEPROMConfig,
     eer: AVRInterruptConfig,
 
-    write_enabled_cycles: u32,
-    write_complete_cycles: u32,
+    write_enabled_cycles: u64,
+    write_complete_cycles: u64,
 
     pub memory: Vec<u8>,
+    backing_file: Option<PathBuf>, // image file that writes are flushed to
 }
 
 impl AVREEPROM {
@@ -55,8 +59,9 @@ impl AVREEPROM {
             flag_mask: EEPE,
             enable_register: config.EECR as u16,
             enable_mask: EERIE,
-            // TODO: add constant and inverse flag
+            // The ready interrupt is pending for as long as EEPE is clear
             inverse_flag: true,
+            constant: true,
         };
         Self {
             config,
@@ -64,6 +69,89 @@ impl AVREEPROM {
             write_enabled_cycles: 0,
             write_complete_cycles: 0,
             memory: vec![0xff; memory_size],
+            backing_file: None,
+        }
+    }
+
+    /// Puts the EEPROM back in its reset state, keeping its contents and backing file. A write in
+    /// progress is completed.
+    pub fn reset(&mut self) {
+        self.write_enabled_cycles = 0;
+        self.write_complete_cycles = 0;
+    }
+
+    /// Creates an EEPROM with the contents of an image file, see `load_file`
+    pub fn from_file(
+        config: AVREEPROMConfig,
+        memory_size: usize,
+        path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let mut eeprom = Self::new(config, memory_size);
+        eeprom.load_file(path)?;
+        Ok(eeprom)
+    }
+
+    /// Replaces the contents with an image, the bytes past its end are erased (0xff)
+    pub fn load(&mut self, image: &[u8]) -> io::Result<()> {
+        if image.len() > self.memory.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "EEPROM image of {} bytes is larger than the {} bytes EEPROM",
+                    image.len(),
+                    self.memory.len()
+                ),
+            ));
+        }
+        self.memory.fill(0xff);
+        self.memory[..image.len()].copy_from_slice(image);
+        Ok(())
+    }
+
+    /// The current contents
+    pub fn dump(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Loads the contents from an Intel HEX (.eep or .hex) or raw binary image file
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let image = if is_hex_file(path) {
+            parse_hex(&fs::read_to_string(path)?)?
+        } else {
+            fs::read(path)?
+        };
+        self.load(&image)
+    }
+
+    /// Saves the contents to an image file, in Intel HEX if it ends in .eep or .hex
+    pub fn save_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if is_hex_file(path) {
+            fs::write(path, format_hex(&self.memory))
+        } else {
+            fs::write(path, &self.memory)
+        }
+    }
+
+    /// Saves the contents to an image file after every write, so that they survive between
+    /// simulation runs. Loads the file first if it exists.
+    pub fn persist_to(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            self.load_file(path)?;
+        } else {
+            self.save_file(path)?;
+        }
+        self.backing_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn flush(&self) {
+        if let Some(path) = &self.backing_file
+            && let Err(err) = self.save_file(path)
+        {
+            flog!("failed to save EEPROM to {}: {}", path.display(), err);
         }
     }
 
@@ -72,18 +160,16 @@ impl AVREEPROM {
             self.config.EECR as u16,
             Box::new(|atmega, value, _, _, _| {
                 let config = &atmega.eeprom.config;
-                let addr = ((atmega.cpu.data[config.EEARH as usize] as u32) << 8)
-                    | (atmega.cpu.data[config.EEARL as usize] as u32);
+                // The unused high bits of EEAR are ignored
+                let addr = (((atmega.cpu.data[config.EEARH as usize] as u32) << 8)
+                    | (atmega.cpu.data[config.EEARL as usize] as u32))
+                    & (atmega.eeprom.memory.len() as u32 - 1);
 
                 atmega.cpu.data[config.EECR as usize] = (atmega.cpu.data[config.EECR as usize]
                     & !EECR_WRITE_MASK)
                     | (value & EECR_WRITE_MASK);
                 atmega.cpu.update_interrupt_enable(atmega.eeprom.eer, value);
 
-                if value & EERE != 0 {
-                    atmega.cpu.clear_interrupt(&atmega.eeprom.eer, true);
-                }
-
                 if value & EEMPE != 0 {
                     let eempe_cycles = 4;
                     atmega.eeprom.write_enabled_cycles = atmega.cpu.cycles + eempe_cycles;
@@ -92,11 +178,24 @@ impl AVREEPROM {
                             atmega.cpu.data[atmega.eeprom.config.EECR as usize] &= !EEMPE;
                         }),
                         eempe_cycles,
-                        crate::clock::AVRClockEventType::EEPROMFinish,
+                        crate::clock::AVRClockEventType::EEPROMWriteEnableTimeout,
                     );
                 }
 
-                // TODO: implement Read
+                // Read
+                if value & EERE != 0 {
+                    // The EEPROM can not be read while a write is in progress
+                    if atmega.cpu.cycles < atmega.eeprom.write_complete_cycles {
+                        return true;
+                    }
+
+                    atmega.cpu.data[config.EEDR as usize] = atmega.eeprom.memory[addr as usize];
+
+                    // When the EEPROM is read, the CPU is halted for four cycles before the
+                    // next instruction is executed.
+                    atmega.cpu.cycles += 4;
+                    return true;
+                }
 
                 // Write
                 if value & EEPE != 0 {
@@ -126,8 +225,11 @@ impl AVREEPROM {
                         atmega.eeprom.write_complete_cycles += atmega.eeprom.config.write_cycles;
                     }
 
+                    atmega.eeprom.flush();
+
                     let config = &atmega.eeprom.config;
                     atmega.cpu.data[config.EECR as usize] |= EEPE;
+                    atmega.cpu.clear_interrupt(&atmega.eeprom.eer, false);
 
                     atmega.cpu.add_clock_event(
                         Box::new(|atmega, _, _, _| {
@@ -156,13 +258,58 @@ impl AVREEPROM {
     }
 }
 
+fn is_hex_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("eep" | "hex")
+    )
+}
+
+/// Parses an Intel HEX image, as written by avr-objcopy
+fn parse_hex(source: &str) -> io::Result<Vec<u8>> {
+    let mut image = vec![];
+    program::parse_hex(source, |_, addr, data| {
+        if image.len() < addr + data.len() {
+            image.resize(addr + data.len(), 0xff);
+        }
+        image[addr..addr + data.len()].copy_from_slice(data);
+        Ok(())
+    })
+    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+    Ok(image)
+}
+
+/// Formats an image as Intel HEX, with 16 bytes per data record
+fn format_hex(image: &[u8]) -> String {
+    let mut source = String::new();
+    for (i, data) in image.chunks(16).enumerate() {
+        let addr = i * 16;
+        let mut record = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, 0x00];
+        record.extend_from_slice(data);
+        let checksum = record
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        record.push(checksum);
+        source.push(':');
+        for byte in record {
+            source.push_str(&format!("{:02X}", byte));
+        }
+        source.push('\n');
+    }
+    source.push_str(":00000001FF\n");
+    source
+}
+
 #[cfg(test)]
 mod eeprom_tests {
-    use plotters::data;
+    use std::io::ErrorKind;
 
     use crate::{
         atmega328p::{ATMega328P, DEFAULT_FREQ},
-        peripheral::eeprom::{EEMPE, EEPE, EEPROM_CONFIG},
+        peripheral::eeprom::{
+            AVREEPROM, EEMPE, EEPE, EEPM0, EEPM1, EEPROM_CONFIG, EERE, EERIE, format_hex, parse_hex,
+        },
     };
 
     #[test]
@@ -258,4 +405,293 @@ mod eeprom_tests {
         assert_eq!(atmega.cpu.cycles, 10000000 + 2 + 2);
         assert_eq!(atmega.eeprom.memory[addr as usize], data2);
     }
+
+    #[test]
+    fn write_completes_across_u32_cycles() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let start = u32::MAX as u64 - 100;
+        atmega.cpu.cycles = start;
+        let addr = 15;
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EEDR as u16, 0x55);
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, addr);
+        atmega.write_data(EEPROM_CONFIG.EEARH as u16, 0);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEMPE);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEPE);
+        atmega.cpu.cycles += EEPROM_CONFIG.erase_cycles + EEPROM_CONFIG.write_cycles;
+        atmega.tick(None); // EEMPE timeout
+        atmega.tick(None); // write complete
+
+        // Assert
+        assert!(atmega.cpu.cycles > u32::MAX as u64);
+        assert_eq!(atmega.eeprom.memory[addr as usize], 0x55);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EECR as usize] & EEPE, 0);
+    }
+
+    fn start_write(atmega: &mut ATMega328P, addr: u8, data: u8, mode: u8) {
+        atmega.write_data(EEPROM_CONFIG.EEDR as u16, data);
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, addr);
+        atmega.write_data(EEPROM_CONFIG.EEARH as u16, 0);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEMPE | mode);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEPE | mode);
+    }
+
+    #[test]
+    fn read_empty() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, 0x10);
+        atmega.write_data(EEPROM_CONFIG.EEARH as u16, 0);
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERE);
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, 4);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EEDR as usize], 0xff);
+    }
+
+    #[test]
+    fn read() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.eeprom.memory[0x250] = 0x42;
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, 0x50);
+        atmega.write_data(EEPROM_CONFIG.EEARH as u16, 0x2);
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERE);
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, 4);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EEDR as usize], 0x42);
+    }
+
+    #[test]
+    fn unused_address_bits_ignored() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.eeprom.memory[0x350] = 0x42;
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, 0x50);
+        atmega.write_data(EEPROM_CONFIG.EEARH as u16, 0xff);
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERE);
+        let read = atmega.cpu.data[EEPROM_CONFIG.EEDR as usize];
+        atmega.write_data(EEPROM_CONFIG.EEDR as u16, 0x55);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEMPE);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEPE);
+
+        // Assert
+        assert_eq!(read, 0x42);
+        assert_eq!(atmega.eeprom.memory[0x350], 0x55);
+    }
+
+    #[test]
+    fn read_blocked_during_write() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.eeprom.memory[9] = 0x42;
+        start_write(&mut atmega, 15, 0x55, 0);
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, 9);
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERE);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, 2);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EEDR as usize], 0x55);
+    }
+
+    #[test]
+    fn write_skipped_without_eempe() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let addr = 15;
+        atmega.write_data(EEPROM_CONFIG.EEDR as u16, 0x55);
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, addr);
+
+        // Act
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EEPE);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, 0);
+        assert_eq!(atmega.eeprom.memory[addr as usize], 0xff);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EECR as usize] & EEPE, 0);
+    }
+
+    #[test]
+    fn write_only_does_not_erase() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let addr = 15;
+        atmega.eeprom.memory[addr as usize] = 0x0f;
+
+        // Act
+        start_write(&mut atmega, addr, 0x55, EEPM1);
+
+        // Assert
+        assert_eq!(atmega.eeprom.memory[addr as usize], 0x05);
+    }
+
+    #[test]
+    fn erase_only_does_not_write() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let addr = 15;
+        atmega.eeprom.memory[addr as usize] = 0x0f;
+
+        // Act
+        start_write(&mut atmega, addr, 0x55, EEPM0);
+
+        // Assert
+        assert_eq!(atmega.eeprom.memory[addr as usize], 0xff);
+    }
+
+    #[test]
+    fn write_skipped_while_in_progress() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        start_write(&mut atmega, 15, 0x55, 0);
+
+        // Act
+        atmega.cpu.cycles += 10;
+        atmega.tick(None); // EEMPE timeout
+        start_write(&mut atmega, 16, 0x66, 0);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, 12);
+        assert_eq!(atmega.eeprom.memory[16], 0xff);
+    }
+
+    #[test]
+    fn ready_interrupt_when_write_completes() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let interrupt = EEPROM_CONFIG.eepromReadyInterrupt as usize;
+        start_write(&mut atmega, 15, 0x55, 0);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERIE);
+        let pending_during_write = atmega.cpu.pending_interrupts[interrupt].is_some();
+
+        // Act
+        atmega.cpu.cycles += EEPROM_CONFIG.erase_cycles + EEPROM_CONFIG.write_cycles;
+        atmega.tick(None); // EEMPE timeout
+        atmega.tick(None); // write complete
+
+        // Assert
+        assert!(!pending_during_write);
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EECR as usize] & EEPE, 0);
+        assert!(atmega.cpu.pending_interrupts[interrupt].is_some());
+    }
+
+    #[test]
+    fn ready_interrupt_stays_pending_after_service() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let interrupt = EEPROM_CONFIG.eepromReadyInterrupt as usize;
+        atmega.cpu.set_data_u16(93, 0x8ff); // SP
+        atmega.cpu.set_sreg(0x80); // I
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERIE);
+
+        // Act
+        atmega.tick(None);
+        let pc_after_service = atmega.cpu.pc;
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, 0);
+
+        // Assert
+        assert_eq!(pc_after_service, interrupt as u32);
+        assert!(atmega.cpu.pending_interrupts[interrupt].is_none());
+    }
+
+    #[test]
+    fn load_and_dump() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.eeprom.memory[10] = 0;
+
+        // Act
+        atmega.eeprom.load(&[1, 2, 3]).unwrap();
+        atmega.write_data(EEPROM_CONFIG.EEARL as u16, 2);
+        atmega.write_data(EEPROM_CONFIG.EECR as u16, EERE);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[EEPROM_CONFIG.EEDR as usize], 3);
+        assert_eq!(&atmega.eeprom.dump()[..4], &[1, 2, 3, 0xff]);
+        assert_eq!(atmega.eeprom.dump()[10], 0xff);
+    }
+
+    #[test]
+    fn load_oversized_image() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let image = vec![0; atmega.eeprom.dump().len() + 1];
+        atmega.eeprom.memory[0] = 0x55;
+
+        // Act
+        let result = atmega.eeprom.load(&image);
+
+        // Assert
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(atmega.eeprom.dump()[0], 0x55);
+    }
+
+    #[test]
+    fn parse_eep() {
+        // Arrange
+        let source = ":0400100001020304E2\n:00000001FF\n";
+
+        // Act
+        let image = parse_hex(source).unwrap();
+
+        // Assert
+        assert_eq!(image.len(), 0x14);
+        assert_eq!(image[0x0f], 0xff);
+        assert_eq!(&image[0x10..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_eep_errors() {
+        // Act
+        let length_err = parse_hex(":0100000000FF\n:10000000\n").unwrap_err();
+        let checksum_err = parse_hex(":0400100001020304E3\n").unwrap_err();
+
+        // Assert
+        assert_eq!(length_err.to_string(), "line 2: wrong record length");
+        assert_eq!(checksum_err.to_string(), "line 1: wrong checksum");
+    }
+
+    #[test]
+    fn format_eep_round_trip() {
+        // Arrange
+        let image: Vec<u8> = (0..40).collect();
+
+        // Act
+        let source = format_hex(&image);
+
+        // Assert
+        assert!(source.starts_with(":10000000000102030405060708090A0B0C0D0E0F78\n"));
+        assert_eq!(parse_hex(&source).unwrap(), image);
+    }
+
+    #[test]
+    fn persist_writes_to_file() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("avr8rs-eeprom-{}.eep", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.eeprom.persist_to(&path).unwrap();
+
+        // Act
+        start_write(&mut atmega, 15, 0x55, 0);
+        let eeprom = AVREEPROM::from_file(EEPROM_CONFIG, 1024, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Assert
+        assert_eq!(eeprom.memory[15], 0x55);
+        assert_eq!(eeprom.memory[16], 0xff);
+    }
 }
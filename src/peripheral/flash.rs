@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use crate::{
+    atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
+    clock::AVRClockEventType,
+    fuses::LOCK_BLB,
+    interrupt::AVRInterruptConfig,
+};
+
+// SPMCSR bits
+pub const SPMCSR_SPMIE: u8 = 0x80; // SPM Interrupt Enable
+pub const SPMCSR_RWWSB: u8 = 0x40; // Read-While-Write Section Busy
+pub const SPMCSR_SIGRD: u8 = 0x20; // Signature Row Read
+pub const SPMCSR_RWWSRE: u8 = 0x10; // Read-While-Write Section Read Enable
+pub const SPMCSR_BLBSET: u8 = 0x8; // Boot Lock Bit Set
+pub const SPMCSR_PGWRT: u8 = 0x4; // Page Write
+pub const SPMCSR_PGERS: u8 = 0x2; // Page Erase
+pub const SPMCSR_SPMEN: u8 = 0x1; // Store Program Memory Enable
+
+// Operation bits, cleared when the operation completes
+const SPMCSR_OPERATION_MASK: u8 =
+    SPMCSR_SIGRD | SPMCSR_RWWSRE | SPMCSR_BLBSET | SPMCSR_PGWRT | SPMCSR_PGERS | SPMCSR_SPMEN;
+const SPM_ENABLE_CYCLES: u64 = 4;
+const LPM_ENABLE_CYCLES: u64 = 3; // for reading the fuse and lock bits
+
+pub const PAGE_WORDS: usize = 64;
+const NRWW_START: u32 = 0x3800; // word address of the No-Read-While-Write section
+const PAGE_PROGRAMMING_US: u64 = 4500; // page erase and page write time (tWD_FLASH)
+
+#[derive(Clone)]
+#[allow(non_snake_case)]
+pub struct AVRFlashConfig {
+    pub spm_ready_interrupt: u8,
+
+    pub SPMCSR: u8, // Store Program Memory Control and Status Register address
+}
+
+pub const FLASH_CONFIG: AVRFlashConfig = AVRFlashConfig {
+    spm_ready_interrupt: 0x32,
+    SPMCSR: 0x57,
+};
+
+/// Self-programming of the flash by the SPM instruction, executed from the boot section.
+/// Note: the RWW section can still be read while it is busy, and the lock bits are not enforced.
+pub struct AVRFlash {
+    pub config: AVRFlashConfig,
+    freq_hz: usize,
+
+    spm_ready: AVRInterruptConfig,
+
+    page_buffer: [u16; PAGE_WORDS], // temporary page buffer, filled by SPM
+    spm_enabled_cycles: u64,        // SPM can be executed until this cycle count
+    busy_cycles: u64,               // a page erase or write of the RWW section ends at this count
+}
+
+impl AVRFlash {
+    pub fn new(config: AVRFlashConfig, freq_hz: usize) -> Self {
+        let spm_ready = AVRInterruptConfig {
+            address: config.spm_ready_interrupt,
+            flag_register: config.SPMCSR as u16,
+            flag_mask: SPMCSR_SPMEN,
+            enable_register: config.SPMCSR as u16,
+            enable_mask: SPMCSR_SPMIE,
+            // The ready interrupt is pending for as long as SPMEN is clear
+            inverse_flag: true,
+            constant: true,
+        };
+        Self {
+            config,
+            freq_hz,
+            spm_ready,
+            page_buffer: [0xffff; PAGE_WORDS],
+            spm_enabled_cycles: 0,
+            busy_cycles: 0,
+        }
+    }
+
+    /// Puts the self-programming back in its reset state. An operation in progress is completed,
+    /// and the temporary page buffer is erased.
+    pub fn reset(&mut self) {
+        self.page_buffer = [0xffff; PAGE_WORDS];
+        self.spm_enabled_cycles = 0;
+        self.busy_cycles = 0;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_SPMCSR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.SPMCSR as u16,
+            Box::new(|atmega, value, old_value, addr, _| {
+                let cycles = atmega.cpu.cycles;
+                let spm_ready = atmega.flash.spm_ready;
+                // Only SPMIE can be changed while a page erase or write is in progress
+                if cycles < atmega.flash.busy_cycles {
+                    let new_value = (old_value & !SPMCSR_SPMIE) | (value & SPMCSR_SPMIE);
+                    atmega.cpu.set_data(addr, new_value);
+                    atmega.cpu.update_interrupt_enable(spm_ready, new_value);
+                    return true;
+                }
+
+                let new_value = (value & !SPMCSR_RWWSB) | (old_value & SPMCSR_RWWSB);
+                atmega.cpu.set_data(addr, new_value);
+                atmega.cpu.update_interrupt_enable(spm_ready, new_value);
+
+                atmega
+                    .cpu
+                    .clear_clock_event(AVRClockEventType::SPMEnableTimeout);
+                if value & SPMCSR_SPMEN != 0 {
+                    atmega.flash.spm_enabled_cycles = cycles + SPM_ENABLE_CYCLES;
+                    atmega.cpu.clear_interrupt(&spm_ready, false);
+                    atmega.cpu.add_clock_event(
+                        Box::new(|atmega, _, _, _| atmega.spm_complete()),
+                        SPM_ENABLE_CYCLES,
+                        AVRClockEventType::SPMEnableTimeout,
+                    );
+                } else {
+                    atmega.flash.spm_enabled_cycles = 0;
+                }
+                true
+            }),
+        );
+    }
+
+    /// Number of CPU clock cycles a page erase or write takes
+    pub fn page_programming_cycles(&self) -> u64 {
+        self.freq_hz as u64 * PAGE_PROGRAMMING_US / 1_000_000
+    }
+}
+
+impl ATMega328P {
+    /// Executes the SPM instruction with the operation selected in SPMCSR. It is ignored unless
+    /// executed from the boot section within 4 cycles of setting SPMEN.
+    pub fn spm(&mut self) {
+        let spmcsr = self.cpu.data[self.flash.config.SPMCSR as usize];
+        if spmcsr & SPMCSR_SPMEN == 0
+            || self.cpu.cycles >= self.flash.spm_enabled_cycles
+            || !self.in_boot_section(self.cpu.pc)
+        {
+            return;
+        }
+        self.flash.spm_enabled_cycles = 0;
+        self.cpu
+            .clear_clock_event(AVRClockEventType::SPMEnableTimeout);
+
+        let z = self.cpu.get_data_u16(30) as u32 >> 1; // word address
+        let page = z & !(PAGE_WORDS as u32 - 1);
+        match spmcsr & (SPMCSR_OPERATION_MASK & !SPMCSR_SPMEN) {
+            0 => {
+                // Page buffer fill, from R1:R0
+                self.flash.page_buffer[z as usize % PAGE_WORDS] = self.cpu.get_data_u16(0);
+                self.spm_complete();
+            }
+            SPMCSR_PGERS => {
+                self.program_page(page, |_, _| 0xffff);
+            }
+            SPMCSR_PGWRT => {
+                // Programming can only clear bits, the page has to be erased first
+                let buffer = self.flash.page_buffer;
+                self.program_page(page, |i, word| word & buffer[i]);
+                self.flash.page_buffer = [0xffff; PAGE_WORDS];
+            }
+            SPMCSR_BLBSET => {
+                // Boot lock bits can be programmed from R0, but not erased
+                self.fuses.lock &= self.cpu.data[0] | !LOCK_BLB;
+                self.spm_complete();
+            }
+            SPMCSR_RWWSRE => {
+                let spmcsr = self.flash.config.SPMCSR as usize;
+                self.cpu.data[spmcsr] &= !SPMCSR_RWWSB;
+                self.flash.page_buffer = [0xffff; PAGE_WORDS];
+                self.spm_complete();
+            }
+            _ => self.spm_complete(),
+        }
+    }
+
+    /// Executes the LPM instruction: reads a byte of the program memory, or the fuse and lock
+    /// bits within 3 cycles of setting BLBSET and SPMEN
+    pub fn lpm(&mut self, addr: u32) -> u8 {
+        let spmcsr = self.cpu.data[self.flash.config.SPMCSR as usize];
+        let read_fuses = SPMCSR_BLBSET | SPMCSR_SPMEN;
+        if spmcsr & read_fuses != read_fuses
+            || self.cpu.cycles + SPM_ENABLE_CYCLES - LPM_ENABLE_CYCLES
+                >= self.flash.spm_enabled_cycles
+        {
+            return self.cpu.get_prog_byte(addr);
+        }
+        self.flash.spm_enabled_cycles = 0;
+        self.cpu
+            .clear_clock_event(AVRClockEventType::SPMEnableTimeout);
+        self.spm_complete();
+        self.fuses.read(addr)
+    }
+
+    /// Erases or writes a page through the given function of (word in page, current word).
+    /// The CPU keeps running while the RWW section is programmed, and is halted while the NRWW
+    /// section is.
+    fn program_page(&mut self, page: u32, program: impl Fn(usize, u16) -> u16) {
+        if page as usize + PAGE_WORDS > self.cpu.prog_mem().len() {
+            self.spm_complete();
+            return;
+        }
+        for i in 0..PAGE_WORDS {
+            let addr = page + i as u32;
+            let word = program(i, self.cpu.prog_mem()[addr as usize]);
+            self.cpu.set_prog_word(addr, word);
+        }
+
+        let cycles = self.flash.page_programming_cycles();
+        if page >= NRWW_START {
+            self.cpu.cycles += cycles;
+            self.spm_complete();
+            return;
+        }
+        self.cpu.data[self.flash.config.SPMCSR as usize] |= SPMCSR_RWWSB;
+        self.flash.busy_cycles = self.cpu.cycles + cycles;
+        self.cpu.add_clock_event(
+            Box::new(|atmega: &mut ATMega328P, _, _, _| atmega.spm_complete()),
+            cycles,
+            AVRClockEventType::SPMComplete,
+        );
+    }
+
+    /// Ends the SPM operation: SPMEN and the operation bits are cleared, which raises the SPM
+    /// ready interrupt
+    fn spm_complete(&mut self) {
+        self.flash.busy_cycles = 0;
+        self.cpu.data[self.flash.config.SPMCSR as usize] &= !SPMCSR_OPERATION_MASK;
+        self.cpu.set_interrupt_flag(self.flash.spm_ready);
+    }
+}
+
+#[cfg(test)]
+mod flash_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        error::SimErrorKind,
+        instruction::instructions::Instruction,
+        peripheral::flash::{
+            FLASH_CONFIG, PAGE_WORDS, SPMCSR_BLBSET, SPMCSR_PGERS, SPMCSR_PGWRT, SPMCSR_RWWSB,
+            SPMCSR_RWWSRE, SPMCSR_SPMEN, SPMCSR_SPMIE,
+        },
+    };
+
+    const SPMCSR: u16 = FLASH_CONFIG.SPMCSR as u16;
+    const SREG: usize = 95;
+    const BOOT_START: u32 = 0x3800; // factory BOOTSZ
+    const SPM: u16 = 0x95e8;
+    const PAGE: u16 = 0x0100; // byte address of an RWW page
+    const PAGE_PROGRAMMING_CYCLES: u64 = 72_000; // 4.5ms at 16MHz
+
+    fn spm_atmega() -> ATMega328P {
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.set_prog_word(BOOT_START, SPM);
+        atmega
+    }
+
+    /// Executes SPM from the boot section with Z and SPMCSR set
+    fn spm(atmega: &mut ATMega328P, z: u16, spmcsr: u8) {
+        atmega.cpu.set_data_u16(30, z);
+        atmega.write_data(SPMCSR, spmcsr);
+        atmega.cpu.pc = BOOT_START;
+        atmega.step(None).unwrap();
+    }
+
+    fn run_until(atmega: &mut ATMega328P, cycles: u64) {
+        while atmega.cpu.cycles < cycles {
+            atmega.cpu.cycles += 1;
+            atmega.tick(None);
+        }
+    }
+
+    fn spmcsr(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[SPMCSR as usize]
+    }
+
+    #[test]
+    fn page_erase_fill_and_write() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        spm(&mut atmega, PAGE, SPMCSR_PGERS | SPMCSR_SPMEN);
+        let cycles = atmega.cpu.cycles;
+        run_until(&mut atmega, cycles + PAGE_PROGRAMMING_CYCLES);
+        for (i, word) in [0x0000, 0x9598].iter().enumerate() {
+            atmega.cpu.set_data_u16(0, *word); // R1:R0
+            spm(&mut atmega, PAGE + 2 * i as u16, SPMCSR_SPMEN);
+        }
+
+        // Act
+        spm(&mut atmega, PAGE, SPMCSR_PGWRT | SPMCSR_SPMEN);
+
+        // Assert
+        let page = PAGE as usize / 2;
+        assert_eq!(
+            atmega.cpu.prog_mem()[page..page + 3],
+            [0x0000, 0x9598, 0xffff]
+        );
+        assert_eq!(
+            atmega.cpu.prog_bytes()[2 * page + 2..2 * page + 4],
+            [0x98, 0x95]
+        );
+        assert_eq!(
+            atmega.cpu.decoded()[page + 1].map(|(instruction, _)| instruction),
+            Some(Instruction::BREAK)
+        );
+    }
+
+    #[test]
+    fn page_write_only_clears_bits() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        let page = PAGE as u32 / 2;
+        atmega.cpu.set_prog_word(page, 0x00ff);
+        atmega.cpu.set_data_u16(0, 0x0f0f);
+        spm(&mut atmega, PAGE, SPMCSR_SPMEN);
+
+        // Act
+        spm(&mut atmega, PAGE, SPMCSR_PGWRT | SPMCSR_SPMEN);
+
+        // Assert
+        assert_eq!(atmega.cpu.prog_mem()[page as usize], 0x000f);
+        assert_eq!(atmega.cpu.prog_mem()[page as usize + 1], 0x0000);
+    }
+
+    #[test]
+    fn spm_z_plus_is_illegal() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        atmega.cpu.set_prog_word(BOOT_START + 1, 0x95f8); // SPM Z+, not on the ATmega328P
+        atmega.cpu.pc = BOOT_START + 1;
+
+        // Act
+        let result = atmega.step(None);
+
+        // Assert
+        assert_eq!(
+            result.unwrap_err().kind,
+            SimErrorKind::IllegalOpcode(0x95f8)
+        );
+    }
+
+    #[test]
+    fn rww_section_busy_until_rwwsre() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        let start = atmega.cpu.cycles;
+        spm(&mut atmega, PAGE, SPMCSR_PGERS | SPMCSR_SPMEN);
+        assert_eq!(spmcsr(&atmega), SPMCSR_RWWSB | SPMCSR_PGERS | SPMCSR_SPMEN);
+
+        // Act
+        run_until(&mut atmega, start + PAGE_PROGRAMMING_CYCLES - 1);
+        let busy = spmcsr(&atmega);
+        run_until(&mut atmega, start + PAGE_PROGRAMMING_CYCLES);
+        let complete = spmcsr(&atmega);
+        spm(&mut atmega, 0, SPMCSR_RWWSRE | SPMCSR_SPMEN);
+
+        // Assert
+        assert_eq!(busy & SPMCSR_SPMEN, SPMCSR_SPMEN);
+        assert_eq!(complete, SPMCSR_RWWSB);
+        assert_eq!(spmcsr(&atmega), 0);
+    }
+
+    #[test]
+    fn nrww_section_halts_cpu() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        let cycles = atmega.cpu.cycles;
+
+        // Act
+        spm(&mut atmega, 0x7f00, SPMCSR_PGERS | SPMCSR_SPMEN);
+
+        // Assert
+        assert_eq!(atmega.cpu.cycles, cycles + PAGE_PROGRAMMING_CYCLES + 1);
+        assert_eq!(spmcsr(&atmega), 0);
+        assert_eq!(
+            atmega.cpu.prog_mem()[0x3f80..0x3f80 + PAGE_WORDS],
+            [0xffff; PAGE_WORDS]
+        );
+    }
+
+    #[test]
+    fn spmen_times_out_after_4_cycles() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        atmega.write_data(SPMCSR, SPMCSR_PGERS | SPMCSR_SPMEN);
+
+        // Act
+        let cycles = atmega.cpu.cycles;
+        run_until(&mut atmega, cycles + 4);
+        atmega.cpu.set_data_u16(30, PAGE);
+        atmega.cpu.pc = BOOT_START;
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(spmcsr(&atmega), 0);
+        assert_eq!(atmega.cpu.prog_mem()[PAGE as usize / 2], 0);
+    }
+
+    #[test]
+    fn spm_ignored_outside_boot_section() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        atmega.cpu.set_prog_word(0, SPM);
+        atmega.cpu.set_data_u16(30, PAGE);
+        atmega.write_data(SPMCSR, SPMCSR_PGERS | SPMCSR_SPMEN);
+
+        // Act
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 1);
+        assert_eq!(atmega.cpu.prog_mem()[PAGE as usize / 2], 0);
+    }
+
+    #[test]
+    fn spm_ready_interrupt() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        atmega.cpu.data[SREG] = 0x80; // I
+        spm(
+            &mut atmega,
+            PAGE,
+            SPMCSR_SPMIE | SPMCSR_PGERS | SPMCSR_SPMEN,
+        );
+        let start = atmega.cpu.cycles;
+        assert_eq!(atmega.cpu.pc, BOOT_START + 1); // not pending while SPMEN is set
+
+        // Act
+        run_until(&mut atmega, start + PAGE_PROGRAMMING_CYCLES);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, FLASH_CONFIG.spm_ready_interrupt as u32);
+    }
+
+    #[test]
+    fn blbset_programs_boot_lock_bits() {
+        // Arrange
+        let mut atmega = spm_atmega();
+        atmega.fuses.lock = 0xef; // BLB12 programmed
+        atmega.cpu.data[0] = 0xf0; // BLB02 and BLB01, and the LB bits that SPM can not write
+
+        // Act
+        spm(&mut atmega, 0x0001, SPMCSR_BLBSET | SPMCSR_SPMEN);
+
+        // Assert
+        assert_eq!(atmega.fuses.lock, 0xe3);
+        assert_eq!(spmcsr(&atmega), 0);
+    }
+}
@@ -3,9 +3,9 @@ use std::{collections::HashMap, panic};
 use crate::{
     atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
     clock::AVRClockEventType,
-    cpu::{self, CPU},
+    cpu::CPU,
     interrupt::AVRInterruptConfig,
-    peripheral::i2c::{self, bus::I2CBus},
+    peripheral::i2c::bus::I2CBus,
 };
 
 pub mod bus;
@@ -36,12 +36,14 @@ const STATUS_SLAR_NACK: u8 = 0x48;
 const STATUS_DATA_RECEIVED_ACK: u8 = 0x50;
 const STATUS_DATA_RECEIVED_NACK: u8 = 0x58;
 
+#[derive(Clone)]
 pub struct TWIConfig {
     twi_interrupt: u8,
     pub TWBR: u8,
     pub TWSR: u8,
     pub TWCR: u8,
     pub TWDR: u8,
+    pub TWAR: u8,
 }
 
 pub const TWI_CONFIG: TWIConfig = TWIConfig {
@@ -50,6 +52,7 @@ pub const TWI_CONFIG: TWIConfig = TWIConfig {
     TWSR: 0xb9,
     TWCR: 0xbc,
     TWDR: 0xbb,
+    TWAR: 0xba,
 };
 
 /// I2C communication interface
@@ -72,6 +75,7 @@ impl AVRI2C {
             enable_register: config.TWCR as u16,
             enable_mask: TWCR_TWIE,
             inverse_flag: false,
+            constant: false,
         };
         let mut i2c = Self {
             config,
@@ -84,6 +88,13 @@ impl AVRI2C {
         i2c
     }
 
+    /// Puts the TWI back in its reset state
+    pub fn reset(&mut self, cpu: &mut CPU) {
+        *self = Self::new(self.config.clone(), self.freq_hz, cpu);
+        cpu.data[self.config.TWDR as usize] = 0xff;
+        cpu.data[self.config.TWAR as usize] = 0xfe;
+    }
+
     pub fn add_TWCR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
         write_hooks.insert(
             self.config.TWCR as u16,
@@ -96,7 +107,7 @@ impl AVRI2C {
                     atmega.cpu.add_clock_event(
                         Box::new(ATMega328P::i2c_op),
                         0,
-                        crate::clock::AVRClockEventType::I2C,
+                        crate::clock::AVRClockEventType::TWI,
                     );
                 }
                 true
@@ -167,7 +178,7 @@ impl AVRI2C {
         );
     }
 
-    pub fn status(&self, data: &Vec<u8>) -> u8 {
+    pub fn status(&self, data: &[u8]) -> u8 {
         data[self.config.TWSR as usize] & TWSR_TWS_MASK
     }
 
@@ -179,12 +190,11 @@ impl AVRI2C {
         cpu.set_interrupt_flag(self.twi);
     }
 
-    pub fn scl_frequency(&self, data: &Vec<u8>) -> usize {
-        self.freq_hz
-            / (16 + 2 * data[self.config.TWBR as usize] as usize * self.prescaler(data)) as usize
+    pub fn scl_frequency(&self, data: &[u8]) -> usize {
+        self.freq_hz / (16 + 2 * data[self.config.TWBR as usize] as usize * self.prescaler(data))
     }
 
-    pub fn prescaler(&self, data: &Vec<u8>) -> usize {
+    pub fn prescaler(&self, data: &[u8]) -> usize {
         match data[self.config.TWSR as usize] & TWSR_TWPS_MASK {
             0 => 1,
             1 => 4,
@@ -233,7 +243,7 @@ impl ATMega328P {
                     self.cpu.add_clock_event(
                         Box::new(ATMega328P::i2c_op),
                         0,
-                        AVRClockEventType::I2C,
+                        AVRClockEventType::TWI,
                     ); // check for ack
                 } else {
                     self.i2c.wait_ack = false;
@@ -248,7 +258,7 @@ impl ATMega328P {
         } else if status == STATUS_SLAW_ACK || status == STATUS_DATA_SENT_ACK {
             self.i2c.busy = true;
             if let Some(i2c_bus) = i2c_bus {
-                assert_eq!(i2c_bus.read, false);
+                assert!(!i2c_bus.read);
                 if !self.i2c.wait_ack {
                     i2c_bus.status = bus::I2CBusStatus::DATA_AVAILABLE;
                     i2c_bus.data = twdr_value;
@@ -256,7 +266,7 @@ impl ATMega328P {
                     self.cpu.add_clock_event(
                         Box::new(ATMega328P::i2c_op),
                         0,
-                        AVRClockEventType::I2C,
+                        AVRClockEventType::TWI,
                     ); // check for ack
                 } else {
                     self.i2c.wait_ack = false;
@@ -271,7 +281,7 @@ impl ATMega328P {
         } else if status == STATUS_SLAR_ACK || status == STATUS_DATA_RECEIVED_ACK {
             self.i2c.busy = true;
             if let Some(i2c_bus) = i2c_bus {
-                assert_eq!(i2c_bus.read, true);
+                assert!(i2c_bus.read);
                 self.cpu.data[self.i2c.config.TWDR as usize] = i2c_bus.data; // read data
                 let ack = twcr_value & TWCR_TWEA != 0;
                 i2c_bus.status = bus::I2CBusStatus::DATA_REQUEST;
@@ -287,7 +297,7 @@ impl ATMega328P {
 #[cfg(test)]
 mod i2c_tests {
     use crate::{
-        atmega328p::{self, ATMega328P, DEFAULT_FREQ},
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
         peripheral::i2c::{
             STATUS_DATA_SENT_ACK, STATUS_IDLE, STATUS_REPEATED_START, STATUS_SLAW_ACK,
             STATUS_START, TWCR_TWEN, TWCR_TWIE, TWCR_TWINT, TWCR_TWSTA, TWCR_TWSTO, TWI_CONFIG,
@@ -396,13 +406,13 @@ mod i2c_tests {
         atmega.tick(Some(&mut i2c_bus));
 
         let address = 0x36;
-        atmega.write_data(TWI_CONFIG.TWDR as u16, address << 1 | 0x0); // write
+        atmega.write_data(TWI_CONFIG.TWDR as u16, (address << 1)); // write
         atmega.write_data(TWI_CONFIG.TWCR as u16, TWCR_TWINT | TWCR_TWEN);
         atmega.cpu.cycles += 1;
         atmega.tick(Some(&mut i2c_bus));
 
         assert_eq!(i2c_bus.address, address);
-        assert_eq!(i2c_bus.read, false);
+        assert!(!i2c_bus.read);
 
         i2c_bus.acked = true;
         atmega.cpu.cycles += 1;
@@ -422,7 +432,7 @@ mod i2c_tests {
         atmega.tick(Some(&mut i2c_bus));
 
         let address = 0x36;
-        atmega.write_data(TWI_CONFIG.TWDR as u16, address << 1 | 0x0); // write address
+        atmega.write_data(TWI_CONFIG.TWDR as u16, (address << 1)); // write address
         atmega.write_data(TWI_CONFIG.TWCR as u16, TWCR_TWINT | TWCR_TWEN);
         atmega.cpu.cycles += 1;
         atmega.tick(Some(&mut i2c_bus));
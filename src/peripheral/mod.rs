@@ -1,5 +1,9 @@
+pub mod adc;
 pub mod eeprom;
+pub mod flash;
 pub mod i2c;
 pub mod port;
+pub mod spi;
 pub mod timer;
 pub mod usart;
+pub mod watchdog;
@@ -2,9 +2,26 @@ use std::collections::HashMap;
 
 use crate::{
     atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
+    cpu::CPU,
+    interrupt::AVRInterruptConfig,
     ternary,
 };
 
+// External interrupt registers
+const EICRA: u8 = 0x69; // External Interrupt Control Register A
+const EIMSK: u8 = 0x3d; // External Interrupt Mask Register
+const EIFR: u8 = 0x3c; // External Interrupt Flag Register
+
+// Pin change interrupt registers
+const PCICR: u8 = 0x68; // Pin Change Interrupt Control Register
+const PCIFR: u8 = 0x3b; // Pin Change Interrupt Flag Register
+
+// Interrupt sense control, ISCn1:0 bits of EICRA
+const ISC_LOW_LEVEL: u8 = 0;
+const ISC_ANY_CHANGE: u8 = 1;
+const ISC_FALLING_EDGE: u8 = 2;
+const ISC_RISING_EDGE: u8 = 3;
+
 #[derive(Debug)]
 pub enum PinState {
     Low,
@@ -13,17 +30,106 @@ pub enum PinState {
     InputPullUp,
 }
 
+impl PinState {
+    /// State of a pin from the PORT value and DDR given to GPIO listeners
+    pub fn from_gpio(value: u8, ddr: u8, pin: u8) -> Self {
+        let bit_mask: u8 = 1 << pin;
+        match (ddr & bit_mask != 0, value & bit_mask != 0) {
+            (true, true) => PinState::High,
+            (true, false) => PinState::Low,
+            (false, true) => PinState::InputPullUp,
+            (false, false) => PinState::Input,
+        }
+    }
+}
+
+/// Level driven on a pin from outside the MCU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinInput {
+    Low,
+    High,
+    Floating, // not driven, reads the pull-up if enabled, low otherwise
+}
+
+/// How a peripheral (e.g. a timer compare output) overrides the output value of a pin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinOverrideMode {
+    None, // not overridden, the pin follows PORT
+    Enable,
+    Set,
+    Clear,
+    Toggle,
+}
+
+/// External interrupt (INTn) of a pin
+#[derive(Clone, Copy)]
+pub struct AVRExternalInterrupt {
+    pub index: u8,     // n in INTn: bit in EIMSK and EIFR
+    pub interrupt: u8, // interrupt hander address
+}
+
+impl AVRExternalInterrupt {
+    /// The low level interrupt does not set the flag, and stays pending while the pin is low
+    fn interrupt_config(&self, low_level: bool) -> AVRInterruptConfig {
+        AVRInterruptConfig {
+            address: self.interrupt,
+            flag_register: EIFR as u16,
+            flag_mask: 1 << self.index,
+            enable_register: EIMSK as u16,
+            enable_mask: 1 << self.index,
+            inverse_flag: false,
+            constant: low_level,
+        }
+    }
+
+    fn sense_control(&self, data: &[u8]) -> u8 {
+        (data[EICRA as usize] >> (2 * self.index)) & 0x3
+    }
+}
+
+/// Pin change interrupt (PCINTn) shared by the pins of a port
+#[derive(Clone, Copy)]
+#[allow(non_snake_case)]
+pub struct AVRPinChangeInterrupt {
+    pub index: u8,     // n in PCINTn: bit in PCICR and PCIFR
+    pub interrupt: u8, // interrupt hander address
+    pub PCMSK: u8,     // Pin change mask register address
+}
+
+impl AVRPinChangeInterrupt {
+    fn interrupt_config(&self) -> AVRInterruptConfig {
+        AVRInterruptConfig {
+            address: self.interrupt,
+            flag_register: PCIFR as u16,
+            flag_mask: 1 << self.index,
+            enable_register: PCICR as u16,
+            enable_mask: 1 << self.index,
+            inverse_flag: false,
+            constant: false,
+        }
+    }
+}
+
+/// Called with (port name, new PORT value, DDR, cycle) when the output state of a port changes
+pub type GPIOListener = Box<dyn FnMut(&str, u8, u8, u64)>;
+
+#[derive(Clone)]
 #[allow(non_snake_case)]
 pub struct AVRPortConfig {
-    pub PIN: u8,  // Input register address
-    pub DDR: u8,  // Direction register address
-    pub PORT: u8, // Data register address
+    pub name: &'static str, // "B", "C" or "D"
+    pub PIN: u8,            // Input register address
+    pub DDR: u8,            // Direction register address
+    pub PORT: u8,           // Data register address
+
+    pub pin_change: AVRPinChangeInterrupt,
+    pub external_interrupts: [Option<AVRExternalInterrupt>; 8], // indexed by pin
 }
 
 pub struct AVRIOPort {
     pub config: AVRPortConfig,
 
-    pin_value: u8,
+    pin_value: u8,    // levels driven from outside
+    pin_floating: u8, // pins not driven from outside
 
     override_mask: u8,
     override_value: u8,
@@ -31,6 +137,8 @@ pub struct AVRIOPort {
     last_ddr: u8,
     last_pin: u8,
     pub open_collector: u8,
+
+    listeners: Vec<GPIOListener>,
 }
 
 impl AVRIOPort {
@@ -38,12 +146,14 @@ impl AVRIOPort {
         AVRIOPort {
             config,
             pin_value: 0,
+            pin_floating: 0xff,
             override_mask: 0xff,
             override_value: 0,
             last_value: 0,
             last_ddr: 0,
             last_pin: 0,
             open_collector: 0,
+            listeners: Vec::new(),
         }
     }
 
@@ -58,15 +168,15 @@ impl AVRIOPort {
                 let config = &atmega.ports[port_id].config;
                 let port = config.PORT;
                 let ddr = config.DDR;
-                let pin = config.PIN;
 
                 let port_value = atmega.cpu.data[port as usize];
                 atmega.cpu.data[ddr as usize] = ddr_mask;
+                let adc_trigger = atmega.adc_trigger_flag();
 
                 let port = &mut atmega.ports[port_id];
-                port.write_gpio(port_value, ddr_mask);
-                let new_pin = port.update_pin_register(ddr_mask);
-                atmega.cpu.data[pin as usize] = new_pin;
+                port.write_gpio(port_value, ddr_mask, atmega.cpu.cycles);
+                port.update_pin_register(ddr_mask, &mut atmega.cpu);
+                atmega.port_pins_updated(port_id, adc_trigger);
 
                 true
             }),
@@ -87,32 +197,114 @@ impl AVRIOPort {
 
                 let ddr_mask = atmega.cpu.data[ddr as usize];
                 atmega.cpu.data[port as usize] = port_value;
+                let adc_trigger = atmega.adc_trigger_flag();
 
                 let port = &mut atmega.ports[port_id];
-                port.write_gpio(port_value, ddr_mask);
-                port.update_pin_register(ddr_mask);
+                port.write_gpio(port_value, ddr_mask, atmega.cpu.cycles);
+                port.update_pin_register(ddr_mask, &mut atmega.cpu);
+                atmega.port_pins_updated(port_id, adc_trigger);
                 true
             }),
         );
     }
 
-    pub fn update_pin_register(&mut self, ddr: u8) -> u8 {
-        let new_pin = (self.pin_value & !ddr) | (self.last_value & ddr);
+    /// Puts the port back in its reset state, with all the pins as inputs. The levels driven from
+    /// outside and the listeners are kept.
+    pub fn reset(&mut self, cpu: &mut CPU) {
+        self.override_mask = 0xff;
+        self.override_value = 0;
+        self.write_gpio(0, 0, cpu.cycles);
+        self.update_pin_register(0, cpu);
+    }
+
+    /// Updates the PIN register, and raises the interrupts of the pins that changed
+    pub fn update_pin_register(&mut self, ddr: u8, cpu: &mut CPU) {
+        // Open collector outputs driven high are released, and read like inputs
+        let released = ddr & self.open_collector & self.last_value;
+        let driven = ddr & !released;
+        let pull_up = cpu.data[self.config.PORT as usize] & !driven;
+        let input = (self.pin_value & !self.pin_floating) | (pull_up & self.pin_floating);
+        let new_pin = (input & !driven) | (self.last_value & driven);
+        cpu.data[self.config.PIN as usize] = new_pin;
         if self.last_pin != new_pin {
             for index in 0..8 {
                 if (new_pin & (1 << index)) != (self.last_pin & (1 << index)) {
                     let value = (new_pin & (1 << index)) != 0;
-                    // TODO: implement interrupt and listener
-                    // self.toggleInterrupt(index, value);
-                    // self.externalClockListeners[index]?.(value);
+                    self.toggle_interrupt(index, value, cpu);
                 }
             }
             self.last_pin = new_pin;
         }
-        new_pin
     }
 
-    pub fn write_gpio(&mut self, value: u8, ddr: u8) {
+    /// Sets the level driven on a pin by external code, seen in PIN while the pin is an input
+    pub fn set_pin_input(&mut self, index: u8, level: PinInput, cpu: &mut CPU) {
+        let pin_mask = 1 << index;
+        match level {
+            PinInput::Low => {
+                self.pin_value &= !pin_mask;
+                self.pin_floating &= !pin_mask;
+            }
+            PinInput::High => {
+                self.pin_value |= pin_mask;
+                self.pin_floating &= !pin_mask;
+            }
+            PinInput::Floating => {
+                self.pin_value &= !pin_mask;
+                self.pin_floating |= pin_mask;
+            }
+        }
+        let ddr = cpu.data[self.config.DDR as usize];
+        self.update_pin_register(ddr, cpu);
+    }
+
+    fn toggle_interrupt(&self, pin: u8, high: bool, cpu: &mut CPU) {
+        if let Some(external_interrupt) = self.config.external_interrupts[pin as usize] {
+            match external_interrupt.sense_control(&cpu.data) {
+                ISC_LOW_LEVEL => update_low_level_interrupt(&external_interrupt, high, cpu),
+                ISC_ANY_CHANGE => {
+                    cpu.set_interrupt_flag(external_interrupt.interrupt_config(false))
+                }
+                ISC_FALLING_EDGE if !high => {
+                    cpu.set_interrupt_flag(external_interrupt.interrupt_config(false))
+                }
+                ISC_RISING_EDGE if high => {
+                    cpu.set_interrupt_flag(external_interrupt.interrupt_config(false))
+                }
+                _ => {}
+            }
+        }
+        let pin_change = self.config.pin_change;
+        if cpu.data[pin_change.PCMSK as usize] & (1 << pin) != 0 {
+            cpu.set_interrupt_flag(pin_change.interrupt_config());
+        }
+    }
+
+    /// Overrides the output value of a pin driven by a timer compare output
+    pub fn timer_override_pin(&mut self, pin: u8, mode: PinOverrideMode, cpu: &mut CPU) {
+        let pin_mask = 1 << pin;
+        if mode == PinOverrideMode::None {
+            self.override_mask |= pin_mask;
+            self.override_value &= !pin_mask;
+        } else {
+            self.override_mask &= !pin_mask;
+            match mode {
+                PinOverrideMode::Enable => {
+                    self.override_value &= !pin_mask;
+                    self.override_value |= cpu.data[self.config.PORT as usize] & pin_mask;
+                }
+                PinOverrideMode::Set => self.override_value |= pin_mask,
+                PinOverrideMode::Clear => self.override_value &= !pin_mask,
+                PinOverrideMode::Toggle => self.override_value ^= pin_mask,
+                PinOverrideMode::None => unreachable!(),
+            }
+        }
+        let ddr = cpu.data[self.config.DDR as usize];
+        self.write_gpio(cpu.data[self.config.PORT as usize], ddr, cpu.cycles);
+        self.update_pin_register(ddr, cpu);
+    }
+
+    pub fn write_gpio(&mut self, value: u8, ddr: u8, cycles: u64) {
         let new_value =
             (((value & self.override_mask) | self.override_value) & ddr) | (value & !ddr);
         let prev_value = self.last_value;
@@ -120,13 +312,17 @@ impl AVRIOPort {
             self.last_value = new_value;
             self.last_ddr = ddr;
 
-            // TODO: implement GPIO listeners
-            // for (const listener of this.listeners) {
-            //   listener(newValue, prev_value);
-            // }
+            for listener in self.listeners.iter_mut() {
+                listener(self.config.name, new_value, ddr, cycles);
+            }
         }
     }
 
+    /// Adds a listener, called whenever the output state of the port changes
+    pub fn add_listener(&mut self, listener: GPIOListener) {
+        self.listeners.push(listener);
+    }
+
     /// Get the state of a given GPIO pin
     ///
     /// @param index Pin index to return from 0 to 7
@@ -147,40 +343,188 @@ impl AVRIOPort {
     }
 }
 
+/// The low level interrupt is pending while it is enabled and the pin is low
+fn update_low_level_interrupt(
+    external_interrupt: &AVRExternalInterrupt,
+    high: bool,
+    cpu: &mut CPU,
+) {
+    let interrupt = external_interrupt.interrupt_config(true);
+    if !high && cpu.data[EIMSK as usize] & interrupt.enable_mask != 0 {
+        cpu.queue_interrupt(interrupt);
+    } else {
+        cpu.clear_interrupt(&interrupt, false);
+    }
+}
+
+/// Hooks for the external and pin change interrupt registers, shared by all the ports
+pub fn add_interrupt_hooks(write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+    // Changing the sense control or mask re-evaluates the low level interrupts
+    let update_external_interrupts: fn(&mut ATMega328P) = |atmega| {
+        for port in atmega.ports.iter() {
+            for (pin, external_interrupt) in port.config.external_interrupts.iter().enumerate() {
+                let Some(external_interrupt) = external_interrupt else {
+                    continue;
+                };
+                let cpu = &mut atmega.cpu;
+                if external_interrupt.sense_control(&cpu.data) == ISC_LOW_LEVEL {
+                    let high = cpu.data[port.config.PIN as usize] & (1 << pin) != 0;
+                    update_low_level_interrupt(external_interrupt, high, cpu);
+                } else {
+                    let mask = cpu.data[EIMSK as usize];
+                    cpu.update_interrupt_enable(external_interrupt.interrupt_config(false), mask);
+                }
+            }
+        }
+    };
+    write_hooks.insert(
+        EICRA as u16,
+        Box::new(move |atmega, value, _, addr, _| {
+            atmega.cpu.set_data(addr, value);
+            update_external_interrupts(atmega);
+            true
+        }),
+    );
+    write_hooks.insert(
+        EIMSK as u16,
+        Box::new(move |atmega, value, _, addr, _| {
+            atmega.cpu.set_data(addr, value);
+            update_external_interrupts(atmega);
+            true
+        }),
+    );
+    write_hooks.insert(
+        EIFR as u16,
+        Box::new(|atmega, value, _, _, _| {
+            for port in atmega.ports.iter() {
+                for external_interrupt in port.config.external_interrupts.iter().flatten() {
+                    let interrupt = external_interrupt.interrupt_config(false);
+                    atmega.cpu.clear_interrupt_by_flag(&interrupt, value);
+                }
+            }
+            true
+        }),
+    );
+    write_hooks.insert(
+        PCICR as u16,
+        Box::new(|atmega, value, _, addr, _| {
+            atmega.cpu.set_data(addr, value);
+            for port in atmega.ports.iter() {
+                let interrupt = port.config.pin_change.interrupt_config();
+                atmega.cpu.update_interrupt_enable(interrupt, value);
+            }
+            true
+        }),
+    );
+    write_hooks.insert(
+        PCIFR as u16,
+        Box::new(|atmega, value, _, _, _| {
+            for port in atmega.ports.iter() {
+                let interrupt = port.config.pin_change.interrupt_config();
+                atmega.cpu.clear_interrupt_by_flag(&interrupt, value);
+            }
+            true
+        }),
+    );
+}
+
+/// Index of a port in ATMega328P::ports, from its name
+pub fn port_id(port: &str) -> usize {
+    match port {
+        "B" => 0,
+        "C" => 1,
+        "D" => 2,
+        _ => panic!("unknown port"),
+    }
+}
+
 impl ATMega328P {
     pub fn port_pin_state(&self, port: &str, pin: u8) -> PinState {
-        match port {
-            "B" => self.ports[0].pin_state(pin, &self.cpu.data),
-            "C" => self.ports[1].pin_state(pin, &self.cpu.data),
-            "D" => self.ports[2].pin_state(pin, &self.cpu.data),
-            _ => panic!("unknown port"),
+        self.ports[port_id(port)].pin_state(pin, &self.cpu.data)
+    }
+
+    /// Adds a listener on the output state of a port
+    pub fn add_gpio_listener(&mut self, port: &str, listener: GPIOListener) {
+        self.ports[port_id(port)].add_listener(listener);
+    }
+
+    /// Drives an input pin from outside the MCU, e.g. a button or another device
+    pub fn set_pin_input(&mut self, port: &str, pin: u8, level: PinInput) {
+        let port_id = port_id(port);
+        let adc_trigger = self.adc_trigger_flag();
+        self.ports[port_id].set_pin_input(pin, level, &mut self.cpu);
+        self.port_pins_updated(port_id, adc_trigger);
+    }
+
+    /// Lets the peripherals that sense the pins of the port react to their new levels. An
+    /// external interrupt flag raised by the update can trigger an ADC conversion.
+    fn port_pins_updated(&mut self, port_id: usize, adc_trigger: bool) {
+        if port_id == self.spi.config.ss_pin.0 {
+            self.spi_check_mode_fault();
         }
+        self.adc_notify_trigger(adc_trigger);
     }
 }
 
+pub const INT0: AVRExternalInterrupt = AVRExternalInterrupt {
+    index: 0,
+    interrupt: 0x02,
+};
+
+pub const INT1: AVRExternalInterrupt = AVRExternalInterrupt {
+    index: 1,
+    interrupt: 0x04,
+};
+
 pub const PORTB_CONFIG: AVRPortConfig = AVRPortConfig {
+    name: "B",
     PIN: 0x23,
     DDR: 0x24,
     PORT: 0x25,
+    pin_change: AVRPinChangeInterrupt {
+        index: 0,
+        interrupt: 0x06,
+        PCMSK: 0x6b,
+    },
+    external_interrupts: [None; 8],
 };
 
 pub const PORTC_CONFIG: AVRPortConfig = AVRPortConfig {
+    name: "C",
     PIN: 0x26,
     DDR: 0x27,
     PORT: 0x28,
+    pin_change: AVRPinChangeInterrupt {
+        index: 1,
+        interrupt: 0x08,
+        PCMSK: 0x6c,
+    },
+    external_interrupts: [None; 8],
 };
 
 pub const PORTD_CONFIG: AVRPortConfig = AVRPortConfig {
+    name: "D",
     PIN: 0x29,
     DDR: 0x2a,
     PORT: 0x2b,
+    pin_change: AVRPinChangeInterrupt {
+        index: 2,
+        interrupt: 0x0a,
+        PCMSK: 0x6d,
+    },
+    external_interrupts: [None, None, Some(INT0), Some(INT1), None, None, None, None], // PD2, PD3
 };
 
 #[cfg(test)]
 mod port_tests {
+    use std::{cell::RefCell, rc::Rc};
+
     use crate::{
         atmega328p::{ATMega328P, DEFAULT_FREQ},
-        peripheral::port::{PORTB_CONFIG, PORTC_CONFIG, PORTD_CONFIG, PinState},
+        peripheral::port::{
+            EICRA, EIFR, EIMSK, PCICR, PCIFR, PORTB_CONFIG, PORTC_CONFIG, PORTD_CONFIG, PinInput,
+            PinState,
+        },
     };
 
     #[test]
@@ -236,4 +580,225 @@ mod port_tests {
             PinState::InputPullUp
         ));
     }
+
+    #[test]
+    fn int0_rising_edge() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(EICRA as u16, 0x03); // ISC01, ISC00: rising edge
+        atmega.write_data(EIMSK as u16, 0x01);
+
+        // Act
+        atmega.set_pin_input("D", 2, PinInput::High);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[PORTD_CONFIG.PIN as usize], 1 << 2);
+        assert_eq!(atmega.cpu.data[EIFR as usize], 0x01);
+        assert!(atmega.cpu.pending_interrupts[0x02].is_some());
+    }
+
+    #[test]
+    fn int1_falling_edge_ignores_rising_edge() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(EICRA as u16, 0x08); // ISC11: falling edge
+        atmega.write_data(EIMSK as u16, 0x02);
+
+        // Act
+        atmega.set_pin_input("D", 3, PinInput::High);
+        let flag_after_rise = atmega.cpu.data[EIFR as usize];
+        atmega.set_pin_input("D", 3, PinInput::Low);
+
+        // Assert
+        assert_eq!(flag_after_rise, 0);
+        assert_eq!(atmega.cpu.data[EIFR as usize], 0x02);
+        assert!(atmega.cpu.pending_interrupts[0x04].is_some());
+    }
+
+    #[test]
+    fn int0_any_change_from_output() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(EICRA as u16, 0x01); // ISC00: any logical change
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 2);
+
+        // Act
+        atmega.write_data(PORTD_CONFIG.PORT as u16, 1 << 2);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[EIFR as usize], 0x01);
+        assert!(atmega.cpu.pending_interrupts[0x02].is_none()); // masked
+    }
+
+    #[test]
+    fn int0_low_level() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_pin_input("D", 2, PinInput::High);
+
+        // Act
+        atmega.write_data(EIMSK as u16, 0x01);
+        let pending_while_high = atmega.cpu.pending_interrupts[0x02].is_some();
+        atmega.set_pin_input("D", 2, PinInput::Low);
+        let pending_while_low = atmega.cpu.pending_interrupts[0x02].is_some();
+        atmega.set_pin_input("D", 2, PinInput::High);
+
+        // Assert
+        assert!(!pending_while_high);
+        assert!(pending_while_low);
+        assert!(atmega.cpu.pending_interrupts[0x02].is_none());
+        assert_eq!(atmega.cpu.data[EIFR as usize], 0);
+    }
+
+    #[test]
+    fn eifr_write_one_to_clear() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(EICRA as u16, 0x05); // any change on INT0 and INT1
+        atmega.set_pin_input("D", 2, PinInput::High);
+        atmega.set_pin_input("D", 3, PinInput::High);
+
+        // Act
+        atmega.write_data(EIFR as u16, 0x01);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[EIFR as usize], 0x02);
+    }
+
+    #[test]
+    fn pin_change_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PCICR as u16, 0x01); // PCIE0: port B
+        atmega.write_data(PORTB_CONFIG.pin_change.PCMSK as u16, 1 << 4);
+
+        // Act
+        atmega.set_pin_input("B", 3, PinInput::High);
+        let flag_after_masked_pin = atmega.cpu.data[PCIFR as usize];
+        atmega.set_pin_input("B", 4, PinInput::High);
+
+        // Assert
+        assert_eq!(flag_after_masked_pin, 0);
+        assert_eq!(atmega.cpu.data[PCIFR as usize], 0x01);
+        assert!(atmega.cpu.pending_interrupts[0x06].is_some());
+    }
+
+    #[test]
+    fn pin_change_interrupt_enabled_later() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PORTC_CONFIG.pin_change.PCMSK as u16, 1 << 0);
+        atmega.set_pin_input("C", 0, PinInput::High);
+
+        // Act
+        atmega.write_data(PCICR as u16, 0x02); // PCIE1: port C
+
+        // Assert
+        assert_eq!(atmega.cpu.data[PCIFR as usize], 0x02);
+        assert!(atmega.cpu.pending_interrupts[0x08].is_some());
+    }
+
+    #[test]
+    fn pin_input_levels() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let pin = 5;
+
+        // Act
+        atmega.set_pin_input("B", pin, PinInput::High);
+        let pin_high = atmega.cpu.data[PORTB_CONFIG.PIN as usize];
+        atmega.set_pin_input("B", pin, PinInput::Low);
+        let pin_low = atmega.cpu.data[PORTB_CONFIG.PIN as usize];
+        atmega.set_pin_input("B", pin, PinInput::Floating);
+
+        // Assert
+        assert_eq!(pin_high, 1 << pin);
+        assert_eq!(pin_low, 0);
+        assert_eq!(atmega.cpu.data[PORTB_CONFIG.PIN as usize], 0);
+    }
+
+    #[test]
+    fn floating_pin_reads_pullup() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let pin = 1;
+        atmega.write_data(PORTC_CONFIG.PORT as u16, 1 << pin);
+
+        // Act
+        let pin_pulled_up = atmega.cpu.data[PORTC_CONFIG.PIN as usize];
+        atmega.set_pin_input("C", pin, PinInput::Low);
+
+        // Assert
+        assert_eq!(pin_pulled_up, 1 << pin);
+        assert_eq!(atmega.cpu.data[PORTC_CONFIG.PIN as usize], 0);
+    }
+
+    #[test]
+    fn output_pin_ignores_input() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let pin = 6;
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << pin);
+
+        // Act
+        atmega.set_pin_input("D", pin, PinInput::High);
+        let pin_output = atmega.cpu.data[PORTD_CONFIG.PIN as usize];
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 0);
+
+        // Assert
+        assert_eq!(pin_output, 0);
+        assert_eq!(atmega.cpu.data[PORTD_CONFIG.PIN as usize], 1 << pin);
+    }
+
+    #[test]
+    fn open_collector_released_pin_reads_input() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let pin = 4;
+        atmega.ports[1].open_collector = 1 << pin;
+        atmega.write_data(PORTC_CONFIG.DDR as u16, 1 << pin);
+        atmega.write_data(PORTC_CONFIG.PORT as u16, 1 << pin);
+
+        // Act
+        atmega.set_pin_input("C", pin, PinInput::Low);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[PORTC_CONFIG.PIN as usize], 0);
+    }
+
+    #[test]
+    fn gpio_listener() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let changes = Rc::new(RefCell::new(vec![]));
+        let listener_changes = changes.clone();
+        atmega.add_gpio_listener(
+            "D",
+            Box::new(move |port, value, ddr, cycle| {
+                listener_changes
+                    .borrow_mut()
+                    .push((port.to_string(), value, ddr, cycle));
+            }),
+        );
+
+        // Act
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 3);
+        atmega.cpu.cycles = 10;
+        atmega.write_data(PORTD_CONFIG.PORT as u16, 1 << 3);
+        atmega.write_data(PORTD_CONFIG.PORT as u16, 1 << 3); // unchanged
+        atmega.write_data(PORTB_CONFIG.PORT as u16, 1 << 3); // another port
+
+        // Assert
+        assert_eq!(
+            *changes.borrow(),
+            vec![
+                ("D".to_string(), 0, 1 << 3, 0),
+                ("D".to_string(), 1 << 3, 1 << 3, 10)
+            ]
+        );
+        assert!(matches!(
+            PinState::from_gpio(1 << 3, 1 << 3, 3),
+            PinState::High
+        ));
+    }
 }
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+
+use crate::{
+    atmega328p::{ATMega328P, PeripheralMemoryReadHook, PeripheralMemoryWriteHook},
+    clock::AVRClockEventType,
+    interrupt::AVRInterruptConfig,
+    peripheral::port::port_id,
+};
+
+// SPCR bits
+pub const SPCR_SPIE: u8 = 0x80; // SPI Interrupt Enable
+pub const SPCR_SPE: u8 = 0x40; // SPI Enable
+pub const SPCR_DORD: u8 = 0x20; // Data Order, LSB first if set
+pub const SPCR_MSTR: u8 = 0x10; // Master/Slave Select
+pub const SPCR_SPR1: u8 = 0x2; // SPI Clock Rate Select 1
+pub const SPCR_SPR0: u8 = 0x1; // SPI Clock Rate Select 0
+
+// SPSR bits
+pub const SPSR_SPIF: u8 = 0x80; // SPI Interrupt Flag
+pub const SPSR_WCOL: u8 = 0x40; // Write COLlision Flag
+pub const SPSR_SPI2X: u8 = 0x1; // Double SPI Speed Bit
+
+const BITS_PER_BYTE: u64 = 8;
+
+#[derive(Clone)]
+#[allow(non_snake_case)]
+pub struct SPIConfig {
+    pub spi_interrupt: u8, // interrupt hander address on serial transfer complete
+
+    pub SPCR: u8, // control register address
+    pub SPSR: u8, // status register address
+    pub SPDR: u8, // data register address
+
+    pub ss_pin: (usize, u8), // Slave Select pin, as (index in ATMega328P::ports, pin)
+}
+
+pub const SPI_CONFIG: SPIConfig = SPIConfig {
+    spi_interrupt: 0x22,
+    SPCR: 0x4c,
+    SPSR: 0x4d,
+    SPDR: 0x4e,
+    ss_pin: (0, 2), // PB2
+};
+
+/// Device on the SPI bus, with the ATMega328P as the master
+pub trait SpiDevice {
+    /// Exchanges a byte once a transfer completes: receives the byte the master sent on MOSI,
+    /// and returns the byte sent back on MISO. Bytes are given MSB first, as on the wire.
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+/// A device with the pin used as its chip select line, active low
+struct SpiSlave {
+    cs_pin: (usize, u8), // (index in ATMega328P::ports, pin)
+    device: Box<dyn SpiDevice>,
+}
+
+/// Serial Peripheral Interface.
+/// Note: the clock polarity and phase (SPI mode) are not simulated.
+pub struct AVRSPI {
+    pub config: SPIConfig,
+
+    spi: AVRInterruptConfig,
+
+    pub busy: bool, // a transfer is in progress, in master mode
+    tx_byte: u8,    // byte being shifted out, in master mode
+    devices: Vec<SpiSlave>,
+}
+
+impl AVRSPI {
+    pub fn new(config: SPIConfig) -> Self {
+        let spi = AVRInterruptConfig {
+            address: config.spi_interrupt,
+            flag_register: config.SPSR as u16,
+            flag_mask: SPSR_SPIF,
+            enable_register: config.SPCR as u16,
+            enable_mask: SPCR_SPIE,
+            inverse_flag: false,
+            constant: false,
+        };
+        Self {
+            config,
+            spi,
+            busy: false,
+            tx_byte: 0,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Puts the SPI back in its reset state, keeping the devices
+    pub fn reset(&mut self) {
+        let devices = std::mem::take(&mut self.devices);
+        *self = Self {
+            devices,
+            ..Self::new(self.config.clone())
+        };
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_SPCR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.SPCR as u16,
+            Box::new(|atmega, value, _, addr, _| {
+                atmega.cpu.set_data(addr, value);
+                atmega.cpu.update_interrupt_enable(atmega.spi.spi, value);
+                atmega.spi_check_mode_fault();
+                true
+            }),
+        );
+    }
+
+    /// Only SPI2X is writable in SPSR
+    #[allow(non_snake_case)]
+    pub fn add_SPSR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.SPSR as u16,
+            Box::new(|atmega, value, old_value, addr, _| {
+                atmega
+                    .cpu
+                    .set_data(addr, (old_value & !SPSR_SPI2X) | (value & SPSR_SPI2X));
+                true
+            }),
+        );
+    }
+
+    /// Writing SPDR starts a transfer in master mode, or loads the byte to send in slave mode
+    #[allow(non_snake_case)]
+    pub fn add_SPDR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.SPDR as u16,
+            Box::new(|atmega, value, _, _, _| {
+                let spcr = atmega.cpu.data[atmega.spi.config.SPCR as usize];
+                if spcr & SPCR_SPE == 0 || spcr & SPCR_MSTR == 0 {
+                    return false;
+                }
+                let spsr = atmega.spi.config.SPSR as usize;
+                if atmega.spi.busy {
+                    atmega.cpu.data[spsr] |= SPSR_WCOL; // the write is ignored
+                    return true;
+                }
+                atmega.cpu.data[spsr] &= !SPSR_WCOL;
+                let spi = atmega.spi.spi;
+                atmega.cpu.clear_interrupt(&spi, true);
+                atmega.spi.busy = true;
+                atmega.spi.tx_byte = value;
+                atmega.cpu.add_clock_event(
+                    Box::new(|atmega: &mut ATMega328P, _, _, _| atmega.spi_complete_transfer()),
+                    atmega.spi_clock_divider() as u64 * BITS_PER_BYTE,
+                    AVRClockEventType::SPI,
+                );
+                false
+            }),
+        );
+    }
+
+    /// Accessing SPDR clears SPIF and WCOL
+    #[allow(non_snake_case)]
+    pub fn add_SPDR_read_hook(&self, read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>) {
+        read_hooks.insert(
+            self.config.SPDR as u16,
+            Box::new(|atmega, addr| {
+                let spi = atmega.spi.spi;
+                atmega.cpu.clear_interrupt(&spi, true);
+                atmega.cpu.data[atmega.spi.config.SPSR as usize] &= !SPSR_WCOL;
+                atmega.cpu.get_data(addr)
+            }),
+        );
+    }
+
+    /// Ratio of the CPU clock to the SPI clock
+    pub fn clock_divider(&self, data: &[u8]) -> usize {
+        let spcr = data[self.config.SPCR as usize];
+        let divider = match spcr & (SPCR_SPR1 | SPCR_SPR0) {
+            0 => 4,
+            1 => 16,
+            2 => 64,
+            _ => 128,
+        };
+        if data[self.config.SPSR as usize] & SPSR_SPI2X != 0 {
+            divider / 2
+        } else {
+            divider
+        }
+    }
+}
+
+impl ATMega328P {
+    pub fn spi_clock_divider(&self) -> usize {
+        self.spi.clock_divider(&self.cpu.data)
+    }
+
+    /// Connects a device to the SPI bus, selected when the given pin is an output driven low
+    pub fn spi_add_device(&mut self, cs_port: &str, cs_pin: u8, device: Box<dyn SpiDevice>) {
+        self.spi.devices.push(SpiSlave {
+            cs_pin: (port_id(cs_port), cs_pin),
+            device,
+        });
+    }
+
+    /// Clocks a byte in slave mode, with the host as the master. Returns the byte sent back by
+    /// the firmware, or None if the SPI is not enabled as a slave or SS is not driven low.
+    pub fn spi_slave_transfer(&mut self, mosi: u8) -> Option<u8> {
+        let spcr = self.cpu.data[self.spi.config.SPCR as usize];
+        if spcr & SPCR_SPE == 0 || spcr & SPCR_MSTR != 0 || self.spi_ss_high() {
+            return None;
+        }
+        let spdr = self.spi.config.SPDR as usize;
+        let miso = self.cpu.data[spdr];
+        self.cpu.data[spdr] = mosi;
+        self.cpu.set_interrupt_flag(self.spi.spi);
+        Some(miso)
+    }
+
+    fn spi_ss_high(&self) -> bool {
+        let (port, pin) = self.spi.config.ss_pin;
+        self.cpu.data[self.ports[port].config.PIN as usize] & (1 << pin) != 0
+    }
+
+    /// In master mode, driving SS low while it is an input switches the SPI to slave mode
+    pub(crate) fn spi_check_mode_fault(&mut self) {
+        let spcr = self.spi.config.SPCR as usize;
+        if self.cpu.data[spcr] & (SPCR_SPE | SPCR_MSTR) != SPCR_SPE | SPCR_MSTR {
+            return;
+        }
+        let (port, pin) = self.spi.config.ss_pin;
+        let ss_input = self.cpu.data[self.ports[port].config.DDR as usize] & (1 << pin) == 0;
+        if ss_input && !self.spi_ss_high() {
+            self.cpu.data[spcr] &= !SPCR_MSTR;
+            self.cpu.set_interrupt_flag(self.spi.spi);
+        }
+    }
+
+    /// Exchanges the byte with the selected devices at the end of a transfer
+    fn spi_complete_transfer(&mut self) {
+        self.spi.busy = false;
+        let lsb_first = self.cpu.data[self.spi.config.SPCR as usize] & SPCR_DORD != 0;
+        let mosi = if lsb_first {
+            self.spi.tx_byte.reverse_bits()
+        } else {
+            self.spi.tx_byte
+        };
+        let mut miso = 0xff; // pulled up while no device drives it
+        for slave in self.spi.devices.iter_mut() {
+            let (port, pin) = slave.cs_pin;
+            let config = &self.ports[port].config;
+            let output = self.cpu.data[config.DDR as usize] & (1 << pin) != 0;
+            let high = self.ports[port].last_value & (1 << pin) != 0;
+            if output && !high {
+                miso &= slave.device.transfer(mosi);
+            }
+        }
+        let received = if lsb_first { miso.reverse_bits() } else { miso };
+        self.cpu.data[self.spi.config.SPDR as usize] = received;
+        self.cpu.set_interrupt_flag(self.spi.spi);
+    }
+}
+
+#[cfg(test)]
+mod spi_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::{
+            port::{PORTB_CONFIG, PinInput},
+            spi::{
+                SPCR_DORD, SPCR_MSTR, SPCR_SPE, SPCR_SPIE, SPCR_SPR0, SPCR_SPR1, SPI_CONFIG,
+                SPSR_SPI2X, SPSR_SPIF, SPSR_WCOL, SpiDevice,
+            },
+        },
+    };
+
+    /// Records the bytes it receives, and always answers with the same byte
+    struct EchoDevice {
+        received: Rc<RefCell<Vec<u8>>>,
+        reply: u8,
+    }
+
+    impl SpiDevice for EchoDevice {
+        fn transfer(&mut self, mosi: u8) -> u8 {
+            self.received.borrow_mut().push(mosi);
+            self.reply
+        }
+    }
+
+    /// Enables the SPI as master, with SS (PB2) and the chip select pin (PB1) as outputs
+    fn enable_master(atmega: &mut ATMega328P, spcr: u8) {
+        atmega.write_data(PORTB_CONFIG.DDR as u16, (1 << 2) | (1 << 1));
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPE | SPCR_MSTR | spcr);
+    }
+
+    fn spsr(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[SPI_CONFIG.SPSR as usize]
+    }
+
+    #[test]
+    fn clock_divider() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act/Assert
+        assert_eq!(atmega.spi_clock_divider(), 4);
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPR0);
+        assert_eq!(atmega.spi_clock_divider(), 16);
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPR1);
+        assert_eq!(atmega.spi_clock_divider(), 64);
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPR1 | SPCR_SPR0);
+        assert_eq!(atmega.spi_clock_divider(), 128);
+        atmega.write_data(SPI_CONFIG.SPSR as u16, SPSR_SPI2X);
+        assert_eq!(atmega.spi_clock_divider(), 64);
+    }
+
+    /// The selected device exchanges the byte once it has been shifted out
+    #[test]
+    fn master_transfer_with_selected_device() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let device = EchoDevice {
+            received: received.clone(),
+            reply: 0x5a,
+        };
+        atmega.spi_add_device("B", 1, Box::new(device));
+        enable_master(&mut atmega, SPCR_SPR0); // divider of 16
+
+        // Act & Assert
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x3c);
+        atmega.cpu.cycles = 16 * 8 - 1;
+        atmega.tick(None);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, 0);
+        assert!(received.borrow().is_empty());
+
+        atmega.cpu.cycles = 16 * 8;
+        atmega.tick(None);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, SPSR_SPIF);
+        assert_eq!(*received.borrow(), vec![0x3c]);
+        assert_eq!(atmega.read_data(SPI_CONFIG.SPDR as u16), 0x5a);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, 0);
+    }
+
+    /// A device whose chip select line is high does not take part in the transfer
+    #[test]
+    fn master_transfer_without_selected_device() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let device = EchoDevice {
+            received: received.clone(),
+            reply: 0x5a,
+        };
+        atmega.spi_add_device("B", 1, Box::new(device));
+        enable_master(&mut atmega, 0);
+        atmega.write_data(PORTB_CONFIG.PORT as u16, 1 << 1); // deselect
+
+        // Act
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x3c);
+        atmega.cpu.cycles = 4 * 8;
+        atmega.tick(None);
+
+        // Assert
+        assert!(received.borrow().is_empty());
+        assert_eq!(atmega.read_data(SPI_CONFIG.SPDR as u16), 0xff);
+    }
+
+    /// In LSB first order, the bytes are reversed on the wire
+    #[test]
+    fn master_transfer_lsb_first() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let device = EchoDevice {
+            received: received.clone(),
+            reply: 0x80,
+        };
+        atmega.spi_add_device("B", 1, Box::new(device));
+        enable_master(&mut atmega, SPCR_DORD);
+
+        // Act
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x01);
+        atmega.cpu.cycles = 4 * 8;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(*received.borrow(), vec![0x80]);
+        assert_eq!(atmega.read_data(SPI_CONFIG.SPDR as u16), 0x01);
+    }
+
+    #[test]
+    fn transfer_complete_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_master(&mut atmega, SPCR_SPIE);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x3c);
+        atmega.cpu.cycles = 4 * 8;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, SPI_CONFIG.spi_interrupt as u32);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, 0); // cleared by the interrupt
+    }
+
+    /// Writing SPDR during a transfer is ignored, and sets WCOL
+    #[test]
+    #[allow(non_snake_case)]
+    fn WCOL_on_write_during_transfer() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let device = EchoDevice {
+            received: received.clone(),
+            reply: 0,
+        };
+        atmega.spi_add_device("B", 1, Box::new(device));
+        enable_master(&mut atmega, 0);
+
+        // Act
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x11);
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x22);
+        atmega.cpu.cycles = 4 * 8;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(spsr(&atmega), SPSR_SPIF | SPSR_WCOL);
+        assert_eq!(*received.borrow(), vec![0x11]);
+    }
+
+    /// SS driven low while it is an input switches the SPI to slave mode
+    #[test]
+    fn mode_fault_on_ss_low() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPE | SPCR_MSTR);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[SPI_CONFIG.SPCR as usize], SPCR_SPE);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, SPSR_SPIF);
+    }
+
+    /// SS driven low from outside while the SPI is a master, with SS as an input
+    #[test]
+    fn mode_fault_on_ss_input_change() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_pin_input("B", 2, PinInput::High);
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPE | SPCR_MSTR);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, 0);
+
+        // Act
+        atmega.set_pin_input("B", 2, PinInput::Low);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[SPI_CONFIG.SPCR as usize], SPCR_SPE);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, SPSR_SPIF);
+    }
+
+    /// Releasing SS as an output while it reads low is a mode fault too
+    #[test]
+    fn mode_fault_on_ss_direction_change() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_pin_input("B", 2, PinInput::Low);
+        enable_master(&mut atmega, 0);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, 0);
+
+        // Act
+        atmega.write_data(PORTB_CONFIG.DDR as u16, 0);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[SPI_CONFIG.SPCR as usize], SPCR_SPE);
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, SPSR_SPIF);
+    }
+
+    #[test]
+    fn slave_transfer() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        assert_eq!(atmega.spi_slave_transfer(0x42), None); // SPI disabled
+        atmega.write_data(SPI_CONFIG.SPCR as u16, SPCR_SPE);
+        atmega.write_data(SPI_CONFIG.SPDR as u16, 0x99);
+
+        // Act
+        let miso = atmega.spi_slave_transfer(0x42);
+
+        // Assert
+        assert_eq!(miso, Some(0x99));
+        assert_eq!(spsr(&atmega) & SPSR_SPIF, SPSR_SPIF);
+        assert_eq!(atmega.read_data(SPI_CONFIG.SPDR as u16), 0x42);
+    }
+}
@@ -2,54 +2,360 @@ use std::collections::HashMap;
 
 use crate::{
     atmega328p::{ATMega328P, PeripheralMemoryReadHook, PeripheralMemoryWriteHook},
-    clock::AVRClockEventType,
+    clock::{AVRClockEventCallback, AVRClockEventType},
     cpu::CPU,
     interrupt::AVRInterruptConfig,
-    peripheral::i2c::bus::I2CBus,
+    peripheral::port::{AVRIOPort, PinOverrideMode},
     ternary,
 };
 
-const CS00: u8 = 1 << 0; // Clock Select 0
-const CS01: u8 = 1 << 1; // Clock Select 1
+// TCCRB bits
+pub const CS00: u8 = 1 << 0; // Clock Select 0
+pub const CS01: u8 = 1 << 1; // Clock Select 1
+pub const CS02: u8 = 1 << 2; // Clock Select 2
+pub const CS10: u8 = 1 << 0; // Clock Select 0 (16-bit timer)
+pub const WGM02: u8 = 1 << 3; // Waveform Generation Mode 2
+pub const WGM12: u8 = 1 << 3; // Waveform Generation Mode 2 (16-bit timer)
+pub const WGM13: u8 = 1 << 4; // Waveform Generation Mode 3 (16-bit timer)
+const FOCA: u8 = 1 << 7; // Force Output Compare A (TCCRB of 8-bit timers, TCCRC of 16-bit timers)
+const FOCB: u8 = 1 << 6; // Force Output Compare B
 
+// ASSR bits
+pub const AS2: u8 = 1 << 5; // Asynchronous Timer/Counter2
+pub const EXCLK: u8 = 1 << 6; // Enable External Clock Input
+
+// TCCRA bits
+pub const COMA0: u8 = 1 << 6; // Compare Match Output A Mode 0
+pub const COMA1: u8 = 1 << 7; // Compare Match Output A Mode 1
+pub const COMB0: u8 = 1 << 4; // Compare Match Output B Mode 0
+pub const COMB1: u8 = 1 << 5; // Compare Match Output B Mode 1
+pub const WGM00: u8 = 1 << 0; // Waveform Generation Mode 0
+pub const WGM01: u8 = 1 << 1; // Waveform Generation Mode 1
+pub const WGM10: u8 = 1 << 0; // Waveform Generation Mode 0 (16-bit timer)
+pub const WGM11: u8 = 1 << 1; // Waveform Generation Mode 1 (16-bit timer)
+
+/// Clock dividers of timer 0 and 1, indexed by the clock select bits
+/// (6 and 7 select the external clock, which is not implemented)
+pub const TIMER_01_DIVIDERS: [u16; 8] = [0, 1, 8, 64, 256, 1024, 0, 0];
+
+/// Clock dividers of timer 2, indexed by the clock select bits
+pub const TIMER_2_DIVIDERS: [u16; 8] = [0, 1, 8, 32, 64, 128, 256, 1024];
+
+/// Frequency of the watch crystal clocking timer 2 in asynchronous mode
+pub const ASYNC_CLOCK_HZ: u64 = 32_768;
+
+#[derive(Clone)]
 #[allow(non_snake_case)]
 pub struct AVRTimerConfig {
+    pub bits: u8,           // 8 or 16
+    pub dividers: [u16; 8], // clock divider for each clock select value
+    pub count_event: AVRClockEventType,
+
     // Interrupt vectors
+    pub capture_interrupt: u8, // 0 if the timer has no input capture unit
+    pub comp_a_interrupt: u8,
+    pub comp_b_interrupt: u8,
     pub ovf_interrupt: u8,
 
     // Register addresses
     pub TIFR: u8,
-    pub TCNT: u8,
     pub OCRA: u8,
+    pub OCRB: u8,
+    pub ICR: u8, // 0 if the timer has no input capture unit
+    pub TCNT: u8,
     pub TCCRA: u8,
     pub TCCRB: u8,
+    pub TCCRC: u8, // 0 if the force output compare bits are in TCCRB
     pub TIMSK: u8,
+    pub ASSR: u8, // 0 if the timer cannot run asynchronously
+
+    // Output compare pins, as (index in ATMega328P::ports, pin)
+    pub comp_pin_a: (usize, u8),
+    pub comp_pin_b: (usize, u8),
 
     // TIFR bits
     pub TOV: u8,
+    pub OCFA: u8,
+    pub OCFB: u8,
+    pub ICF: u8,
 
     // TIMSK bits
     pub TOIE: u8,
+    pub OCIEA: u8,
+    pub OCIEB: u8,
+    pub ICIE: u8,
+}
+
+/// Output compare unit of a timer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompChannel {
+    A,
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerMode {
+    Normal,
+    PWMPhaseCorrect,
+    CTC,
+    FastPWM,
+    PWMPhaseFrequencyCorrect,
+    Reserved,
+}
+
+/// Where the TOP value of the counter comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerTop {
+    Fixed(u16),
+    OCRA,
+    ICR,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OCRUpdateMode {
     Immediate,
+    Top,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TOVUpdateMode {
+    Max,
+    Top,
+    Bottom,
 }
 
+/// The compare output mode 1 toggles OCnA on compare match, instead of being reserved
+const OC_TOGGLE: u8 = 1;
+
+type WGMConfig = (TimerMode, TimerTop, OCRUpdateMode, TOVUpdateMode, u8); // (..., flags)
+
+/// Waveform generation modes of 8-bit timers, indexed by WGM (table 15-8 of the datasheet)
+const WGM_MODES_8_BIT: [WGMConfig; 8] = [
+    /*0*/
+    (
+        TimerMode::Normal,
+        TimerTop::Fixed(0xff),
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*1*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::Fixed(0xff),
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*2*/
+    (
+        TimerMode::CTC,
+        TimerTop::OCRA,
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*3*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::Fixed(0xff),
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*4*/
+    (
+        TimerMode::Reserved,
+        TimerTop::Fixed(0xff),
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*5*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::OCRA,
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        OC_TOGGLE,
+    ),
+    /*6*/
+    (
+        TimerMode::Reserved,
+        TimerTop::Fixed(0xff),
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*7*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::OCRA,
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        OC_TOGGLE,
+    ),
+];
+
+/// Waveform generation modes of 16-bit timers, indexed by WGM (table 16-4 of the datasheet)
+const WGM_MODES_16_BIT: [WGMConfig; 16] = [
+    /*0*/
+    (
+        TimerMode::Normal,
+        TimerTop::Fixed(0xffff),
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*1*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::Fixed(0x00ff),
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*2*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::Fixed(0x01ff),
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*3*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::Fixed(0x03ff),
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*4*/
+    (
+        TimerMode::CTC,
+        TimerTop::OCRA,
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*5*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::Fixed(0x00ff),
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        0,
+    ),
+    /*6*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::Fixed(0x01ff),
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        0,
+    ),
+    /*7*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::Fixed(0x03ff),
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        0,
+    ),
+    /*8*/
+    (
+        TimerMode::PWMPhaseFrequencyCorrect,
+        TimerTop::ICR,
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*9*/
+    (
+        TimerMode::PWMPhaseFrequencyCorrect,
+        TimerTop::OCRA,
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Bottom,
+        OC_TOGGLE,
+    ),
+    /*10*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::ICR,
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        0,
+    ),
+    /*11*/
+    (
+        TimerMode::PWMPhaseCorrect,
+        TimerTop::OCRA,
+        OCRUpdateMode::Top,
+        TOVUpdateMode::Bottom,
+        OC_TOGGLE,
+    ),
+    /*12*/
+    (
+        TimerMode::CTC,
+        TimerTop::ICR,
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*13*/
+    (
+        TimerMode::Reserved,
+        TimerTop::Fixed(0xffff),
+        OCRUpdateMode::Immediate,
+        TOVUpdateMode::Max,
+        0,
+    ),
+    /*14*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::ICR,
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        OC_TOGGLE,
+    ),
+    /*15*/
+    (
+        TimerMode::FastPWM,
+        TimerTop::OCRA,
+        OCRUpdateMode::Bottom,
+        TOVUpdateMode::Top,
+        OC_TOGGLE,
+    ),
+];
+
 pub struct AVRTimer {
     pub max: u16,
 
     pub config: AVRTimerConfig,
-    pub last_cycle: u32,
+    pub freq_hz: usize,    // CPU clock frequency
+    pub async_clock: bool, // whether the timer is clocked by the 32.768 kHz crystal instead of the CPU clock
+    pub last_cycle: u64,   // timer clock tick of the last count
 
     pub ocra: u16,
     pub next_ocra: u16,
+    pub ocrb: u16,
+    pub next_ocrb: u16,
+    pub icr: u16,
 
+    pub timer_mode: TimerMode,
+    pub top_value: TimerTop,
     pub ocr_update_mode: OCRUpdateMode,
+    pub tov_update_mode: TOVUpdateMode,
+
+    pub comp_a: u8, // compare output mode (COMA bits), 0 if OCA is disconnected
+    pub comp_b: u8, // compare output mode (COMB bits), 0 if OCB is disconnected
 
     pub tcnt: u16,
     pub tcnt_next: u16,
     pub tcnt_updated: bool,
+    pub counting_up: bool, // direction of the counter in the phase correct modes
 
     pub update_divider: bool,
     pub divider: u16,
@@ -57,48 +363,98 @@ pub struct AVRTimer {
     pub high_byte_temp: u8, // This is the temporary register used to access 16-bit registers (section 16.3 of the datasheet)
 
     pub ovf: AVRInterruptConfig,
+    pub ocfa: AVRInterruptConfig,
+    pub ocfb: AVRInterruptConfig,
+    pub icf: Option<AVRInterruptConfig>,
 }
 
 impl AVRTimer {
-    pub fn new(config: AVRTimerConfig) -> Self {
-        let ovf = AVRInterruptConfig {
-            address: config.ovf_interrupt,
+    pub fn new(config: AVRTimerConfig, freq_hz: usize) -> Self {
+        let interrupt = |address, flag_mask, enable_mask| AVRInterruptConfig {
+            address,
             enable_register: config.TIMSK as u16,
-            enable_mask: config.TOIE,
+            enable_mask,
             flag_register: config.TIFR as u16,
-            flag_mask: config.TOV,
+            flag_mask,
             inverse_flag: false,
+            constant: false,
         };
+        let ovf = interrupt(config.ovf_interrupt, config.TOV, config.TOIE);
+        let ocfa = interrupt(config.comp_a_interrupt, config.OCFA, config.OCIEA);
+        let ocfb = interrupt(config.comp_b_interrupt, config.OCFB, config.OCIEB);
+        let icf = ternary!(
+            config.capture_interrupt,
+            Some(interrupt(config.capture_interrupt, config.ICF, config.ICIE)),
+            None
+        );
+        let (timer_mode, top_value, ocr_update_mode, tov_update_mode, _) =
+            ternary!(config.bits & 16, WGM_MODES_16_BIT[0], WGM_MODES_8_BIT[0]);
         AVRTimer {
-            max: 0xff, // config.bits === 16 ? 0xffff : 0xff;
+            max: ternary!(config.bits & 16, 0xffff, 0xff),
             config,
+            freq_hz,
+            async_clock: false,
             last_cycle: 0,
             ocra: 0,
             next_ocra: 0,
-            ocr_update_mode: OCRUpdateMode::Immediate,
+            ocrb: 0,
+            next_ocrb: 0,
+            icr: 0,
+            timer_mode,
+            top_value,
+            ocr_update_mode,
+            tov_update_mode,
+            comp_a: 0,
+            comp_b: 0,
             tcnt: 0,
             tcnt_next: 0,
             tcnt_updated: false,
+            counting_up: true,
             update_divider: false,
             divider: 0,
             high_byte_temp: 0,
             ovf,
+            ocfa,
+            ocfb,
+            icf,
         }
     }
 
+    /// Puts the timer back in its reset state
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone(), self.freq_hz);
+    }
+
     /// TOP value of counter
     pub fn top(&self) -> u16 {
-        // for now, assume to be 0xff
-        0xff
+        match self.top_value {
+            TimerTop::Fixed(top) => top,
+            TimerTop::OCRA => self.ocra,
+            TimerTop::ICR => self.icr,
+        }
+    }
+
+    /// Bits of the output compare registers used by the current mode
+    pub fn ocr_mask(&self) -> u16 {
+        match self.top_value {
+            TimerTop::Fixed(top) => top,
+            TimerTop::OCRA | TimerTop::ICR => 0xffff,
+        }
     }
 
     #[allow(non_snake_case)]
-    pub fn add_TCNT_read_hook(&self, read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>) {
+    pub fn add_TCNT_read_hook(
+        &self,
+        read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>,
+        timer_id: usize,
+    ) {
         read_hooks.insert(
             self.config.TCNT as u16,
-            Box::new(|atmega, addr| {
-                atmega.count(None, false, false);
-                let data = (atmega.timer0.tcnt & 0xff) as u8;
+            Box::new(move |atmega, addr| {
+                atmega.timer_count(timer_id, false, false);
+                let timer = &mut atmega.timers[timer_id];
+                timer.high_byte_temp = (timer.tcnt >> 8) as u8;
+                let data = (timer.tcnt & 0xff) as u8;
                 atmega.cpu.set_data(addr, data);
                 data
             }),
@@ -106,59 +462,310 @@ impl AVRTimer {
     }
 
     #[allow(non_snake_case)]
-    pub fn add_TCNT_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+    pub fn add_TCNT_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
         write_hooks.insert(
             self.config.TCNT as u16,
-            Box::new(|atmega, value, _, _, _| {
-                atmega.timer0.tcnt_next = value as u16 | (atmega.timer0.high_byte_temp as u16) << 8;
-                // atmega.cpu.timer0.counting_up = true;
-                atmega.timer0.tcnt_updated = true;
-                atmega.cpu.update_clock_event(
-                    Box::new(ATMega328P::count),
-                    crate::clock::AVRClockEventType::Count,
-                    0,
-                );
-                // if atmega.cpu.timer0.divider != 0 {
-                //     atmega.cpu.timer0.timer_updated
-                // }
+            Box::new(move |atmega, value, _, _, _| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.tcnt_next = value as u16 | (timer.high_byte_temp as u16) << 8;
+                timer.counting_up = true;
+                timer.tcnt_updated = true;
+                let event_type = timer.config.count_event;
+                atmega
+                    .cpu
+                    .update_clock_event(timer_count_event(timer_id), event_type, 0);
                 false
             }),
         );
     }
 
     #[allow(non_snake_case)]
-    pub fn add_TCCRB_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+    pub fn add_OCR_write_hooks(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        write_hooks.insert(
+            self.config.OCRA as u16,
+            Box::new(move |atmega, value, _, _, _| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.next_ocra = value as u16 | (timer.high_byte_temp as u16) << 8;
+                if timer.ocr_update_mode == OCRUpdateMode::Immediate {
+                    timer.ocra = timer.next_ocra;
+                }
+                false
+            }),
+        );
+        write_hooks.insert(
+            self.config.OCRB as u16,
+            Box::new(move |atmega, value, _, _, _| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.next_ocrb = value as u16 | (timer.high_byte_temp as u16) << 8;
+                if timer.ocr_update_mode == OCRUpdateMode::Immediate {
+                    timer.ocrb = timer.next_ocrb;
+                }
+                false
+            }),
+        );
+    }
+
+    /// Hooks for the high bytes of 16-bit registers, which go through the TEMP register:
+    /// reading the low byte latches the high byte, and writing the low byte commits the high byte.
+    pub fn add_16_bit_hooks(
+        &self,
+        read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        if self.config.bits != 16 {
+            return;
+        }
+        let config = &self.config;
+
+        let read_temp: fn(&mut ATMega328P, u16, usize) -> u8 = |atmega, addr, timer_id| {
+            let data = atmega.timers[timer_id].high_byte_temp;
+            atmega.cpu.set_data(addr, data);
+            data
+        };
+        read_hooks.insert(
+            config.TCNT as u16 + 1,
+            Box::new(move |atmega, addr| read_temp(atmega, addr, timer_id)),
+        );
+        read_hooks.insert(
+            config.ICR as u16 + 1,
+            Box::new(move |atmega, addr| read_temp(atmega, addr, timer_id)),
+        );
+        read_hooks.insert(
+            config.ICR as u16,
+            Box::new(move |atmega, addr| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.high_byte_temp = (timer.icr >> 8) as u8;
+                let data = (timer.icr & 0xff) as u8;
+                atmega.cpu.set_data(addr, data);
+                data
+            }),
+        );
+
+        write_hooks.insert(
+            config.ICR as u16,
+            Box::new(move |atmega, value, _, _, _| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.icr = value as u16 | (timer.high_byte_temp as u16) << 8;
+                false
+            }),
+        );
+        let write_temp: fn(&mut ATMega328P, u8, usize) -> bool = |atmega, value, timer_id| {
+            atmega.timers[timer_id].high_byte_temp = value;
+            false
+        };
+        write_hooks.insert(
+            config.TCNT as u16 + 1,
+            Box::new(move |atmega, value, _, _, _| write_temp(atmega, value, timer_id)),
+        );
+        write_hooks.insert(
+            config.ICR as u16 + 1,
+            Box::new(move |atmega, value, _, _, _| write_temp(atmega, value, timer_id)),
+        );
+        // OCR high bytes beyond the TOP of the current mode are not writable
+        let write_ocr_high: fn(&mut ATMega328P, u8, u16, usize) -> bool =
+            |atmega, value, addr, timer_id| {
+                let timer = &mut atmega.timers[timer_id];
+                timer.high_byte_temp = value & (timer.ocr_mask() >> 8) as u8;
+                let data = timer.high_byte_temp;
+                atmega.cpu.set_data(addr, data);
+                true
+            };
+        write_hooks.insert(
+            config.OCRA as u16 + 1,
+            Box::new(move |atmega, value, _, addr, _| {
+                write_ocr_high(atmega, value, addr, timer_id)
+            }),
+        );
+        write_hooks.insert(
+            config.OCRB as u16 + 1,
+            Box::new(move |atmega, value, _, addr, _| {
+                write_ocr_high(atmega, value, addr, timer_id)
+            }),
+        );
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_TCCRA_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        write_hooks.insert(
+            self.config.TCCRA as u16,
+            Box::new(move |atmega, value, _, addr, _| {
+                atmega.cpu.set_data(addr, value);
+                atmega.timers[timer_id].update_wgm_config(&mut atmega.cpu, &mut atmega.ports);
+                true
+            }),
+        );
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_TCCRB_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
         write_hooks.insert(
             self.config.TCCRB as u16,
-            Box::new(|atmega, value, _, _, _| {
-                // TODO: check force compare
+            Box::new(move |atmega, value, _, addr, _| {
+                let timer = &mut atmega.timers[timer_id];
+                let mut value = value;
+                if timer.config.TCCRC == 0 {
+                    timer.check_force_compare(value, &mut atmega.cpu, &mut atmega.ports);
+                    value &= !(FOCA | FOCB); // always read as zero
+                }
+                atmega.cpu.set_data(addr, value);
+                timer.update_divider = true;
+                timer.update_wgm_config(&mut atmega.cpu, &mut atmega.ports);
+                let event_type = timer.config.count_event;
+                atmega.cpu.clear_clock_event(event_type);
                 atmega
                     .cpu
-                    .set_data(atmega.timer0.config.TCCRB as u16, value);
-                atmega.timer0.update_divider = true;
-                atmega.cpu.clear_clock_event(AVRClockEventType::Count);
-                atmega.cpu.add_clock_event(
-                    Box::new(ATMega328P::count),
-                    0,
-                    AVRClockEventType::Count,
+                    .add_clock_event(timer_count_event(timer_id), 0, event_type);
+                true
+            }),
+        );
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_TCCRC_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        if self.config.TCCRC == 0 {
+            return;
+        }
+        write_hooks.insert(
+            self.config.TCCRC as u16,
+            Box::new(move |atmega, value, _, addr, _| {
+                atmega.timers[timer_id].check_force_compare(
+                    value,
+                    &mut atmega.cpu,
+                    &mut atmega.ports,
                 );
-                // TODO: update wgm config
+                atmega.cpu.set_data(addr, value & !(FOCA | FOCB)); // always read as zero
+                true
+            }),
+        );
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_TIFR_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        write_hooks.insert(
+            self.config.TIFR as u16,
+            Box::new(move |atmega, value, _, addr, _| {
+                // flags are cleared by writing a logical one to them
+                atmega.cpu.set_data(addr, value);
+                let timer = &atmega.timers[timer_id];
+                for interrupt in [
+                    Some(timer.ovf),
+                    Some(timer.ocfa),
+                    Some(timer.ocfb),
+                    timer.icf,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    atmega.cpu.clear_interrupt_by_flag(&interrupt, value);
+                }
                 true
             }),
         );
     }
 
     #[allow(non_snake_case)]
-    pub fn add_TIMSK_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+    pub fn add_TIMSK_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
         write_hooks.insert(
             self.config.TIMSK as u16,
-            Box::new(|atmega, value, _, _, _| {
-                atmega.cpu.update_interrupt_enable(atmega.timer0.ovf, value);
+            Box::new(move |atmega, value, _, _, _| {
+                let timer = &atmega.timers[timer_id];
+                for interrupt in [
+                    Some(timer.ovf),
+                    Some(timer.ocfa),
+                    Some(timer.ocfb),
+                    timer.icf,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    atmega.cpu.update_interrupt_enable(interrupt, value);
+                }
                 false
             }),
         );
     }
 
+    /// Runs timer 2 from the crystal (AS2) instead of the CPU clock.
+    /// Register writes take effect immediately, so the ASSR update busy flags always read as zero.
+    #[allow(non_snake_case)]
+    pub fn add_ASSR_write_hook(
+        &self,
+        write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>,
+        timer_id: usize,
+    ) {
+        if self.config.ASSR == 0 {
+            return;
+        }
+        write_hooks.insert(
+            self.config.ASSR as u16,
+            Box::new(move |atmega, value, _, addr, _| {
+                let value = value & (EXCLK | AS2);
+                atmega.cpu.set_data(addr, value);
+                let timer = &mut atmega.timers[timer_id];
+                let async_clock = value & AS2 != 0;
+                if async_clock != timer.async_clock {
+                    timer.async_clock = async_clock;
+                    // restart the prescaler on the new clock, counting in its ticks
+                    timer.last_cycle = timer.clock_ticks(atmega.cpu.cycles);
+                    timer.update_divider = true;
+                    let event_type = timer.config.count_event;
+                    atmega.cpu.clear_clock_event(event_type);
+                    atmega
+                        .cpu
+                        .add_clock_event(timer_count_event(timer_id), 0, event_type);
+                }
+                true
+            }),
+        );
+    }
+
+    /// Number of timer clock ticks at the given CPU cycle
+    pub fn clock_ticks(&self, cycles: u64) -> u64 {
+        if self.async_clock {
+            (cycles as u128 * ASYNC_CLOCK_HZ as u128 / self.freq_hz as u128) as u64
+        } else {
+            cycles
+        }
+    }
+
+    /// First CPU cycle at which the timer clock has ticked the given number of times
+    pub fn cycles_at(&self, ticks: u64) -> u64 {
+        if self.async_clock {
+            (ticks as u128 * self.freq_hz as u128).div_ceil(ASYNC_CLOCK_HZ as u128) as u64
+        } else {
+            ticks
+        }
+    }
+
     pub fn tccrb(&self, cpu: &CPU) -> u8 {
         cpu.get_data(self.config.TCCRB as u16)
     }
@@ -166,103 +773,425 @@ impl AVRTimer {
     pub fn cs(&self, cpu: &CPU) -> u8 {
         self.tccrb(cpu) & 0x7
     }
+
+    /// Waveform generation mode, from the WGM bits of TCCRA and TCCRB
+    pub fn wgm(&self, cpu: &CPU) -> u8 {
+        let mask = ternary!(self.config.bits & 16, WGM13 | WGM12, WGM02);
+        let tccra = cpu.get_data(self.config.TCCRA as u16);
+        ((self.tccrb(cpu) & mask) >> 1) | (tccra & (WGM01 | WGM00))
+    }
+
+    fn update_wgm_config(&mut self, cpu: &mut CPU, ports: &mut [AVRIOPort]) {
+        let wgm = self.wgm(cpu) as usize;
+        let (timer_mode, top_value, ocr_update_mode, tov_update_mode, flags) = ternary!(
+            self.config.bits & 16,
+            WGM_MODES_16_BIT[wgm],
+            WGM_MODES_8_BIT[wgm]
+        );
+        self.timer_mode = timer_mode;
+        self.top_value = top_value;
+        self.ocr_update_mode = ocr_update_mode;
+        self.tov_update_mode = tov_update_mode;
+
+        let tccra = cpu.get_data(self.config.TCCRA as u16);
+        let pwm_mode = matches!(
+            timer_mode,
+            TimerMode::FastPWM | TimerMode::PWMPhaseCorrect | TimerMode::PWMPhaseFrequencyCorrect
+        );
+        let prev_comp_a = self.comp_a;
+        self.comp_a = (tccra >> 6) & 0x3;
+        if self.comp_a == 1 && pwm_mode && flags & OC_TOGGLE == 0 {
+            self.comp_a = 0; // reserved
+        }
+        if (prev_comp_a != 0) != (self.comp_a != 0) {
+            let mode = ternary!(self.comp_a, PinOverrideMode::Enable, PinOverrideMode::None);
+            self.override_comp_pin(CompChannel::A, mode, cpu, ports);
+        }
+        let prev_comp_b = self.comp_b;
+        self.comp_b = (tccra >> 4) & 0x3;
+        if self.comp_b == 1 && pwm_mode {
+            self.comp_b = 0; // reserved
+        }
+        if (prev_comp_b != 0) != (self.comp_b != 0) {
+            let mode = ternary!(self.comp_b, PinOverrideMode::Enable, PinOverrideMode::None);
+            self.override_comp_pin(CompChannel::B, mode, cpu, ports);
+        }
+    }
+
+    /// Applies the force output compare bits, which are only active in the non-PWM modes
+    fn check_force_compare(&self, value: u8, cpu: &mut CPU, ports: &mut [AVRIOPort]) {
+        if matches!(
+            self.timer_mode,
+            TimerMode::FastPWM | TimerMode::PWMPhaseCorrect | TimerMode::PWMPhaseFrequencyCorrect
+        ) {
+            return;
+        }
+        if value & FOCA != 0 {
+            self.update_comp_pin(CompChannel::A, false, cpu, ports);
+        }
+        if value & FOCB != 0 {
+            self.update_comp_pin(CompChannel::B, false, cpu, ports);
+        }
+    }
+
+    fn override_comp_pin(
+        &self,
+        channel: CompChannel,
+        mode: PinOverrideMode,
+        cpu: &mut CPU,
+        ports: &mut [AVRIOPort],
+    ) {
+        let (port, pin) = match channel {
+            CompChannel::A => self.config.comp_pin_a,
+            CompChannel::B => self.config.comp_pin_b,
+        };
+        ports[port].timer_override_pin(pin, mode, cpu);
+    }
+
+    /// Drives the output compare pin on a compare match, or at BOTTOM in the fast PWM mode
+    fn update_comp_pin(
+        &self,
+        channel: CompChannel,
+        bottom: bool,
+        cpu: &mut CPU,
+        ports: &mut [AVRIOPort],
+    ) {
+        let comp = match channel {
+            CompChannel::A => self.comp_a,
+            CompChannel::B => self.comp_b,
+        };
+        if comp == 0 {
+            return;
+        }
+        let inverting = comp == 3;
+        let mode = match self.timer_mode {
+            TimerMode::Normal | TimerMode::CTC => match comp {
+                1 => PinOverrideMode::Toggle,
+                2 => PinOverrideMode::Clear,
+                _ => PinOverrideMode::Set,
+            },
+            TimerMode::FastPWM => {
+                if comp == 1 {
+                    ternary!(bottom as u8, PinOverrideMode::None, PinOverrideMode::Toggle)
+                } else if inverting != bottom {
+                    PinOverrideMode::Set
+                } else {
+                    PinOverrideMode::Clear
+                }
+            }
+            TimerMode::PWMPhaseCorrect | TimerMode::PWMPhaseFrequencyCorrect => {
+                if comp == 1 {
+                    PinOverrideMode::Toggle
+                } else if self.counting_up == inverting {
+                    PinOverrideMode::Set
+                } else {
+                    PinOverrideMode::Clear
+                }
+            }
+            TimerMode::Reserved => PinOverrideMode::None,
+        };
+        if mode != PinOverrideMode::None {
+            self.override_comp_pin(channel, mode, cpu, ports);
+        }
+    }
+
+    /// Loads the double buffered output compare registers
+    fn update_ocr(&mut self) {
+        self.ocra = self.next_ocra;
+        self.ocrb = self.next_ocrb;
+    }
+
+    fn set_icf_at_top(&self, cpu: &mut CPU) {
+        // when ICR is used as TOP, ICF is set when the counter reaches TOP
+        if self.top_value == TimerTop::ICR
+            && let Some(icf) = self.icf
+        {
+            cpu.set_interrupt_flag(icf);
+        }
+    }
+
+    /// Counts up to TOP then down to BOTTOM, in the phase correct modes
+    fn phase_pwm_count(
+        &mut self,
+        cpu: &mut CPU,
+        ports: &mut [AVRIOPort],
+        mut value: u16,
+        mut delta: u64,
+    ) -> u16 {
+        let top = self.top();
+        let tcnt_updated = self.tcnt_updated;
+        while delta > 0 {
+            if self.counting_up {
+                value = value.wrapping_add(1) & self.max;
+                if value == top && !tcnt_updated {
+                    self.counting_up = false;
+                    if self.ocr_update_mode == OCRUpdateMode::Top {
+                        self.update_ocr();
+                    }
+                    self.set_icf_at_top(cpu);
+                }
+            } else {
+                value = value.wrapping_sub(1) & self.max;
+                if value == 0 && !tcnt_updated {
+                    self.counting_up = true;
+                    cpu.set_interrupt_flag(self.ovf);
+                    if self.ocr_update_mode == OCRUpdateMode::Bottom {
+                        self.update_ocr();
+                    }
+                }
+            }
+            if !tcnt_updated {
+                if value == self.ocra {
+                    cpu.set_interrupt_flag(self.ocfa);
+                    self.update_comp_pin(CompChannel::A, false, cpu, ports);
+                }
+                if value == self.ocrb {
+                    cpu.set_interrupt_flag(self.ocfb);
+                    self.update_comp_pin(CompChannel::B, false, cpu, ports);
+                }
+            }
+            delta -= 1;
+        }
+        value
+    }
+
+    /// Raises the compare match flags if the counter went past the output compare registers
+    fn timer_updated(&self, cpu: &mut CPU, ports: &mut [AVRIOPort], value: u16, prev_value: u16) {
+        let overflow = prev_value > value;
+        for (channel, ocr, interrupt) in [
+            (CompChannel::A, self.ocra, self.ocfa),
+            (CompChannel::B, self.ocrb, self.ocfb),
+        ] {
+            if ((prev_value < ocr || overflow) && value >= ocr) || (prev_value < ocr && overflow) {
+                cpu.set_interrupt_flag(interrupt);
+                self.update_comp_pin(channel, false, cpu, ports);
+            }
+        }
+    }
+}
+
+/// Clock event that counts the given timer
+fn timer_count_event(timer_id: usize) -> AVRClockEventCallback {
+    Box::new(move |atmega, _, reschedule, external| {
+        atmega.timer_count(timer_id, reschedule, external)
+    })
 }
 
 impl ATMega328P {
-    pub fn timer0_cs(&self) -> u8 {
-        self.timer0.cs(&self.cpu)
-    }
-
-    pub fn count(&mut self, _: Option<&mut I2CBus>, reschedule: bool, external: bool) {
-        // println!("count");
-        // println!("cpu cycles: {}", self.cycles);
-        let divider = self.timer0.divider;
-        let last_cycle = self.timer0.last_cycle;
-        let cycles = self.cpu.cycles;
-        let delta = (cycles - last_cycle) as u16;
-        // println!("delta: {} divider: {}", delta, divider);
-        if (divider != 0 && delta >= divider) || external {
-            let counter_delta = if external { 1 } else { delta / divider };
-            self.timer0.last_cycle += counter_delta as u32 * divider as u32;
-            let val = self.timer0.tcnt;
-            // timer mode, assume is normal
-            let TOP = self.timer0.top();
-            let new_val = (val + counter_delta) % (TOP + 1);
-            // println!("val: {}, new val: {}", val, new_val);
-            let overflow = val + counter_delta > TOP;
+    pub fn timer_count(&mut self, timer_id: usize, reschedule: bool, external: bool) {
+        let adc_trigger = self.adc_trigger_flag();
+        let timer = &mut self.timers[timer_id];
+        let cpu = &mut self.cpu;
+        let ports = &mut self.ports;
+        let divider = timer.divider;
+        let now = timer.clock_ticks(cpu.cycles);
+        let delta = now - timer.last_cycle;
+        if (divider != 0 && delta >= divider as u64) || external {
+            let counter_delta = if external { 1 } else { delta / divider as u64 };
+            timer.last_cycle += counter_delta * divider as u64;
+            let val = timer.tcnt;
+            let TOP = timer.top();
+            let phase_pwm = matches!(
+                timer.timer_mode,
+                TimerMode::PWMPhaseCorrect | TimerMode::PWMPhaseFrequencyCorrect
+            );
+            let new_val = if phase_pwm {
+                timer.phase_pwm_count(cpu, ports, val, counter_delta)
+            } else {
+                ((val as u64 + counter_delta) % (TOP as u64 + 1)) as u16
+            };
+            let overflow = val as u64 + counter_delta > TOP as u64;
             // A CPU write overrides all counter clear or count operations
-            if true {
-                // if !tcntUpdated
-                self.timer0.tcnt = new_val;
-                // if !phase pwm
-                // self.timer0.timerUpdated(new_val, val);
+            if !timer.tcnt_updated {
+                timer.tcnt = new_val;
+                if !phase_pwm {
+                    timer.timer_updated(cpu, ports, new_val, val);
+                }
             }
 
-            // OCRUpdateMode.Bottom only occurs in Phase Correct modes, handled by phasePwmCount().
-            // Thus we only handle TOVUpdateMode.Top or TOVUpdateMode.Max here.
-            // println!("overflow: {}", overflow);
-            // if overflow {
-            //     println!("overflow");
-            // }
-            if overflow && TOP == self.timer0.max {
-                self.cpu.set_interrupt_flag(self.timer0.ovf);
+            if !phase_pwm && overflow {
+                if timer.timer_mode == TimerMode::FastPWM {
+                    timer.update_comp_pin(CompChannel::A, true, cpu, ports);
+                    timer.update_comp_pin(CompChannel::B, true, cpu, ports);
+                }
+                // OCRUpdateMode::Top and TOVUpdateMode::Bottom only occur in the phase correct
+                // modes, handled by phase_pwm_count()
+                if timer.ocr_update_mode == OCRUpdateMode::Bottom {
+                    timer.update_ocr();
+                }
+                if timer.tov_update_mode == TOVUpdateMode::Top || TOP == timer.max {
+                    cpu.set_interrupt_flag(timer.ovf);
+                }
+                timer.set_icf_at_top(cpu);
             }
         }
-        if self.timer0.tcnt_updated {
-            self.timer0.tcnt = self.timer0.tcnt_next;
-            self.timer0.tcnt_updated = false;
-            // TODO: OCR updates
+        if timer.tcnt_updated {
+            timer.tcnt = timer.tcnt_next;
+            timer.tcnt_updated = false;
+            if (timer.tcnt == 0 && timer.ocr_update_mode == OCRUpdateMode::Bottom)
+                || (timer.tcnt == timer.top() && timer.ocr_update_mode == OCRUpdateMode::Top)
+            {
+                timer.update_ocr();
+            }
         }
-        // TODO: handle if tcntUpdated
-        if self.timer0.update_divider {
-            let cs = self.timer0_cs();
-            let timer01_dividers = [0, 1, 8, 64, 256, 1024, 0, 0];
-            let new_divider = timer01_dividers[cs as usize];
-
-            self.timer0.last_cycle = ternary!(new_divider, self.cpu.cycles, 0);
-            self.timer0.update_divider = false;
-            self.timer0.divider = new_divider;
+        if timer.update_divider {
+            let new_divider = timer.config.dividers[timer.cs(cpu) as usize];
+            timer.last_cycle = ternary!(new_divider, now, 0);
+            timer.update_divider = false;
+            timer.divider = new_divider;
             if new_divider != 0 {
-                self.cpu.add_clock_event(
-                    Box::new(Self::count),
-                    self.timer0.last_cycle + new_divider as u32 - self.cpu.cycles,
-                    AVRClockEventType::Count,
+                cpu.add_clock_event(
+                    timer_count_event(timer_id),
+                    timer.cycles_at(timer.last_cycle + new_divider as u64) - cpu.cycles,
+                    timer.config.count_event,
                 );
             }
-            return;
-        }
-        if reschedule && divider != 0 {
-            self.cpu.add_clock_event(
-                Box::new(ATMega328P::count),
-                self.timer0.last_cycle + divider as u32 - self.cpu.cycles,
-                AVRClockEventType::Count,
+        } else if reschedule && divider != 0 {
+            cpu.add_clock_event(
+                timer_count_event(timer_id),
+                timer.cycles_at(timer.last_cycle + divider as u64) - cpu.cycles,
+                timer.config.count_event,
             );
         }
+        self.adc_notify_trigger(adc_trigger);
     }
 }
 
 pub const TIMER_0_CONFIG: AVRTimerConfig = AVRTimerConfig {
+    bits: 8,
+    dividers: TIMER_01_DIVIDERS,
+    count_event: AVRClockEventType::Timer0Count,
+
+    capture_interrupt: 0, // not available
+    comp_a_interrupt: 0x1c,
+    comp_b_interrupt: 0x1e,
     ovf_interrupt: 0x20,
 
     TIFR: 0x35,
-    TCNT: 0x46,
     OCRA: 0x47,
+    OCRB: 0x48,
+    ICR: 0, // not available
+    TCNT: 0x46,
     TCCRA: 0x44,
     TCCRB: 0x45,
+    TCCRC: 0, // not available
     TIMSK: 0x6e,
+    ASSR: 0, // not available
+
+    comp_pin_a: (2, 6), // PD6
+    comp_pin_b: (2, 5), // PD5
+
+    TOV: 1,
+    OCFA: 2,
+    OCFB: 4,
+    ICF: 0, // not available
+
+    TOIE: 1,
+    OCIEA: 2,
+    OCIEB: 4,
+    ICIE: 0, // not available
+};
+
+pub const TIMER_1_CONFIG: AVRTimerConfig = AVRTimerConfig {
+    bits: 16,
+    dividers: TIMER_01_DIVIDERS,
+    count_event: AVRClockEventType::Timer1Count,
+
+    capture_interrupt: 0x14,
+    comp_a_interrupt: 0x16,
+    comp_b_interrupt: 0x18,
+    ovf_interrupt: 0x1a,
+
+    TIFR: 0x36,
+    OCRA: 0x88,
+    OCRB: 0x8a,
+    ICR: 0x86,
+    TCNT: 0x84,
+    TCCRA: 0x80,
+    TCCRB: 0x81,
+    TCCRC: 0x82,
+    TIMSK: 0x6f,
+    ASSR: 0, // not available
+
+    comp_pin_a: (0, 1), // PB1
+    comp_pin_b: (0, 2), // PB2
 
     TOV: 1,
+    OCFA: 2,
+    OCFB: 4,
+    ICF: 0x20,
 
     TOIE: 1,
+    OCIEA: 2,
+    OCIEB: 4,
+    ICIE: 0x20,
+};
+
+pub const TIMER_2_CONFIG: AVRTimerConfig = AVRTimerConfig {
+    bits: 8,
+    dividers: TIMER_2_DIVIDERS,
+    count_event: AVRClockEventType::Timer2Count,
+
+    capture_interrupt: 0, // not available
+    comp_a_interrupt: 0x0e,
+    comp_b_interrupt: 0x10,
+    ovf_interrupt: 0x12,
+
+    TIFR: 0x37,
+    OCRA: 0xb3,
+    OCRB: 0xb4,
+    ICR: 0, // not available
+    TCNT: 0xb2,
+    TCCRA: 0xb0,
+    TCCRB: 0xb1,
+    TCCRC: 0, // not available
+    TIMSK: 0x70,
+    ASSR: 0xb6,
+
+    comp_pin_a: (0, 3), // PB3
+    comp_pin_b: (2, 3), // PD3
+
+    TOV: 1,
+    OCFA: 2,
+    OCFB: 4,
+    ICF: 0, // not available
+
+    TOIE: 1,
+    OCIEA: 2,
+    OCIEB: 4,
+    ICIE: 0, // not available
 };
 
 #[cfg(test)]
 mod timer_tests {
     use crate::{
         atmega328p::{ATMega328P, DEFAULT_FREQ},
-        peripheral::timer::{CS00, CS01, TIMER_0_CONFIG},
+        peripheral::{
+            port::{PORTD_CONFIG, PinState},
+            timer::{
+                AS2, COMA0, COMA1, COMB1, CS00, CS01, CS10, TIMER_0_CONFIG, TIMER_1_CONFIG,
+                TIMER_2_CONFIG, WGM00, WGM01, WGM10, WGM11, WGM12, WGM13,
+            },
+        },
     };
 
+    /// Writes a 16-bit register, high byte first as required by the TEMP register
+    fn write_u16(atmega: &mut ATMega328P, addr: u8, value: u16) {
+        atmega.write_data(addr as u16 + 1, (value >> 8) as u8);
+        atmega.write_data(addr as u16, (value & 0xff) as u8);
+    }
+
+    /// Reads a 16-bit register, low byte first as required by the TEMP register
+    fn read_u16(atmega: &mut ATMega328P, addr: u8) -> u16 {
+        let lo = atmega.read_data(addr as u16) as u16;
+        let hi = atmega.read_data(addr as u16 + 1) as u16;
+        (hi << 8) | lo
+    }
+
+    fn timer1_flags(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[TIMER_1_CONFIG.TIFR as usize]
+    }
+
     #[test]
     fn timer_inc_when_tick_with_prescaler_1() {
         // Arrange
@@ -457,4 +1386,561 @@ mod timer_tests {
         assert_eq!(atmega.cpu.pc, 0); // unchanged
         assert_eq!(atmega.cpu.cycles, 2); // unchanged
     }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn timer0_OCR0A_compare_match_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_0_CONFIG.OCRA as u16, 0x20);
+        atmega.write_data(TIMER_0_CONFIG.TCNT as u16, 0x1f);
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00);
+        atmega.write_data(TIMER_0_CONFIG.TIMSK as u16, TIMER_0_CONFIG.OCIEA);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, TIMER_0_CONFIG.comp_a_interrupt as u32);
+        assert_eq!(
+            atmega.cpu.data[TIMER_0_CONFIG.TIFR as usize] & TIMER_0_CONFIG.OCFA,
+            0
+        ); // cleared by the interrupt
+    }
+
+    /// Clear Timer on Compare match, toggling OC0A (PD6) on every match
+    #[test]
+    fn timer0_ctc_mode_toggles_oc0a() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 6);
+        atmega.write_data(TIMER_0_CONFIG.OCRA as u16, 0x10);
+        atmega.write_data(TIMER_0_CONFIG.TCNT as u16, 0x0f);
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, COMA0 | WGM01); // mode 2, toggle OC0A
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00);
+
+        // Act & Assert
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::Low));
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 0x10);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::High));
+
+        atmega.cpu.cycles = 3;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 0); // cleared, no TOV
+        assert_eq!(
+            atmega.cpu.data[TIMER_0_CONFIG.TIFR as usize] & TIMER_0_CONFIG.TOV,
+            0
+        );
+
+        atmega.cpu.cycles = 3 + 0x10;
+        atmega.tick(None);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::Low));
+    }
+
+    /// Non-inverting fast PWM on OC0A (PD6): set at BOTTOM, cleared on compare match
+    #[test]
+    fn timer0_fast_pwm_waveform_on_oc0a() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 6);
+        atmega.write_data(TIMER_0_CONFIG.OCRA as u16, 0x80);
+        atmega.write_data(TIMER_0_CONFIG.TCNT as u16, 0xff);
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, COMA1 | WGM01 | WGM00); // mode 3
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+
+        // Act & Assert
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 0);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::High));
+
+        atmega.cpu.cycles = 2 + 0x7f;
+        atmega.tick(None);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::High));
+
+        atmega.cpu.cycles = 2 + 0x80;
+        atmega.tick(None);
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::Low));
+    }
+
+    /// In the PWM modes, OCR0B is double buffered and updated at BOTTOM (fast PWM) or TOP
+    /// (phase correct PWM)
+    #[test]
+    fn timer0_pwm_double_buffers_ocr0b() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_0_CONFIG.TCNT as u16, 0xfe);
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, COMB1 | WGM00); // mode 1
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+
+        // Act & Assert
+        atmega.write_data(TIMER_0_CONFIG.OCRB as u16, 0x40);
+        assert_eq!(atmega.timers[0].ocrb, 0);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 0xff); // TOP
+        assert_eq!(atmega.timers[0].ocrb, 0x40);
+    }
+
+    /// The force output compare bit applies a compare match to OC0A without raising OCF0A
+    #[test]
+    fn timer0_force_output_compare() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 6);
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, COMA1 | COMA0); // set OC0A on match
+
+        // Act
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 1 << 7); // FOC0A
+
+        // Assert
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::High));
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCCRB as u16), 0);
+        assert_eq!(atmega.cpu.data[TIMER_0_CONFIG.TIFR as usize], 0);
+    }
+
+    /// Disconnecting OC0A gives the pin back to PORTD
+    #[test]
+    fn timer0_disconnect_oc0a() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(PORTD_CONFIG.DDR as u16, 1 << 6);
+        atmega.write_data(PORTD_CONFIG.PORT as u16, 1 << 6);
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, COMA1 | WGM01); // clear OC0A on match
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 1 << 7); // FOC0A
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::Low));
+
+        // Act
+        atmega.write_data(TIMER_0_CONFIG.TCCRA as u16, WGM01);
+
+        // Assert
+        assert!(matches!(atmega.port_pin_state("D", 6), PinState::High));
+    }
+
+    #[test]
+    fn timer_counts_across_u32_cycles() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let start = u32::MAX as u64 - 1;
+        atmega.cpu.cycles = start;
+
+        // Act & Assert
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00); // set prescaler to 1
+        atmega.cpu.cycles = start + 1;
+        atmega.tick(None); // first tick updates divider
+        atmega.cpu.cycles = start + 2; // 2^32
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 1);
+
+        atmega.cpu.cycles = start + 12;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 11);
+    }
+
+    #[test]
+    fn timer1_counts_to_0xffff_then_overflows() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act & Assert
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0xfffe);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10); // set prescaler to 1
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0xffff);
+        assert_eq!(timer1_flags(&atmega) & TIMER_1_CONFIG.TOV, 0);
+
+        atmega.cpu.cycles = 3;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0);
+        assert_eq!(
+            timer1_flags(&atmega) & TIMER_1_CONFIG.TOV,
+            TIMER_1_CONFIG.TOV
+        );
+    }
+
+    /// Reading TCNT1L latches the high byte, so TCNT1H reads the same 16-bit value
+    #[test]
+    fn timer1_read_high_byte_from_temp() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x12fe);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 3;
+
+        // Act
+        let lo = atmega.read_data(TIMER_1_CONFIG.TCNT as u16);
+        atmega.cpu.cycles += 0x100; // the counter moves on to 0x14xx
+        let hi = atmega.read_data(TIMER_1_CONFIG.TCNT as u16 + 1);
+
+        // Assert
+        assert_eq!(lo, 0x00);
+        assert_eq!(hi, 0x13);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn timer1_OCR1A_compare_match_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.OCRA, 0x1234);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x1233);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10);
+        atmega.write_data(TIMER_1_CONFIG.TIMSK as u16, TIMER_1_CONFIG.OCIEA);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, TIMER_1_CONFIG.comp_a_interrupt as u32);
+        assert_eq!(atmega.cpu.cycles, 4);
+        assert_eq!(timer1_flags(&atmega) & TIMER_1_CONFIG.OCFA, 0); // cleared by the interrupt
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn timer1_OCR1B_compare_match_flag() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.OCRB, 0x0200);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x01ff);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10);
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(
+            timer1_flags(&atmega),
+            TIMER_1_CONFIG.OCFB // OCFA is not set, as OCR1A is 0
+        );
+    }
+
+    /// Clear Timer on Compare match, with OCR1A as TOP
+    #[test]
+    fn timer1_ctc_mode_with_ocr1a() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.OCRA, 0x10);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x0f);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, WGM12 | CS10); // mode 4
+
+        // Act & Assert
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0x10);
+        assert_eq!(timer1_flags(&atmega), TIMER_1_CONFIG.OCFA);
+
+        atmega.cpu.cycles = 3;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0);
+        assert_eq!(
+            timer1_flags(&atmega),
+            TIMER_1_CONFIG.OCFA | TIMER_1_CONFIG.OCFB // OCR1B is 0, and no TOV as TOP is not MAX
+        );
+    }
+
+    /// Clear Timer on Compare match, with ICR1 as TOP
+    #[test]
+    fn timer1_ctc_mode_with_icr1() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.ICR, 0x20);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x20);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, WGM13 | WGM12 | CS10); // mode 12
+        atmega.write_data(TIMER_1_CONFIG.TIMSK as u16, TIMER_1_CONFIG.ICIE);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.ICR), 0x20);
+        assert_eq!(atmega.timers[1].tcnt, 0);
+        assert_eq!(atmega.cpu.pc, TIMER_1_CONFIG.capture_interrupt as u32);
+    }
+
+    #[test]
+    fn timer1_fast_pwm_10_bit_overflow() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0x3ff);
+        atmega.write_data(TIMER_1_CONFIG.TCCRA as u16, WGM11 | WGM10);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, WGM12 | CS10); // mode 7
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0);
+        assert_eq!(
+            timer1_flags(&atmega) & TIMER_1_CONFIG.TOV,
+            TIMER_1_CONFIG.TOV
+        );
+    }
+
+    #[test]
+    fn timer1_phase_correct_pwm_8_bit() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0xfe);
+        atmega.write_data(TIMER_1_CONFIG.TCCRA as u16, WGM10);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10); // mode 1
+
+        // Act & Assert
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0xff); // TOP
+        atmega.cpu.cycles = 3;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0xfe); // counting down
+        assert_eq!(timer1_flags(&atmega) & TIMER_1_CONFIG.TOV, 0);
+
+        atmega.cpu.cycles = 3 + 0xfe;
+        atmega.tick(None);
+        assert_eq!(read_u16(&mut atmega, TIMER_1_CONFIG.TCNT), 0); // BOTTOM
+        assert_eq!(
+            timer1_flags(&atmega) & TIMER_1_CONFIG.TOV,
+            TIMER_1_CONFIG.TOV
+        );
+    }
+
+    /// In the PWM modes, OCR1A is double buffered and updated at BOTTOM
+    #[test]
+    fn timer1_fast_pwm_double_buffers_ocr1a() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.ICR, 0x100);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0xff);
+        atmega.write_data(TIMER_1_CONFIG.TCCRA as u16, WGM11);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, WGM13 | WGM12 | CS10); // mode 14
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+
+        // Act & Assert
+        write_u16(&mut atmega, TIMER_1_CONFIG.OCRA, 0x80);
+        assert_eq!(atmega.timers[1].ocra, 0);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(atmega.timers[1].ocra, 0); // TCNT1 at TOP
+        atmega.cpu.cycles = 3;
+        atmega.tick(None);
+        assert_eq!(atmega.timers[1].tcnt, 0);
+        assert_eq!(atmega.timers[1].ocra, 0x80); // updated at BOTTOM
+    }
+
+    /// In the 8-bit fast PWM mode, the OCR1AH bits beyond TOP are not writable
+    #[test]
+    #[allow(non_snake_case)]
+    fn timer1_mask_OCR1AH_in_8_bit_mode() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_1_CONFIG.TCCRA as u16, WGM10);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, WGM12); // mode 5
+
+        // Act
+        atmega.write_data(TIMER_1_CONFIG.OCRA as u16 + 1, 0xff);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[TIMER_1_CONFIG.OCRA as usize + 1], 0);
+        assert_eq!(atmega.timers[1].high_byte_temp, 0);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn clear_TOV1_by_writing_one() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        write_u16(&mut atmega, TIMER_1_CONFIG.TCNT, 0xffff);
+        atmega.write_data(TIMER_1_CONFIG.TCCRB as u16, CS10);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+        assert_eq!(
+            timer1_flags(&atmega) & TIMER_1_CONFIG.TOV,
+            TIMER_1_CONFIG.TOV
+        );
+
+        // Act
+        atmega.write_data(TIMER_1_CONFIG.TIFR as u16, TIMER_1_CONFIG.TOV);
+
+        // Assert
+        assert_eq!(timer1_flags(&atmega) & TIMER_1_CONFIG.TOV, 0);
+    }
+
+    #[test]
+    fn timer2_inc_every_32_ticks() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, CS01 | CS00); // set prescaler to 32
+        atmega.cpu.cycles = 1;
+        atmega.tick(None); // first tick updates divider
+        atmega.cpu.cycles = 1 + 32;
+        atmega.tick(None); // increment count
+
+        // Assert
+        let count = atmega.read_data(TIMER_2_CONFIG.TCNT as u16);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn timer2_compare_match_interrupts() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_2_CONFIG.OCRA as u16, 0x20);
+        atmega.write_data(TIMER_2_CONFIG.OCRB as u16, 0x10);
+        atmega.write_data(TIMER_2_CONFIG.TCNT as u16, 0x0f);
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, CS00);
+        atmega.write_data(
+            TIMER_2_CONFIG.TIMSK as u16,
+            TIMER_2_CONFIG.OCIEA | TIMER_2_CONFIG.OCIEB,
+        );
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+
+        // Act
+        atmega.cpu.cycles = 1;
+        atmega.tick(None);
+        atmega.cpu.cycles = 2;
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, TIMER_2_CONFIG.comp_b_interrupt as u32);
+        assert_eq!(
+            atmega.cpu.data[TIMER_2_CONFIG.TIFR as usize],
+            0 // OCF2B cleared by the interrupt, OCF2A not reached yet
+        );
+    }
+
+    /// The 32.768 kHz crystal with a prescaler of 128 overflows timer 2 once per second
+    #[test]
+    fn timer2_async_overflow_every_second() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, AS2);
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, 0x5); // set prescaler to 128
+        atmega.write_data(TIMER_2_CONFIG.TIMSK as u16, TIMER_2_CONFIG.TOIE);
+        atmega.cpu.set_sreg(1 << 7); // enable global interrupt
+        atmega.cpu.cycles = 1;
+        atmega.tick(None); // first tick updates divider
+
+        // Act & Assert
+        atmega.cpu.cycles = DEFAULT_FREQ as u64 - 1;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), 0xff);
+        assert_eq!(atmega.cpu.pc, 0);
+
+        atmega.cpu.cycles = DEFAULT_FREQ as u64;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), 0);
+        assert_eq!(atmega.cpu.pc, TIMER_2_CONFIG.ovf_interrupt as u32);
+    }
+
+    /// Setting AS2 while the prescaler runs on the CPU clock restarts it on the crystal
+    #[test]
+    fn timer2_switch_to_async_clock() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, 0x4); // set prescaler to 64, as init()
+        atmega.cpu.cycles = 1;
+        atmega.tick(None); // first tick updates divider
+        atmega.cpu.cycles = 1_000_000;
+        atmega.tick(None);
+        let count = atmega.read_data(TIMER_2_CONFIG.TCNT as u16);
+
+        // Act
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, AS2);
+        atmega.cpu.cycles += 1;
+        atmega.tick(None); // updates divider
+
+        // Assert
+        atmega.cpu.cycles = 1_031_249; // 64 crystal ticks later
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), count);
+        atmega.cpu.cycles = 1_031_250;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), count + 1);
+    }
+
+    /// Clearing AS2 restarts the prescaler on the CPU clock, without spurious counts
+    #[test]
+    fn timer2_switch_back_to_sync_clock() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, AS2);
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, 0x5); // set prescaler to 128
+        atmega.cpu.cycles = 1;
+        atmega.tick(None); // first tick updates divider
+        atmega.cpu.cycles = DEFAULT_FREQ as u64 - 1;
+        atmega.tick(None);
+
+        // Act
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, 0);
+        atmega.cpu.cycles += 1;
+        atmega.tick(None); // updates divider
+
+        // Assert
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), 0xff);
+        assert_eq!(atmega.cpu.data[TIMER_2_CONFIG.TIFR as usize], 0);
+        atmega.cpu.cycles += 127;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), 0xff);
+        atmega.cpu.cycles += 1;
+        atmega.tick(None);
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.TCNT as u16), 0);
+        assert_eq!(
+            atmega.cpu.data[TIMER_2_CONFIG.TIFR as usize] & TIMER_2_CONFIG.TOV,
+            TIMER_2_CONFIG.TOV
+        );
+    }
+
+    /// ASSR busy flags are read-only, and writes to the timer registers take effect immediately
+    #[test]
+    #[allow(non_snake_case)]
+    fn timer2_ASSR_busy_flags_read_zero() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, 0xff);
+
+        // Assert
+        assert_eq!(atmega.read_data(TIMER_2_CONFIG.ASSR as u16), 0x60); // EXCLK | AS2
+        assert!(atmega.timers[2].async_clock);
+    }
 }
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use crate::{
-    atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
+    atmega328p::{ATMega328P, PeripheralMemoryReadHook, PeripheralMemoryWriteHook},
+    clock::AVRClockEventType,
     cpu::CPU,
     flog,
     interrupt::AVRInterruptConfig,
@@ -9,20 +10,32 @@ use crate::{
 };
 
 // Register consts
+pub const UCSRA_RXC: u8 = 0x80; // USART Receive Complete
 pub const UCSRA_TXC: u8 = 0x40; // USART Transmit Complete, 1 << 6
 const UCSRA_UDRE: u8 = 0x20; // USART Data Register Empty
+pub const UCSRA_FE: u8 = 0x10; // Frame Error
+pub const UCSRA_DOR: u8 = 0x8; // Data OverRun
+const UCSRA_UPE: u8 = 0x4; // USART Parity Error
 pub const UCSRA_U2X: u8 = 0x2; // Double the USART Transmission Speed
+const UCSRA_MPCM: u8 = 0x1; // Multi-processor Communication Mode
+pub const UCSRB_RXCIE: u8 = 0x80; // RX Complete Interrupt Enable
 const UCSRB_TXCIE: u8 = 0x40; // TX Complete Interrupt Enable
 const UCSRB_UDRIE: u8 = 0x20; // USART Data Register Empty Interrupt Enable
+pub const UCSRB_RXEN: u8 = 0x10; // Receiver Enable
 pub const UCSRB_TXEN: u8 = 0x8; // Transmitter Enable
 const UCSRB_UCSZ2: u8 = 1 << 2; // Character Size 2
+pub const UCSRB_RXB8: u8 = 0x2; // Receive Data Bit 8
 const UCSRC_UPM1: u8 = 0x20; // Parity Mode 1
 const UCSRC_USBS: u8 = 0x8; // Stop Bit Select
 const UCSRC_UCSZ1: u8 = 0x4; // Character Size 1
 const UCSRC_UCSZ0: u8 = 0x2; // Character Size 0
 
+const RX_FIFO_SIZE: usize = 2; // characters held by the receive buffer, besides the shift register
+
+#[derive(Clone)]
 #[allow(non_snake_case)]
 pub struct USARTConfig {
+    pub rx_complete_interrupt: u8, // interrupt hander address on receive complete for a frame
     pub data_register_empty_interrupt: u8, // interrupt hander address on data register empty
     pub tx_complete_interrupt: u8, // interrupt hander address on transmit complete for a frame
 
@@ -35,6 +48,7 @@ pub struct USARTConfig {
 }
 
 pub const USART0_CONFIG: USARTConfig = USARTConfig {
+    rx_complete_interrupt: 0x24,
     data_register_empty_interrupt: 0x26,
     tx_complete_interrupt: 0x28,
     UCSRA: 0xc0,
@@ -45,19 +59,65 @@ pub const USART0_CONFIG: USARTConfig = USARTConfig {
     UDR: 0xc6,
 };
 
+/// Receives each character transmitted by the USART, once it has been shifted out
+pub type USARTOutput = Box<dyn FnMut(u8)>;
+
+/// Output that logs the transmitted text line by line, with `flog!`
+pub fn line_logger() -> USARTOutput {
+    let mut buf: Vec<u8> = Vec::new();
+    Box::new(move |value| {
+        buf.push(value);
+        if value == b'\n' || value == b'\r' {
+            if buf.len() > 1 {
+                // ignore "\r" left from"\n\r"
+                flog!(
+                    "{}",
+                    String::from_utf8_lossy(&buf)
+                        .replace("\n", "")
+                        .replace("\r", "")
+                );
+            }
+            buf.clear();
+        }
+    })
+}
+
+/// A character received by the USART, with its error flags
+#[derive(Debug, Clone, Copy)]
+struct RxFrame {
+    data: u16, // up to 9 data bits
+    frame_error: bool,
+    data_overrun: bool, // characters were lost after this one
+}
+
 /// Note: only the Asynchronous mode is implemented
 pub struct AVRUSART {
     pub config: USARTConfig,
     pub freq_hz: usize, // clock frequency
 
+    pub rxc: AVRInterruptConfig,
     pub udre: AVRInterruptConfig,
     pub txc: AVRInterruptConfig,
 
-    pub buf: Vec<u8>, // buffer for string outputs
+    outputs: Vec<USARTOutput>, // line_logger() by default
+
+    rx_input: VecDeque<RxFrame>, // characters sent by the host, not yet shifted in
+    rx_fifo: VecDeque<RxFrame>,  // receive buffer, read through UDR
+    rx_shift: Option<RxFrame>,   // character waiting in the shift register for the buffer
+    rx_busy: bool,               // a character is being shifted in
 }
 
 impl AVRUSART {
     pub fn new(config: USARTConfig, freq_hz: usize) -> Self {
+        let rxc = AVRInterruptConfig {
+            address: config.rx_complete_interrupt,
+            flag_register: config.UCSRA as u16,
+            flag_mask: UCSRA_RXC,
+            enable_register: config.UCSRB as u16,
+            enable_mask: UCSRB_RXCIE,
+            inverse_flag: false,
+            constant: true, // cleared by reading UDR
+        };
         let urde = AVRInterruptConfig {
             address: config.data_register_empty_interrupt,
             flag_register: config.UCSRA as u16,
@@ -65,6 +125,7 @@ impl AVRUSART {
             enable_register: config.UCSRB as u16,
             enable_mask: UCSRB_UDRIE,
             inverse_flag: false,
+            constant: false,
         };
         let txc = AVRInterruptConfig {
             address: config.tx_complete_interrupt,
@@ -73,32 +134,81 @@ impl AVRUSART {
             enable_register: config.UCSRB as u16,
             enable_mask: UCSRB_TXCIE,
             inverse_flag: false,
+            constant: false,
         };
         Self {
             config,
             freq_hz,
+            rxc,
             udre: urde,
             txc,
-            buf: Vec::new(),
+            outputs: vec![line_logger()],
+            rx_input: VecDeque::new(),
+            rx_fifo: VecDeque::new(),
+            rx_shift: None,
+            rx_busy: false,
         }
     }
 
+    /// Puts the USART back in its reset state, keeping the outputs. The characters sent by the
+    /// host and not yet received are dropped.
+    pub fn reset(&mut self, cpu: &mut CPU) {
+        let outputs = std::mem::take(&mut self.outputs);
+        *self = Self {
+            outputs,
+            ..Self::new(self.config.clone(), self.freq_hz)
+        };
+        cpu.data[self.config.UCSRA as usize] = UCSRA_UDRE;
+        cpu.data[self.config.UCSRC as usize] = UCSRC_UCSZ1 | UCSRC_UCSZ0; // 8 bits
+    }
+
+    /// USART Control and Status Register A handler. The status flags are read-only, except TXC
+    /// which is cleared by writing a one to it.
+    pub fn add_ucsra_handler(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.UCSRA as u16,
+            Box::new(|atmega, value, _, addr, _| {
+                let txc = atmega.usart.txc;
+                atmega.cpu.clear_interrupt_by_flag(&txc, value);
+                let status = UCSRA_RXC | UCSRA_TXC | UCSRA_UDRE | UCSRA_FE | UCSRA_DOR | UCSRA_UPE;
+                let status = atmega.cpu.get_data(addr) & status;
+                atmega
+                    .cpu
+                    .set_data(addr, status | (value & (UCSRA_U2X | UCSRA_MPCM)));
+                true
+            }),
+        );
+    }
+
     /// USART Control and Status Register handler
     pub fn add_ucsrb_handler(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
         write_hooks.insert(
             self.config.UCSRB as u16,
             Box::new(|atmega, value, old_value, _, _| {
-                // cpu.update_interrupt_enable(cpu.usart.rxc, value);
+                atmega.cpu.update_interrupt_enable(atmega.usart.rxc, value);
                 atmega.cpu.update_interrupt_enable(atmega.usart.udre, value);
                 atmega.cpu.update_interrupt_enable(atmega.usart.txc, value);
-                // if value & UCSRB_RXEN && old_value & UCSRB_RXEN {
-                //     cpu.clear_interrupt(cpu.usart.rxc, true);
-                // }
                 if value & UCSRB_TXEN != 0 && old_value & UCSRB_TXEN == 0 {
                     // Enabling the transmission - mark UDR as empty
                     atmega.cpu.set_interrupt_flag(atmega.usart.udre);
                 }
-                atmega.cpu.data[atmega.usart.config.UCSRB as usize] = value;
+                // RXB8 is read-only
+                atmega.cpu.data[atmega.usart.config.UCSRB as usize] =
+                    (value & !UCSRB_RXB8) | (old_value & UCSRB_RXB8);
+                if value & UCSRB_RXEN == 0 && old_value & UCSRB_RXEN != 0 {
+                    // Disabling the receiver flushes the receive buffer, and aborts the
+                    // character being shifted in. It is received again once re-enabled.
+                    atmega
+                        .cpu
+                        .clear_clock_event(AVRClockEventType::USART0Receive);
+                    atmega.usart.rx_busy = false;
+                    atmega.usart.rx_fifo.clear();
+                    atmega.usart.rx_shift = None;
+                    atmega.usart_update_rx_status();
+                }
+                if value & UCSRB_RXEN != 0 && old_value & UCSRB_RXEN == 0 {
+                    atmega.usart_receive_next();
+                }
                 // cpu.onConfigurationChange
 
                 true
@@ -106,36 +216,47 @@ impl AVRUSART {
         );
     }
 
+    /// Reading UDR pops a character from the receive buffer, which makes room for the character
+    /// waiting in the shift register
+    pub fn add_udr_read_handler(&self, read_hooks: &mut HashMap<u16, PeripheralMemoryReadHook>) {
+        read_hooks.insert(
+            self.config.UDR as u16,
+            Box::new(|atmega, addr| {
+                let data = match atmega.usart.rx_fifo.pop_front() {
+                    Some(frame) => match atmega.usart_bits_per_char() {
+                        bits @ 5..=7 => frame.data as u8 & ((1 << bits) - 1),
+                        _ => frame.data as u8,
+                    },
+                    None => 0,
+                };
+                if let Some(frame) = atmega.usart.rx_shift.take() {
+                    atmega.usart.rx_fifo.push_back(frame);
+                }
+                atmega.cpu.set_data(addr, data);
+                atmega.usart_update_rx_status();
+                data
+            }),
+        );
+    }
+
     pub fn add_udr_handler(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
         write_hooks.insert(
             self.config.UDR as u16,
             Box::new(|atmega, value, _, _, _| {
-                atmega.usart.buf.push(value);
-                if value == b'\n' || value == b'\r' {
-                    if atmega.usart.buf.len() > 1 {
-                        // ignore "\r" left from"\n\r"
-                        flog!(
-                            "{}",
-                            str::from_utf8(&atmega.usart.buf)
-                                .unwrap()
-                                .replace("\n", "")
-                                .replace("\r", "")
-                        );
-                    }
-                    atmega.usart.buf = Vec::new();
-                }
-
                 atmega.cpu.add_clock_event(
-                    Box::new(|atmega: &mut ATMega328P, _, _, _| {
+                    Box::new(move |atmega: &mut ATMega328P, _, _, _| {
+                        for output in atmega.usart.outputs.iter_mut() {
+                            output(value);
+                        }
                         atmega.cpu.set_interrupt_flag(atmega.usart.udre);
                         atmega.cpu.set_interrupt_flag(atmega.usart.txc);
                     }),
                     atmega.usart_cycles_per_char(),
-                    crate::clock::AVRClockEventType::USART,
+                    AVRClockEventType::USART0,
                 );
-                let txc = atmega.usart.txc.clone();
+                let txc = atmega.usart.txc;
                 atmega.cpu.clear_interrupt(&txc, true);
-                let urde = atmega.usart.udre.clone();
+                let urde = atmega.usart.udre;
                 atmega.cpu.clear_interrupt(&urde, true);
 
                 false
@@ -143,11 +264,21 @@ impl AVRUSART {
         );
     }
 
-    pub fn stop_bits(&self, data: &Vec<u8>) -> usize {
+    /// Adds an output, called with every transmitted character
+    pub fn add_output(&mut self, output: USARTOutput) {
+        self.outputs.push(output);
+    }
+
+    /// Removes all the outputs, including the default line logger
+    pub fn clear_outputs(&mut self) {
+        self.outputs.clear();
+    }
+
+    pub fn stop_bits(&self, data: &[u8]) -> usize {
         ternary!(data[self.config.UCSRC as usize] & UCSRC_USBS, 2, 1)
     }
 
-    pub fn bits_per_char(&self, data: &Vec<u8>) -> usize {
+    pub fn bits_per_char(&self, data: &[u8]) -> usize {
         let ucsz: u8 = ((data[self.config.UCSRC as usize] & (UCSRC_UCSZ1 | UCSRC_UCSZ0)) >> 1)
             | (data[self.config.UCSRB as usize] & UCSRB_UCSZ2);
         match ucsz {
@@ -161,25 +292,25 @@ impl AVRUSART {
     }
 
     #[allow(non_snake_case)]
-    pub fn UBRR(&self, data: &Vec<u8>) -> usize {
+    pub fn UBRR(&self, data: &[u8]) -> usize {
         let UBRRH = self.config.UBRRH;
         let UBRRL = self.config.UBRRL;
         (data[UBRRH as usize] as usize) << 8 | data[UBRRL as usize] as usize
     }
 
-    pub fn multiplier(&self, data: &Vec<u8>) -> usize {
+    pub fn multiplier(&self, data: &[u8]) -> usize {
         ternary!(data[self.config.UCSRA as usize] & UCSRA_U2X, 8, 16)
     }
 
-    pub fn parity_enabled(&self, data: &Vec<u8>) -> bool {
+    pub fn parity_enabled(&self, data: &[u8]) -> bool {
         data[self.config.UCSRC as usize] & UCSRC_UPM1 != 0
     }
 
-    pub fn baud_rate(&self, data: &Vec<u8>) -> usize {
+    pub fn baud_rate(&self, data: &[u8]) -> usize {
         self.freq_hz / (self.multiplier(data) * (1 + self.UBRR(data)))
     }
 
-    pub fn cycles_per_char(&self, data: &Vec<u8>) -> usize {
+    pub fn cycles_per_char(&self, data: &[u8]) -> usize {
         let symbols_per_char = 1
             + self.bits_per_char(data)
             + if self.parity_enabled(data) { 1 } else { 0 }
@@ -206,28 +337,149 @@ impl ATMega328P {
         self.usart.baud_rate(&self.cpu.data)
     }
 
-    pub fn usart_cycles_per_char(&self) -> u32 {
-        self.usart.cycles_per_char(&self.cpu.data) as u32
+    pub fn usart_cycles_per_char(&self) -> u64 {
+        self.usart.cycles_per_char(&self.cpu.data) as u64
+    }
+}
+
+/// USART receive path
+impl ATMega328P {
+    /// Sends characters to the USART receiver, as if written by a host on the RX line.
+    /// Each character takes a frame time to be shifted in. Characters sent while the receiver is
+    /// disabled are held until the firmware enables it.
+    pub fn usart_write_input(&mut self, data: &[u8]) {
+        let data: Vec<u16> = data.iter().map(|&data| data as u16).collect();
+        self.usart_write_input_9_bits(&data);
+    }
+
+    /// Like `usart_write_input`, with the 9 bits characters of the 9-bit mode. The ninth bit is
+    /// read from RXB8.
+    pub fn usart_write_input_9_bits(&mut self, data: &[u16]) {
+        self.usart.rx_input.extend(data.iter().map(|&data| RxFrame {
+            data: data & 0x1ff,
+            frame_error: false,
+            data_overrun: false,
+        }));
+        self.usart_receive_next();
+    }
+
+    /// Sends a break condition (RX held low for a whole frame), received as a zero character
+    /// with a frame error
+    pub fn usart_write_break(&mut self) {
+        self.usart.rx_input.push_back(RxFrame {
+            data: 0,
+            frame_error: true,
+            data_overrun: false,
+        });
+        self.usart_receive_next();
+    }
+
+    /// Number of host characters not yet received by the USART
+    pub fn usart_pending_input(&self) -> usize {
+        self.usart.rx_input.len()
+    }
+
+    /// Starts shifting in the next host character, if the receiver is enabled and idle. The
+    /// character is lost if the receive buffer and the shift register are both full when its
+    /// start bit arrives, which sets DOR on the character waiting in the shift register.
+    fn usart_receive_next(&mut self) {
+        let rx_enabled = self.cpu.data[self.usart.config.UCSRB as usize] & UCSRB_RXEN != 0;
+        if self.usart.rx_busy || !rx_enabled || self.usart.rx_input.is_empty() {
+            return;
+        }
+        self.usart.rx_busy = true;
+        let lost = match self.usart.rx_shift.as_mut() {
+            Some(waiting) => {
+                waiting.data_overrun = true;
+                true
+            }
+            None => false,
+        };
+        self.cpu.add_clock_event(
+            Box::new(move |atmega: &mut ATMega328P, _, _, _| {
+                atmega.usart.rx_busy = false;
+                if let Some(frame) = atmega.usart.rx_input.pop_front()
+                    && !lost
+                {
+                    atmega.usart_receive_frame(frame);
+                }
+                atmega.usart_receive_next();
+            }),
+            self.usart_cycles_per_char(),
+            AVRClockEventType::USART0Receive,
+        );
+    }
+
+    /// Moves a character from the shift register to the receive buffer. If the buffer is full, it
+    /// waits in the shift register until the firmware reads UDR.
+    fn usart_receive_frame(&mut self, frame: RxFrame) {
+        if self.usart.rx_fifo.len() < RX_FIFO_SIZE {
+            self.usart.rx_fifo.push_back(frame);
+        } else {
+            self.usart.rx_shift = Some(frame);
+        }
+        self.usart_update_rx_status();
+    }
+
+    /// Updates RXC, FE, DOR and RXB8 from the character at the head of the receive buffer
+    fn usart_update_rx_status(&mut self) {
+        let ucsra = self.usart.config.UCSRA as usize;
+        let ucsrb = self.usart.config.UCSRB as usize;
+        self.cpu.data[ucsra] &= !(UCSRA_FE | UCSRA_DOR);
+        self.cpu.data[ucsrb] &= !UCSRB_RXB8;
+        let rxc = self.usart.rxc;
+        match self.usart.rx_fifo.front() {
+            Some(frame) => {
+                if frame.frame_error {
+                    self.cpu.data[ucsra] |= UCSRA_FE;
+                }
+                if frame.data_overrun {
+                    self.cpu.data[ucsra] |= UCSRA_DOR;
+                }
+                if frame.data & 0x100 != 0 && self.usart_bits_per_char() == 9 {
+                    self.cpu.data[ucsrb] |= UCSRB_RXB8;
+                }
+                self.cpu.set_interrupt_flag(rxc);
+            }
+            None => self.cpu.clear_interrupt(&rxc, true),
+        }
     }
 }
 
 #[cfg(test)]
 mod usart_tests {
+    use std::{cell::RefCell, rc::Rc};
+
     use crate::{
         atmega328p::{ATMega328P, DEFAULT_FREQ},
         peripheral::usart::{
-            UCSRA_TXC, UCSRA_U2X, UCSRA_UDRE, UCSRB_TXCIE, UCSRB_TXEN, UCSRB_UCSZ2, UCSRB_UDRIE,
+            UCSRA_DOR, UCSRA_FE, UCSRA_RXC, UCSRA_TXC, UCSRA_U2X, UCSRA_UDRE, UCSRB_RXB8,
+            UCSRB_RXCIE, UCSRB_RXEN, UCSRB_TXCIE, UCSRB_TXEN, UCSRB_UCSZ2, UCSRB_UDRIE,
             UCSRC_UCSZ0, UCSRC_UCSZ1, UCSRC_USBS, USART0_CONFIG,
         },
     };
 
+    /// Enables the receiver with 8 data bits, 1 stop bit and no parity
+    fn enable_receiver(atmega: &mut ATMega328P, ucsrb: u8) {
+        atmega.write_data(USART0_CONFIG.UCSRC as u16, UCSRC_UCSZ1 | UCSRC_UCSZ0);
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_RXEN | ucsrb);
+    }
+
+    fn ucsra(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[USART0_CONFIG.UCSRA as usize]
+    }
+
+    fn ucsrb(atmega: &ATMega328P) -> u8 {
+        atmega.cpu.data[USART0_CONFIG.UCSRB as usize]
+    }
+
     /// TX Complete Interrupt Enable bit
     #[test]
     #[allow(non_snake_case)]
     fn TXCIE_trigger() {
         // Arrange
         let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
-        let cycles: u32 = 1_000_000;
+        let cycles: u64 = 1_000_000;
 
         // Act
         atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXCIE | UCSRB_TXEN);
@@ -339,4 +591,211 @@ mod usart_tests {
         atmega.write_data(USART0_CONFIG.UCSRC as u16, UCSRC_USBS);
         assert_eq!(atmega.usart_stop_bits(), 2);
     }
+
+    /// RX Complete Interrupt Enable bit, triggered once a character is shifted in
+    #[test]
+    #[allow(non_snake_case)]
+    fn RXCIE_trigger_after_frame_time() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_receiver(&mut atmega, UCSRB_RXCIE);
+        atmega.cpu.set_sreg(1 << 7);
+        let frame_cycles = atmega.usart_cycles_per_char(); // 10 symbols
+
+        // Act & Assert
+        atmega.usart_write_input(b"a");
+        atmega.cpu.cycles = frame_cycles - 1;
+        atmega.tick(None);
+        assert_eq!(atmega.cpu.pc, 0);
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, 0);
+
+        atmega.cpu.cycles = frame_cycles;
+        atmega.tick(None);
+        assert_eq!(atmega.cpu.pc, USART0_CONFIG.rx_complete_interrupt as u32);
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, UCSRA_RXC); // only cleared by reading UDR
+    }
+
+    /// Reading UDR returns the received characters in order, and clears RXC once all are read
+    #[test]
+    #[allow(non_snake_case)]
+    fn read_UDR_from_receive_buffer() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_receiver(&mut atmega, 0);
+        let frame_cycles = atmega.usart_cycles_per_char();
+
+        // Act & Assert
+        atmega.usart_write_input(b"ok");
+        for i in 1..=2 {
+            atmega.cpu.cycles = i * frame_cycles;
+            atmega.tick(None);
+        }
+        assert_eq!(atmega.usart_pending_input(), 0);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'o');
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, UCSRA_RXC);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'k');
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, 0);
+    }
+
+    /// A third character arriving while the receive buffer is full waits in the shift register
+    #[test]
+    fn third_character_waits_in_shift_register() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_receiver(&mut atmega, 0);
+        let frame_cycles = atmega.usart_cycles_per_char();
+        atmega.usart_write_input(b"abc");
+        for i in 1..=3 {
+            atmega.cpu.cycles = i * frame_cycles;
+            atmega.tick(None);
+        }
+
+        // Act & Assert
+        for data in b"abc" {
+            assert_eq!(ucsra(&atmega) & (UCSRA_DOR | UCSRA_RXC), UCSRA_RXC);
+            assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), *data);
+        }
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, 0);
+    }
+
+    /// The start bit of a fourth character, while the receive buffer and the shift register are
+    /// full, sets DOR on the character in the shift register. The fourth character is lost.
+    #[test]
+    #[allow(non_snake_case)]
+    fn DOR_on_receive_buffer_overrun() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_receiver(&mut atmega, 0);
+        let frame_cycles = atmega.usart_cycles_per_char();
+        atmega.usart_write_input(b"abcd");
+        for i in 1..=3 {
+            atmega.cpu.cycles = i * frame_cycles;
+            atmega.tick(None);
+        }
+
+        // Act & Assert
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'a');
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'b');
+        assert_eq!(ucsra(&atmega) & UCSRA_DOR, UCSRA_DOR);
+        atmega.cpu.cycles = 4 * frame_cycles;
+        atmega.tick(None);
+        assert_eq!(atmega.usart_pending_input(), 0);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'c');
+        assert_eq!(ucsra(&atmega) & (UCSRA_DOR | UCSRA_RXC), 0);
+    }
+
+    /// In the 9-bit mode, RXB8 holds the ninth bit of the character at the head of the buffer
+    #[test]
+    #[allow(non_snake_case)]
+    fn RXB8_in_9_bit_mode() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(USART0_CONFIG.UCSRC as u16, UCSRC_UCSZ1 | UCSRC_UCSZ0);
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_RXEN | UCSRB_UCSZ2);
+        let frame_cycles = atmega.usart_cycles_per_char(); // 12 symbols
+        atmega.usart_write_input_9_bits(&[0x1a5, 0x05a]);
+        for i in 1..=2 {
+            atmega.cpu.cycles = i * frame_cycles;
+            atmega.tick(None);
+        }
+
+        // Act & Assert
+        assert_eq!(ucsrb(&atmega) & UCSRB_RXB8, UCSRB_RXB8);
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_RXEN | UCSRB_UCSZ2); // read-only
+        assert_eq!(ucsrb(&atmega) & UCSRB_RXB8, UCSRB_RXB8);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), 0xa5);
+        assert_eq!(ucsrb(&atmega) & UCSRB_RXB8, 0);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), 0x5a);
+    }
+
+    /// A break condition is received as a zero character with a frame error
+    #[test]
+    #[allow(non_snake_case)]
+    fn FE_on_break() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        enable_receiver(&mut atmega, 0);
+
+        // Act
+        atmega.usart_write_break();
+        atmega.cpu.cycles = atmega.usart_cycles_per_char();
+        atmega.tick(None);
+
+        // Assert
+        assert_eq!(
+            ucsra(&atmega) & (UCSRA_RXC | UCSRA_FE),
+            UCSRA_RXC | UCSRA_FE
+        );
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), 0);
+        assert_eq!(ucsra(&atmega) & UCSRA_FE, 0);
+    }
+
+    /// Host input is held until the firmware enables the receiver
+    #[test]
+    fn input_held_until_receiver_enabled() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(USART0_CONFIG.UCSRC as u16, UCSRC_UCSZ1 | UCSRC_UCSZ0);
+        let frame_cycles = atmega.usart_cycles_per_char();
+
+        // Act & Assert
+        atmega.usart_write_input(b"x");
+        atmega.cpu.cycles = 2 * frame_cycles;
+        atmega.tick(None);
+        assert_eq!(atmega.usart_pending_input(), 1);
+        assert_eq!(ucsra(&atmega) & UCSRA_RXC, 0);
+
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_RXEN);
+        atmega.cpu.cycles += frame_cycles;
+        atmega.tick(None);
+        assert_eq!(atmega.usart_pending_input(), 0);
+        assert_eq!(atmega.read_data(USART0_CONFIG.UDR as u16), b'x');
+    }
+
+    /// Writing UCSRA keeps the status flags, and clears TXC when written with a one
+    #[test]
+    #[allow(non_snake_case)]
+    fn UCSRA_status_flags_read_only() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXEN);
+        atmega.write_data(USART0_CONFIG.UDR as u16, 0x61);
+        atmega.cpu.cycles = atmega.usart_cycles_per_char();
+        atmega.tick(None);
+        assert_eq!(ucsra(&atmega), UCSRA_TXC | UCSRA_UDRE);
+
+        // Act
+        atmega.write_data(USART0_CONFIG.UCSRA as u16, UCSRA_TXC | UCSRA_U2X);
+
+        // Assert
+        assert_eq!(ucsra(&atmega), UCSRA_UDRE | UCSRA_U2X);
+    }
+
+    /// Outputs receive each character once it has been shifted out, including non-UTF8 bytes
+    #[test]
+    fn output_after_frame_time() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let output = sent.clone();
+        atmega.usart.clear_outputs();
+        atmega
+            .usart
+            .add_output(Box::new(move |value| output.borrow_mut().push(value)));
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXEN);
+        let frame_cycles = atmega.usart_cycles_per_char();
+
+        // Act & Assert
+        atmega.write_data(USART0_CONFIG.UDR as u16, 0xff);
+        atmega.cpu.cycles = frame_cycles - 1;
+        atmega.tick(None);
+        assert!(sent.borrow().is_empty());
+
+        atmega.cpu.cycles = frame_cycles;
+        atmega.tick(None);
+        atmega.write_data(USART0_CONFIG.UDR as u16, b'\n');
+        atmega.cpu.cycles = 2 * frame_cycles;
+        atmega.tick(None);
+        assert_eq!(*sent.borrow(), vec![0xff, b'\n']);
+    }
 }
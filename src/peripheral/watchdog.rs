@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::{
+    atmega328p::{ATMega328P, PeripheralMemoryWriteHook},
+    clock::AVRClockEventType,
+    cpu::CPU,
+    interrupt::AVRInterruptConfig,
+    reset::ResetKind,
+};
+
+// MCUSR bits
+pub const MCUSR_WDRF: u8 = 0x8; // Watchdog System Reset Flag
+
+// WDTCSR bits
+pub const WDTCSR_WDIF: u8 = 0x80; // Watchdog Interrupt Flag
+pub const WDTCSR_WDIE: u8 = 0x40; // Watchdog Interrupt Enable
+pub const WDTCSR_WDP3: u8 = 0x20; // Watchdog Timer Prescaler 3
+pub const WDTCSR_WDCE: u8 = 0x10; // Watchdog Change Enable
+pub const WDTCSR_WDE: u8 = 0x8; // Watchdog System Reset Enable
+pub const WDTCSR_WDP210: u8 = 0x7; // Watchdog Timer Prescaler 2:0
+
+// Bits that can only be changed (WDE cleared) within 4 cycles of setting WDCE and WDE
+const WDTCSR_PROTECT_MASK: u8 = WDTCSR_WDE | WDTCSR_WDP3 | WDTCSR_WDP210;
+const CHANGE_ENABLE_CYCLES: u64 = 4;
+
+const WATCHDOG_OSCILLATOR_HZ: usize = 128_000;
+
+#[derive(Clone)]
+#[allow(non_snake_case)]
+pub struct AVRWatchdogConfig {
+    pub watchdog_interrupt: u8, // interrupt hander address on time-out
+
+    pub MCUSR: u8,  // MCU status register address
+    pub WDTCSR: u8, // control register address
+}
+
+pub const WATCHDOG_CONFIG: AVRWatchdogConfig = AVRWatchdogConfig {
+    watchdog_interrupt: 0x0c,
+    MCUSR: 0x54,
+    WDTCSR: 0x60,
+};
+
+/// Watchdog timer, clocked by its own 128kHz oscillator.
+/// Note: in interrupt and system reset mode, WDIE is cleared on time-out rather than when the
+/// interrupt is executed.
+pub struct AVRWatchdog {
+    pub config: AVRWatchdogConfig,
+    freq_hz: usize,
+
+    wdt: AVRInterruptConfig,
+
+    change_enabled_cycles: u64, // protected bits can be changed until this cycle count
+}
+
+impl AVRWatchdog {
+    pub fn new(config: AVRWatchdogConfig, freq_hz: usize) -> Self {
+        let wdt = AVRInterruptConfig {
+            address: config.watchdog_interrupt,
+            flag_register: config.WDTCSR as u16,
+            flag_mask: WDTCSR_WDIF,
+            enable_register: config.WDTCSR as u16,
+            enable_mask: WDTCSR_WDIE,
+            inverse_flag: false,
+            constant: false,
+        };
+        Self {
+            config,
+            freq_hz,
+            wdt,
+            change_enabled_cycles: 0,
+        }
+    }
+
+    #[allow(non_snake_case)]
+    pub fn add_WDTCSR_write_hook(&self, write_hooks: &mut HashMap<u16, PeripheralMemoryWriteHook>) {
+        write_hooks.insert(
+            self.config.WDTCSR as u16,
+            Box::new(|atmega, value, old_value, addr, _| {
+                let cycles = atmega.cpu.cycles;
+                let watchdog = &mut atmega.watchdog;
+                let mut new_value =
+                    (value & !(WDTCSR_WDIF | WDTCSR_WDCE)) | (old_value & WDTCSR_WDIF);
+                if value & WDTCSR_WDCE != 0 && value & WDTCSR_WDE != 0 {
+                    // Timed sequence: only the change enable happens
+                    watchdog.change_enabled_cycles = cycles + CHANGE_ENABLE_CYCLES;
+                    new_value =
+                        (new_value & !WDTCSR_PROTECT_MASK) | (old_value & WDTCSR_PROTECT_MASK);
+                } else if cycles >= watchdog.change_enabled_cycles {
+                    // WDE can be set, but not cleared, outside of the timed sequence
+                    new_value = (new_value & !WDTCSR_PROTECT_MASK)
+                        | (old_value & WDTCSR_PROTECT_MASK)
+                        | (value & WDTCSR_WDE);
+                } else {
+                    watchdog.change_enabled_cycles = 0;
+                }
+                // WDE is overridden by WDRF
+                if atmega.cpu.data[watchdog.config.MCUSR as usize] & MCUSR_WDRF != 0 {
+                    new_value |= WDTCSR_WDE;
+                }
+                // The WDTON fuse keeps the watchdog in system reset mode
+                if atmega.fuses.watchdog_always_on() {
+                    new_value = (new_value | WDTCSR_WDE) & !WDTCSR_WDIE;
+                }
+                atmega.cpu.set_data(addr, new_value);
+
+                atmega
+                    .cpu
+                    .clear_interrupt_by_flag(&atmega.watchdog.wdt, value);
+                atmega
+                    .cpu
+                    .update_interrupt_enable(atmega.watchdog.wdt, new_value);
+
+                let was_enabled = old_value & (WDTCSR_WDE | WDTCSR_WDIE) != 0;
+                let prescaler_changed =
+                    (old_value ^ new_value) & (WDTCSR_WDP3 | WDTCSR_WDP210) != 0;
+                if !atmega.watchdog_enabled() {
+                    atmega.cpu.clear_clock_event(AVRClockEventType::Watchdog);
+                } else if !was_enabled || prescaler_changed {
+                    atmega.watchdog_restart();
+                }
+                true
+            }),
+        );
+    }
+
+    /// Puts the watchdog back in its reset state. It stays enabled with the shortest time-out
+    /// after a watchdog reset, since WDE is overridden by WDRF, or if it is always on.
+    pub fn reset(&mut self, cpu: &mut CPU, always_on: bool) {
+        self.change_enabled_cycles = 0;
+        let mcusr = cpu.data[self.config.MCUSR as usize];
+        cpu.data[self.config.WDTCSR as usize] = if always_on || mcusr & MCUSR_WDRF != 0 {
+            WDTCSR_WDE
+        } else {
+            0
+        };
+    }
+
+    /// Number of watchdog oscillator cycles until time-out, from WDP3:0
+    pub fn prescaler(&self, data: &[u8]) -> usize {
+        let wdtcsr = data[self.config.WDTCSR as usize];
+        let value = ((wdtcsr & WDTCSR_WDP3) >> 2) | (wdtcsr & WDTCSR_WDP210);
+        2048 << value
+    }
+
+    /// Number of CPU clock cycles until time-out
+    pub fn timeout_cycles(&self, data: &[u8]) -> u64 {
+        (self.freq_hz * self.prescaler(data) / WATCHDOG_OSCILLATOR_HZ) as u64
+    }
+}
+
+impl ATMega328P {
+    pub fn watchdog_enabled(&self) -> bool {
+        self.cpu.data[self.watchdog.config.WDTCSR as usize] & (WDTCSR_WDE | WDTCSR_WDIE) != 0
+    }
+
+    /// Restarts the watchdog timer count, as the WDR instruction does
+    pub fn watchdog_restart(&mut self) {
+        self.cpu.clear_clock_event(AVRClockEventType::Watchdog);
+        if !self.watchdog_enabled() {
+            return;
+        }
+        self.cpu.add_clock_event(
+            Box::new(|atmega: &mut ATMega328P, _, _, _| atmega.watchdog_timeout()),
+            self.watchdog.timeout_cycles(&self.cpu.data),
+            AVRClockEventType::Watchdog,
+        );
+    }
+
+    fn watchdog_timeout(&mut self) {
+        let wdtcsr = self.watchdog.config.WDTCSR as usize;
+        let value = self.cpu.data[wdtcsr];
+        if value & WDTCSR_WDIE != 0 {
+            // In interrupt and system reset mode, the next time-out resets
+            if value & WDTCSR_WDE != 0 {
+                self.cpu.data[wdtcsr] &= !WDTCSR_WDIE;
+            }
+            self.cpu.set_interrupt_flag(self.watchdog.wdt);
+            self.watchdog_restart();
+        } else if value & WDTCSR_WDE != 0 {
+            self.reset(ResetKind::Watchdog);
+        }
+    }
+}
+
+#[cfg(test)]
+mod watchdog_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::watchdog::{
+            MCUSR_WDRF, WATCHDOG_CONFIG, WDTCSR_WDCE, WDTCSR_WDE, WDTCSR_WDIE, WDTCSR_WDIF,
+        },
+    };
+
+    const WDTCSR: u16 = WATCHDOG_CONFIG.WDTCSR as u16;
+    const MCUSR: usize = WATCHDOG_CONFIG.MCUSR as usize;
+
+    // 16ms at 16MHz, with the default prescaler
+    const TIMEOUT_CYCLES: u64 = 256_000;
+
+    fn run_until(atmega: &mut ATMega328P, cycles: u64) {
+        while atmega.cpu.cycles < cycles {
+            atmega.cpu.cycles += 1;
+            atmega.tick(None);
+        }
+    }
+
+    #[test]
+    fn system_reset_on_timeout() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.pc = 0x100;
+
+        // Act
+        atmega.write_data(WDTCSR, WDTCSR_WDE);
+        run_until(&mut atmega, TIMEOUT_CYCLES - 1);
+        let pc_before_timeout = atmega.cpu.pc;
+        run_until(&mut atmega, TIMEOUT_CYCLES);
+
+        // Assert
+        assert_eq!(pc_before_timeout, 0x100);
+        assert_eq!(atmega.cpu.pc, 0);
+        assert_eq!(atmega.cpu.data[MCUSR] & MCUSR_WDRF, MCUSR_WDRF);
+        assert_eq!(atmega.cpu.data[WDTCSR as usize], WDTCSR_WDE);
+    }
+
+    #[test]
+    fn wdr_restarts_timer() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.pc = 0x100;
+        atmega.write_data(WDTCSR, WDTCSR_WDE);
+
+        // Act
+        run_until(&mut atmega, TIMEOUT_CYCLES / 2);
+        atmega.watchdog_restart();
+        run_until(&mut atmega, TIMEOUT_CYCLES + 100);
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, 0x100);
+        assert_eq!(atmega.cpu.data[MCUSR] & MCUSR_WDRF, 0);
+    }
+
+    #[test]
+    fn prescaler_sets_timeout() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.write_data(WDTCSR, WDTCSR_WDE | 0x6); // WDP2, WDP1: 1s
+
+        // Assert
+        assert_eq!(
+            atmega.watchdog.timeout_cycles(&atmega.cpu.data),
+            TIMEOUT_CYCLES
+        );
+        atmega.write_data(WDTCSR, WDTCSR_WDCE | WDTCSR_WDE);
+        atmega.write_data(WDTCSR, WDTCSR_WDE | 0x6);
+        assert_eq!(
+            atmega.watchdog.timeout_cycles(&atmega.cpu.data),
+            TIMEOUT_CYCLES * 64
+        );
+    }
+
+    #[test]
+    fn clearing_wde_needs_timed_sequence() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(WDTCSR, WDTCSR_WDE);
+
+        // Act
+        atmega.write_data(WDTCSR, 0);
+        let value_without_sequence = atmega.cpu.data[WDTCSR as usize];
+        atmega.write_data(WDTCSR, WDTCSR_WDCE | WDTCSR_WDE);
+        atmega.cpu.cycles += 4;
+        atmega.write_data(WDTCSR, 0);
+        let value_after_timeout = atmega.cpu.data[WDTCSR as usize];
+        atmega.write_data(WDTCSR, WDTCSR_WDCE | WDTCSR_WDE);
+        atmega.cpu.cycles += 3;
+        atmega.write_data(WDTCSR, 0);
+
+        // Assert
+        assert_eq!(value_without_sequence, WDTCSR_WDE);
+        assert_eq!(value_after_timeout, WDTCSR_WDE);
+        assert_eq!(atmega.cpu.data[WDTCSR as usize], 0);
+        assert!(!atmega.watchdog_enabled());
+    }
+
+    #[test]
+    fn wdrf_overrides_wde() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.data[MCUSR] = MCUSR_WDRF;
+
+        // Act
+        atmega.write_data(WDTCSR, WDTCSR_WDCE | WDTCSR_WDE);
+        atmega.write_data(WDTCSR, 0);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[WDTCSR as usize], WDTCSR_WDE);
+    }
+
+    #[test]
+    fn periodic_interrupt() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.pc = 0x100;
+        atmega.write_data(WDTCSR, WDTCSR_WDIE);
+
+        // Act
+        run_until(&mut atmega, TIMEOUT_CYCLES);
+        let flag_after_timeout = atmega.cpu.data[WDTCSR as usize] & WDTCSR_WDIF;
+        atmega.write_data(WDTCSR, WDTCSR_WDIF | WDTCSR_WDIE);
+        let flag_after_clear = atmega.cpu.data[WDTCSR as usize] & WDTCSR_WDIF;
+        run_until(&mut atmega, 2 * TIMEOUT_CYCLES);
+
+        // Assert
+        assert_eq!(flag_after_timeout, WDTCSR_WDIF);
+        assert_eq!(flag_after_clear, 0);
+        assert_eq!(atmega.cpu.data[WDTCSR as usize] & WDTCSR_WDIF, WDTCSR_WDIF);
+        assert!(atmega.cpu.pending_interrupts[0x0c].is_some());
+        assert_eq!(atmega.cpu.pc, 0x100);
+    }
+
+    #[test]
+    fn interrupt_then_system_reset() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.pc = 0x100;
+        atmega.write_data(WDTCSR, WDTCSR_WDIE | WDTCSR_WDE);
+
+        // Act
+        run_until(&mut atmega, TIMEOUT_CYCLES);
+        let value_after_first_timeout = atmega.cpu.data[WDTCSR as usize];
+        run_until(&mut atmega, 2 * TIMEOUT_CYCLES);
+
+        // Assert
+        assert_eq!(value_after_first_timeout, WDTCSR_WDIF | WDTCSR_WDE);
+        assert_eq!(atmega.cpu.pc, 0);
+        assert_eq!(atmega.cpu.data[MCUSR] & MCUSR_WDRF, MCUSR_WDRF);
+    }
+}
@@ -1,22 +1,194 @@
-const FLASH: usize = 0x8000; // ATmega328p
-
-pub fn load_hex(source: &str) -> Vec<u8> {
-    let mut prog: Vec<u8> = vec![0; FLASH];
-    let mut n_bytes = 0;
-    for line in source.split("\n") {
-        if !line.is_empty() && &line[..1] == ":" && &line[7..9] == "00" {
-            let bytes = u8::from_str_radix(&line[1..3], 16).unwrap(); // number of bytes of instructions on this line
-            let addr = u16::from_str_radix(&line[3..7], 16).unwrap();
-            for i in 0..bytes {
-                let offset = 9 + (i as usize * 2);
-                prog[addr as usize + i as usize] =
-                    u8::from_str_radix(&line[offset..offset + 2], 16).unwrap();
+use crate::error::{HexError, HexErrorKind};
+
+/// Loads an Intel HEX image into a program memory of `flash_size` bytes.
+/// The bytes that are not in the image are 0.
+pub fn load_hex(source: &str, flash_size: usize) -> Result<Vec<u8>, HexError> {
+    let mut prog: Vec<u8> = vec![0; flash_size];
+    parse_hex(source, |line, addr, data| {
+        let end = addr + data.len();
+        if end > flash_size {
+            return Err(HexError {
+                kind: HexErrorKind::OutOfRange(end as u32),
+                line,
+            });
+        }
+        prog[addr..end].copy_from_slice(data);
+        Ok(())
+    })?;
+    Ok(prog)
+}
+
+/// Parses an Intel HEX image, as written by avr-objcopy. `write` is called with the line number,
+/// address and data of each data record. The extended segment and linear address records move
+/// the base address of the records that follow, and the start address records are ignored.
+pub fn parse_hex(
+    source: &str,
+    mut write: impl FnMut(usize, usize, &[u8]) -> Result<(), HexError>,
+) -> Result<(), HexError> {
+    let mut base_addr = 0;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |kind| HexError {
+            kind,
+            line: line_number,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .filter(|record| record.len() % 2 == 0 && record.is_ascii())
+            .ok_or(error(HexErrorKind::MalformedRecord))?;
+        let bytes = (0..record.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&record[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error(HexErrorKind::InvalidDigit))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error(HexErrorKind::WrongLength));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error(HexErrorKind::WrongChecksum));
+        }
+        let offset = ((bytes[1] as usize) << 8) | bytes[2] as usize;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => write(line_number, base_addr + offset, data)?,
+            (0x01, _) => break,
+            // Extended segment address, in 16 bytes paragraphs
+            (0x02, 2) => base_addr = (((data[0] as usize) << 8) | data[1] as usize) << 4,
+            // Extended linear address, the upper 16 bits
+            (0x04, 2) => base_addr = (((data[0] as usize) << 8) | data[1] as usize) << 16,
+            (0x03 | 0x05, 4) => {}
+            (0x02..=0x05, _) => return Err(error(HexErrorKind::WrongLength)),
+            (record_type, _) => {
+                return Err(error(HexErrorKind::UnsupportedRecordType(record_type)));
             }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod program_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        error::{HexError, HexErrorKind},
+        fuses::Fuses,
+        program::load_hex,
+    };
+
+    const FLASH_SIZE: usize = 0x8000;
+
+    fn load_error(source: &str) -> HexError {
+        load_hex(source, FLASH_SIZE).unwrap_err()
+    }
+
+    #[test]
+    fn load_data_records() {
+        // Arrange
+        let source = ":100000000C9434000C943E000C943E000C943E0082\n\
+                      :02001000FFCF20\n\
+                      :00000001FF\n";
+
+        // Act
+        let prog = load_hex(source, FLASH_SIZE).unwrap();
+
+        // Assert
+        assert_eq!(prog.len(), FLASH_SIZE);
+        assert_eq!(prog[..4], [0x0c, 0x94, 0x34, 0x00]);
+        assert_eq!(prog[0x10..0x13], [0xff, 0xcf, 0x00]);
+    }
+
+    #[test]
+    fn stop_at_end_of_file() {
+        // Arrange
+        let source = ":00000001FF\n:0100000001FE\n";
 
-            n_bytes += bytes as usize;
+        // Act
+        let prog = load_hex(source, FLASH_SIZE).unwrap();
+
+        // Assert
+        assert_eq!(prog[0], 0);
+    }
+
+    #[test]
+    fn extended_addresses() {
+        // Arrange
+        let source = ":020000021000EC\n\
+                      :0100000001FE\n\
+                      :020000040001F9\n\
+                      :0100020002FB\n\
+                      :0400000500000000F7\n\
+                      :0400000300000000F9\n";
+
+        // Act
+        let prog = load_hex(source, 0x20000).unwrap();
+
+        // Assert
+        assert_eq!(prog[0x10000], 0x01);
+        assert_eq!(prog[0x10002], 0x02);
+    }
+
+    #[test]
+    fn data_past_flash() {
+        // Arrange
+        let source = ":020000040001F9\n:0100000001FE\n";
+
+        // Act
+        let err = load_error(source);
+
+        // Assert
+        assert_eq!(
+            err,
+            HexError {
+                kind: HexErrorKind::OutOfRange(0x10001),
+                line: 2
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_records() {
+        for (source, kind) in [
+            ("0100000001FE", HexErrorKind::MalformedRecord),
+            (":0100000001F", HexErrorKind::MalformedRecord),
+            (":01000000G1FE", HexErrorKind::InvalidDigit),
+            (":0200000001FE", HexErrorKind::WrongLength),
+            (":03000004000100F8", HexErrorKind::WrongLength),
+            (":0100000001FF", HexErrorKind::WrongChecksum),
+            (":00000006FA", HexErrorKind::UnsupportedRecordType(0x06)),
+        ] {
+            // Act
+            let err = load_error(&format!("\n{}", source)); // after an empty line
+
+            // Assert
+            assert_eq!(err, HexError { kind, line: 2 });
         }
     }
-    println!("number of instructions: {}", n_bytes / 2);
 
-    prog
+    #[test]
+    fn error_message() {
+        // Act
+        let err = load_error(":0100000001FF\n");
+
+        // Assert
+        assert_eq!(err.to_string(), "line 1: wrong checksum");
+    }
+
+    #[test]
+    fn try_new_returns_error() {
+        // Act
+        let result = ATMega328P::try_new("\n:0100", DEFAULT_FREQ, Fuses::default());
+
+        // Assert
+        assert_eq!(
+            result.err(),
+            Some(HexError {
+                kind: HexErrorKind::WrongLength,
+                line: 2
+            })
+        );
+    }
 }
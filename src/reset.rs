@@ -0,0 +1,180 @@
+use crate::{atmega328p::ATMega328P, peripheral::watchdog::MCUSR_WDRF};
+
+const MCUSR: usize = 0x54; // MCU Status Register
+
+// MCUSR bits
+pub const MCUSR_PORF: u8 = 0x1; // Power-on Reset Flag
+pub const MCUSR_EXTRF: u8 = 0x2; // External Reset Flag
+pub const MCUSR_BORF: u8 = 0x4; // Brown-out Reset Flag
+
+const IO_REGISTERS: std::ops::Range<usize> = 0x20..0x100;
+
+/// Sources of a system reset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetKind {
+    PowerOn,
+    External, // RESET pin pulled low
+    BrownOut,
+    Watchdog,
+}
+
+impl ResetKind {
+    /// Flag set in MCUSR by the reset
+    pub fn mcusr_flag(&self) -> u8 {
+        match self {
+            ResetKind::PowerOn => MCUSR_PORF,
+            ResetKind::External => MCUSR_EXTRF,
+            ResetKind::BrownOut => MCUSR_BORF,
+            ResetKind::Watchdog => MCUSR_WDRF,
+        }
+    }
+}
+
+impl ATMega328P {
+    /// Resets the MCU: the I/O registers and peripherals go back to their initial values, and the
+    /// program restarts from the reset vector selected by BOOTRST. A power-on reset also clears the registers and
+    /// SRAM, which the other resets leave alone. The reset source is flagged in MCUSR.
+    ///
+    /// The flash, EEPROM, cycle count and everything attached from the host (USART outputs, SPI
+    /// devices, GPIO listeners and inputs, analog voltages) are kept.
+    pub fn reset(&mut self, kind: ResetKind) {
+        let mcusr = self.cpu.data[MCUSR];
+        if kind == ResetKind::PowerOn {
+            self.cpu.data.fill(0);
+            self.cpu.data[MCUSR] = MCUSR_PORF;
+        } else {
+            self.cpu.data[IO_REGISTERS].fill(0);
+            self.cpu.data[MCUSR] = mcusr | kind.mcusr_flag();
+        }
+        self.cpu.reset();
+        self.cpu.pc = self.fuses.reset_vector();
+        self.reset_clock_prescaler();
+        self.sleep_mode = None;
+
+        for timer in self.timers.iter_mut() {
+            timer.reset();
+        }
+        self.usart.reset(&mut self.cpu);
+        for port in self.ports.iter_mut() {
+            port.reset(&mut self.cpu);
+        }
+        self.i2c.reset(&mut self.cpu);
+        self.spi.reset();
+        self.adc.reset();
+        self.eeprom.reset();
+        self.watchdog
+            .reset(&mut self.cpu, self.fuses.watchdog_always_on());
+        self.flash.reset();
+        self.watchdog_restart();
+    }
+}
+
+#[cfg(test)]
+mod reset_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::{
+            port::{PORTB_CONFIG, PinInput},
+            timer::TIMER_0_CONFIG,
+            usart::USART0_CONFIG,
+            watchdog::{MCUSR_WDRF, WATCHDOG_CONFIG},
+        },
+        reset::{MCUSR, MCUSR_BORF, MCUSR_EXTRF, MCUSR_PORF, ResetKind},
+    };
+
+    const SRAM_ADDR: usize = 0x200;
+
+    #[test]
+    fn power_on_flags() {
+        // Arrange/Act
+        let atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[MCUSR], MCUSR_PORF);
+        assert_eq!(atmega.cpu.data[USART0_CONFIG.UCSRA as usize], 0x20); // UDRE
+        assert_eq!(atmega.cpu.data[USART0_CONFIG.UCSRC as usize], 0x06); // 8 bits
+    }
+
+    #[test]
+    fn external_reset_keeps_sram() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.data[MCUSR] = 0;
+        atmega.cpu.data[SRAM_ADDR] = 0x42;
+        atmega.cpu.pc = 0x100;
+        atmega.cpu.cycles = 1000;
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 0x1);
+        atmega.write_data(PORTB_CONFIG.DDR as u16, 0xff);
+
+        // Act
+        atmega.reset(ResetKind::External);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[MCUSR], MCUSR_EXTRF);
+        assert_eq!(atmega.cpu.data[SRAM_ADDR], 0x42);
+        assert_eq!(atmega.cpu.pc, 0);
+        assert_eq!(atmega.cpu.cycles, 1000);
+        assert_eq!(atmega.cpu.data[TIMER_0_CONFIG.TCCRB as usize], 0);
+        assert_eq!(atmega.cpu.data[PORTB_CONFIG.DDR as usize], 0);
+        assert!(atmega.cpu.clock_events.is_empty());
+    }
+
+    #[test]
+    fn power_on_reset_clears_sram() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.data[SRAM_ADDR] = 0x42;
+        atmega.cpu.data[MCUSR] = MCUSR_BORF;
+
+        // Act
+        atmega.reset(ResetKind::PowerOn);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[SRAM_ADDR], 0);
+        assert_eq!(atmega.cpu.data[MCUSR], MCUSR_PORF);
+    }
+
+    #[test]
+    fn reset_flags_accumulate() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.reset(ResetKind::BrownOut);
+        atmega.reset(ResetKind::External);
+
+        // Assert
+        assert_eq!(
+            atmega.cpu.data[MCUSR],
+            MCUSR_PORF | MCUSR_BORF | MCUSR_EXTRF
+        );
+    }
+
+    #[test]
+    fn watchdog_reset_keeps_watchdog_enabled() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+
+        // Act
+        atmega.reset(ResetKind::Watchdog);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[MCUSR] & MCUSR_WDRF, MCUSR_WDRF);
+        assert_eq!(atmega.cpu.data[WATCHDOG_CONFIG.WDTCSR as usize], 0x08); // WDE
+        assert!(atmega.watchdog_enabled());
+    }
+
+    #[test]
+    fn reset_keeps_pin_inputs() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.set_pin_input("B", 1, PinInput::High);
+        atmega.write_data(PORTB_CONFIG.PORT as u16, 1 << 2); // pull-up
+
+        // Act
+        atmega.reset(ResetKind::External);
+
+        // Assert
+        assert_eq!(atmega.cpu.data[PORTB_CONFIG.PIN as usize], 1 << 1);
+    }
+}
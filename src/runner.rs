@@ -1,22 +1,94 @@
 use crate::{
     atmega328p::{ATMega328P, DEFAULT_FREQ},
+    error::{HexError, SimError, StepOutcome},
+    fuses::Fuses,
     peripheral::i2c::bus::I2CBus,
+    serial::SerialBridge,
 };
 
+#[cfg(target_os = "linux")]
+use {crate::serial::pty::PtyBridge, std::path::PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::{flog, serial::tcp::TcpBridge},
+    std::{
+        io,
+        net::{SocketAddr, ToSocketAddrs},
+    },
+};
+
+const SERIAL_POLL_CYCLES: u64 = 16_000; // 1ms at 16MHz
+
 pub struct AVRRunner {
     // pub cpu: CPU,
     pub atmega328p: ATMega328P,
+
+    serial_bridges: Vec<Box<dyn SerialBridge>>,
+    next_serial_poll: u64, // cycle count at which the serial bridges are polled next
 }
 
 impl AVRRunner {
+    /// Creates the runner
+    ///
+    /// # Panics
+    ///
+    /// If the program is not a valid Intel HEX image, see `try_new` to handle the error instead.
     pub fn new(hex: &str) -> Self {
+        Self::try_new(hex).unwrap_or_else(|err| panic!("invalid hex: {}", err))
+    }
+
+    /// Creates the runner, or returns the line-numbered error of an invalid Intel HEX program
+    pub fn try_new(hex: &str) -> Result<Self, HexError> {
         // Arduino is normally set to run at 16MHz.
         // To use clock with different Hz, need to update firmware as well.
-        let atmega328p = ATMega328P::new(hex, DEFAULT_FREQ);
-        AVRRunner { atmega328p }
+        let atmega328p = ATMega328P::try_new(hex, DEFAULT_FREQ, Fuses::default())?;
+        Ok(AVRRunner {
+            atmega328p,
+            serial_bridges: Vec::new(),
+            next_serial_poll: 0,
+        })
+    }
+
+    /// Executes one instruction. A sleeping CPU is fast-forwarded to its next clock event, or to
+    /// the next poll of the serial bridges, which may wake it up.
+    pub fn step(&mut self, i2c_bus: Option<&mut I2CBus>) -> Result<StepOutcome, SimError> {
+        let max_cycles = if self.serial_bridges.is_empty() {
+            u64::MAX
+        } else {
+            self.next_serial_poll
+        };
+        let outcome = self.atmega328p.step_until(i2c_bus, max_cycles)?;
+        if self.atmega328p.cpu.cycles >= self.next_serial_poll {
+            for bridge in self.serial_bridges.iter_mut() {
+                bridge.poll(&mut self.atmega328p);
+            }
+            self.next_serial_poll = self.atmega328p.cpu.cycles + SERIAL_POLL_CYCLES;
+        }
+        Ok(outcome)
+    }
+
+    /// Connects the USART to a host, polled every millisecond of simulated time
+    pub fn add_serial_bridge(&mut self, bridge: Box<dyn SerialBridge>) {
+        self.serial_bridges.push(bridge);
+    }
+
+    /// Connects the USART to a new pseudo-terminal, and prints its path
+    #[cfg(target_os = "linux")]
+    pub fn open_pty(&mut self) -> io::Result<PathBuf> {
+        let bridge = PtyBridge::open(&mut self.atmega328p)?;
+        let path = bridge.path().to_path_buf();
+        flog!("USART0 connected to {}", path.display());
+        self.add_serial_bridge(Box::new(bridge));
+        Ok(path)
     }
 
-    pub fn step(&mut self, i2c_bus: Option<&mut I2CBus>) {
-        self.atmega328p.step(i2c_bus);
+    /// Connects the USART to the clients of a TCP port, and prints the address it listens on
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn listen_tcp(&mut self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let bridge = TcpBridge::listen(&mut self.atmega328p, addr)?;
+        let addr = bridge.local_addr()?;
+        flog!("USART0 listening on socket://{}", addr);
+        self.add_serial_bridge(Box::new(bridge));
+        Ok(addr)
     }
 }
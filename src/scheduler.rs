@@ -0,0 +1,295 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::clock::{AVRClockEventCallback, AVRClockEventType};
+
+/// Identifies a scheduled clock event, so that it can be cancelled or rescheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventHandle(u64);
+
+struct ScheduledEvent {
+    cycles: u64,
+    seq: u64, // sequence number of the queue entry that is currently valid for this event
+    callback: AVRClockEventCallback,
+    event_type: AVRClockEventType,
+}
+
+/// Clock event queue, ordered by the cycle at which the events fire.
+/// Events due at the same cycle fire in the order they were scheduled.
+///
+/// Cancelled or rescheduled events leave stale entries in the heap, which are
+/// skipped when they reach the top.
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(u64, u64, EventHandle)>>, // (cycles, seq, handle)
+    events: HashMap<EventHandle, ScheduledEvent>,
+    next_handle: u64,
+    next_seq: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            events: HashMap::new(),
+            next_handle: 0,
+            next_seq: 0,
+        }
+    }
+
+    /// Schedules the callback to fire at the given (absolute) cycle count
+    pub fn schedule(
+        &mut self,
+        cycles: u64,
+        callback: AVRClockEventCallback,
+        event_type: AVRClockEventType,
+    ) -> EventHandle {
+        let handle = EventHandle(self.next_handle);
+        self.next_handle += 1;
+        let seq = self.push(cycles, handle);
+        self.events.insert(
+            handle,
+            ScheduledEvent {
+                cycles,
+                seq,
+                callback,
+                event_type,
+            },
+        );
+        handle
+    }
+
+    /// Cancels the event. Returns false if it already fired or was cancelled.
+    pub fn cancel(&mut self, handle: EventHandle) -> bool {
+        self.events.remove(&handle).is_some()
+    }
+
+    /// Moves the event to the given (absolute) cycle count.
+    /// Returns false if it already fired or was cancelled.
+    pub fn reschedule(&mut self, handle: EventHandle, cycles: u64) -> bool {
+        if !self.events.contains_key(&handle) {
+            return false;
+        }
+        let seq = self.push(cycles, handle);
+        let event = self.events.get_mut(&handle).unwrap();
+        event.cycles = cycles;
+        event.seq = seq;
+        true
+    }
+
+    /// Cancels all the events of the given type. Returns whether any was cancelled.
+    pub fn cancel_type(&mut self, event_type: AVRClockEventType) -> bool {
+        let count = self.events.len();
+        self.events
+            .retain(|_, event| event.event_type != event_type);
+        self.events.len() != count
+    }
+
+    pub fn is_scheduled(&self, handle: EventHandle) -> bool {
+        self.events.contains_key(&handle)
+    }
+
+    /// Type of the event, if it is still scheduled
+    pub fn event_type(&self, handle: EventHandle) -> Option<AVRClockEventType> {
+        self.events.get(&handle).map(|event| event.event_type)
+    }
+
+    /// Cycle count at which the event fires, if it is still scheduled
+    pub fn event_cycles(&self, handle: EventHandle) -> Option<u64> {
+        self.events.get(&handle).map(|event| event.cycles)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.events.clear();
+    }
+
+    /// Cycle count of the earliest scheduled event
+    pub fn next_cycles(&mut self) -> Option<u64> {
+        self.discard_stale();
+        self.queue.peek().map(|Reverse((cycles, _, _))| *cycles)
+    }
+
+    /// Removes and returns the callback of the earliest event, if it is due at the given cycle count
+    pub fn pop_due(&mut self, cycles: u64) -> Option<AVRClockEventCallback> {
+        if self.next_cycles()? > cycles {
+            return None;
+        }
+        let Reverse((_, _, handle)) = self.queue.pop().unwrap();
+        self.events.remove(&handle).map(|event| event.callback)
+    }
+
+    fn push(&mut self, cycles: u64, handle: EventHandle) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(Reverse((cycles, seq, handle)));
+        seq
+    }
+
+    /// Pops the heap entries of events that were cancelled or rescheduled
+    fn discard_stale(&mut self) {
+        while let Some(Reverse((_, seq, handle))) = self.queue.peek() {
+            if self
+                .events
+                .get(handle)
+                .is_some_and(|event| event.seq == *seq)
+            {
+                break;
+            }
+            self.queue.pop();
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        clock::AVRClockEventType,
+        peripheral::{
+            timer::{CS00, TIMER_0_CONFIG},
+            usart::{UCSRB_TXEN, USART0_CONFIG},
+        },
+        scheduler::Scheduler,
+    };
+
+    #[test]
+    fn events_fire_in_cycle_order() {
+        // Arrange
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(30, Box::new(|_, _, _, _| {}), AVRClockEventType::TWI);
+        scheduler.schedule(10, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+        scheduler.schedule(
+            20,
+            Box::new(|_, _, _, _| {}),
+            AVRClockEventType::Timer0Count,
+        );
+
+        // Act/Assert
+        assert_eq!(scheduler.next_cycles(), Some(10));
+        assert!(scheduler.pop_due(9).is_none());
+        assert!(scheduler.pop_due(10).is_some());
+        assert_eq!(scheduler.next_cycles(), Some(20));
+        assert!(scheduler.pop_due(25).is_some());
+        assert_eq!(scheduler.next_cycles(), Some(30));
+        assert_eq!(scheduler.len(), 1);
+    }
+
+    #[test]
+    fn cancel_by_handle() {
+        // Arrange
+        let mut scheduler = Scheduler::new();
+        let first = scheduler.schedule(10, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+        let second = scheduler.schedule(20, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+
+        // Act
+        let cancelled = scheduler.cancel(first);
+
+        // Assert
+        assert!(cancelled);
+        assert!(!scheduler.cancel(first)); // already cancelled
+        assert!(!scheduler.is_scheduled(first));
+        assert!(scheduler.is_scheduled(second));
+        assert_eq!(scheduler.next_cycles(), Some(20));
+    }
+
+    #[test]
+    fn reschedule_keeps_handle() {
+        // Arrange
+        let mut scheduler = Scheduler::new();
+        let handle = scheduler.schedule(10, Box::new(|_, _, _, _| {}), AVRClockEventType::TWI);
+        scheduler.schedule(20, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+
+        // Act
+        let rescheduled = scheduler.reschedule(handle, 30);
+
+        // Assert
+        assert!(rescheduled);
+        assert_eq!(scheduler.event_cycles(handle), Some(30));
+        assert_eq!(scheduler.next_cycles(), Some(20));
+        assert!(scheduler.pop_due(20).is_some());
+        assert_eq!(scheduler.next_cycles(), Some(30));
+        assert!(scheduler.pop_due(30).is_some());
+        assert!(!scheduler.is_scheduled(handle));
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn cancel_type_only_matches_that_type() {
+        // Arrange
+        let mut scheduler = Scheduler::new();
+        let usart = scheduler.schedule(10, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+        let timer = scheduler.schedule(
+            20,
+            Box::new(|_, _, _, _| {}),
+            AVRClockEventType::Timer0Count,
+        );
+
+        // Act
+        let cancelled = scheduler.cancel_type(AVRClockEventType::USART0);
+
+        // Assert
+        assert!(cancelled);
+        assert!(!scheduler.is_scheduled(usart));
+        assert!(scheduler.is_scheduled(timer));
+        assert!(!scheduler.cancel_type(AVRClockEventType::USART0));
+    }
+
+    /// Cancelling a USART event leaves the Timer0 count events alone
+    #[test]
+    fn cancel_usart_keeps_timer0_count() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, CS00); // schedules a count event
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXEN);
+        atmega.write_data(USART0_CONFIG.UDR as u16, 0x61); // schedules a USART event
+        assert_eq!(atmega.cpu.clock_events.len(), 2);
+
+        // Act
+        let cancelled = atmega.cpu.clear_clock_event(AVRClockEventType::USART0);
+
+        // Assert
+        assert!(cancelled);
+        assert_eq!(atmega.cpu.clock_events.len(), 1);
+        atmega.cpu.cycles = 1;
+        atmega.tick(None); // first tick updates divider
+        atmega.cpu.cycles = 2;
+        atmega.tick(None); // increment count
+        assert_eq!(atmega.read_data(TIMER_0_CONFIG.TCNT as u16), 1);
+    }
+
+    #[test]
+    fn events_fire_across_u32_cycles() {
+        // Arrange
+        let mut scheduler = Scheduler::new();
+        let before = u32::MAX as u64;
+        scheduler.schedule(
+            before + 2,
+            Box::new(|_, _, _, _| {}),
+            AVRClockEventType::TWI,
+        );
+        scheduler.schedule(before, Box::new(|_, _, _, _| {}), AVRClockEventType::USART0);
+
+        // Act/Assert
+        assert_eq!(scheduler.next_cycles(), Some(before));
+        assert!(scheduler.pop_due(before).is_some());
+        assert!(scheduler.pop_due(before + 1).is_none());
+        assert!(scheduler.pop_due(before + 2).is_some());
+        assert!(scheduler.is_empty());
+    }
+}
@@ -0,0 +1,13 @@
+use crate::atmega328p::ATMega328P;
+
+#[cfg(target_os = "linux")]
+pub mod pty;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp;
+
+/// Host side of a serial connection to the USART. Transmitted characters are sent through a
+/// USART output, and the characters sent by the host are picked up when the bridge is polled.
+pub trait SerialBridge {
+    /// Feeds the characters sent by the host since the last poll to the USART, without blocking
+    fn poll(&mut self, atmega: &mut ATMega328P);
+}
@@ -0,0 +1,128 @@
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    mem::MaybeUninit,
+    os::fd::FromRawFd,
+    path::{Path, PathBuf},
+};
+
+use crate::{atmega328p::ATMega328P, serial::SerialBridge};
+
+/// Bridges the USART to a pseudo-terminal, so that host tools can open it like a serial port.
+///
+/// Characters written to the terminal are received by the USART at its baud rate, one frame
+/// time each, and transmitted characters can be read from the terminal.
+pub struct PtyBridge {
+    master: File,
+    path: PathBuf, // path of the terminal to open, e.g. /dev/pts/3
+}
+
+impl PtyBridge {
+    /// Opens a new pseudo-terminal in raw mode, and connects it to the USART
+    pub fn open(atmega: &mut ATMega328P) -> io::Result<Self> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // closes the terminal on error
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut name = [0 as libc::c_char; 64];
+        let err = unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let path = PathBuf::from(path.to_string_lossy().into_owned());
+
+        // No echo or line editing, so that binary data goes through unchanged
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut termios = unsafe { termios.assume_init() };
+        unsafe { libc::cfmakeraw(&mut termios) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut output = master.try_clone()?;
+        atmega.usart.add_output(Box::new(move |value| {
+            // characters are dropped if nobody reads the terminal, as on a real serial line
+            let _ = output.write(&[value]);
+        }));
+
+        Ok(Self { master, path })
+    }
+
+    /// Path of the terminal device for host tools to open
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SerialBridge for PtyBridge {
+    fn poll(&mut self, atmega: &mut ATMega328P) {
+        let mut buf = [0; 256];
+        loop {
+            match self.master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => atmega.usart_write_input(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // WouldBlock if there is no data, EIO while no process has the terminal open
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod pty_tests {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::usart::{UCSRB_TXEN, USART0_CONFIG},
+        serial::{SerialBridge, pty::PtyBridge},
+    };
+
+    #[test]
+    fn mirror_bytes_both_ways() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let mut bridge = PtyBridge::open(&mut atmega).unwrap();
+        assert!(bridge.path().starts_with("/dev/pts"));
+        let mut host = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(bridge.path())
+            .unwrap();
+
+        // Act & Assert
+        host.write_all(b"ping").unwrap();
+        for _ in 0..100 {
+            bridge.poll(&mut atmega);
+            if atmega.usart_pending_input() == 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1)); // the terminal forwards data asynchronously
+        }
+        assert_eq!(atmega.usart_pending_input(), 4);
+
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXEN);
+        atmega.write_data(USART0_CONFIG.UDR as u16, 0x80);
+        atmega.cpu.cycles = atmega.usart_cycles_per_char();
+        atmega.tick(None);
+        let mut buf = [0; 1];
+        host.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x80]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    rc::Rc,
+};
+
+use crate::{atmega328p::ATMega328P, serial::SerialBridge};
+
+/// Bridges the USART to a TCP port, like the `socket://` transport of pyserial.
+///
+/// One client is connected at a time, and other clients are turned away until it disconnects.
+/// Transmitted characters are dropped while no client is connected.
+pub struct TcpBridge {
+    listener: TcpListener,
+    client: Rc<RefCell<Option<TcpStream>>>, // shared with the USART output
+}
+
+impl TcpBridge {
+    /// Listens on the address, and connects the USART to the clients
+    pub fn listen(atmega: &mut ATMega328P, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let client: Rc<RefCell<Option<TcpStream>>> = Rc::new(RefCell::new(None));
+
+        let output = client.clone();
+        atmega.usart.add_output(Box::new(move |value| {
+            let mut client = output.borrow_mut();
+            if let Some(stream) = client.as_mut()
+                && let Err(e) = stream.write_all(&[value])
+                && e.kind() != ErrorKind::WouldBlock
+            {
+                *client = None;
+            }
+        }));
+
+        Ok(Self { listener, client })
+    }
+
+    /// Address the bridge listens on, e.g. to find the port picked for port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Whether a client is connected
+    pub fn connected(&self) -> bool {
+        self.client.borrow().is_some()
+    }
+
+    fn accept(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let mut client = self.client.borrow_mut();
+            if client.is_none()
+                && stream.set_nonblocking(true).is_ok()
+                && stream.set_nodelay(true).is_ok()
+            {
+                *client = Some(stream);
+            }
+            // otherwise the stream is dropped, closing the connection
+        }
+    }
+}
+
+impl SerialBridge for TcpBridge {
+    fn poll(&mut self, atmega: &mut ATMega328P) {
+        self.accept();
+        let mut client = self.client.borrow_mut();
+        let Some(stream) = client.as_mut() else {
+            return;
+        };
+        let mut buf = [0; 256];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    *client = None; // disconnected, wait for the next client
+                    break;
+                }
+                Ok(n) => atmega.usart_write_input(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    *client = None;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tcp_tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        peripheral::usart::{UCSRB_TXEN, USART0_CONFIG},
+        serial::{SerialBridge, tcp::TcpBridge},
+    };
+
+    /// Polls the bridge until the condition holds, as the socket is read without blocking
+    fn poll_until(
+        bridge: &mut TcpBridge,
+        atmega: &mut ATMega328P,
+        condition: impl Fn(&TcpBridge, &ATMega328P) -> bool,
+    ) {
+        for _ in 0..1000 {
+            bridge.poll(atmega);
+            if condition(bridge, atmega) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("timed out polling the bridge");
+    }
+
+    #[test]
+    fn mirror_bytes_both_ways() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let mut bridge = TcpBridge::listen(&mut atmega, "127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(bridge.local_addr().unwrap()).unwrap();
+
+        // Act & Assert
+        client.write_all(b"ping").unwrap();
+        poll_until(&mut bridge, &mut atmega, |_, atmega| {
+            atmega.usart_pending_input() == 4
+        });
+
+        atmega.write_data(USART0_CONFIG.UCSRB as u16, UCSRB_TXEN);
+        atmega.write_data(USART0_CONFIG.UDR as u16, b'!');
+        atmega.cpu.cycles = atmega.usart_cycles_per_char();
+        atmega.tick(None);
+        let mut buf = [0; 1];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"!");
+    }
+
+    /// A second client is turned away, and can connect once the first one disconnects
+    #[test]
+    fn one_client_at_a_time() {
+        // Arrange
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        let mut bridge = TcpBridge::listen(&mut atmega, "127.0.0.1:0").unwrap();
+        let addr = bridge.local_addr().unwrap();
+        let first = TcpStream::connect(addr).unwrap();
+        poll_until(&mut bridge, &mut atmega, |bridge, _| bridge.connected());
+
+        // Act & Assert
+        let mut second = TcpStream::connect(addr).unwrap();
+        bridge.poll(&mut atmega);
+        let mut buf = [0; 1];
+        assert_eq!(second.read(&mut buf).unwrap(), 0); // closed by the bridge
+
+        drop(first);
+        poll_until(&mut bridge, &mut atmega, |bridge, _| !bridge.connected());
+        let mut third = TcpStream::connect(addr).unwrap();
+        third.write_all(b"x").unwrap();
+        poll_until(&mut bridge, &mut atmega, |_, atmega| {
+            atmega.usart_pending_input() == 1
+        });
+    }
+}
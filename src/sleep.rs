@@ -0,0 +1,347 @@
+use crate::atmega328p::ATMega328P;
+
+const SMCR: usize = 0x53; // Sleep Mode Control Register
+
+// SMCR bits
+pub const SMCR_SE: u8 = 0x1; // Sleep Enable
+pub const SMCR_SM: u8 = 0xe; // Sleep Mode Select 2:0
+
+// Cycles the MCU is halted for when it wakes up, before executing the interrupt
+const WAKE_UP_CYCLES: u64 = 4;
+
+// Interrupt hander addresses of the wake-up sources
+const INT: [u8; 2] = [0x02, 0x04]; // INT0, INT1
+const PCINT: [u8; 3] = [0x06, 0x08, 0x0a];
+const WDT: u8 = 0x0c;
+const TIMER2: [u8; 3] = [0x0e, 0x10, 0x12];
+const ADC: u8 = 0x2a;
+const EE_READY: u8 = 0x2c;
+const TWI: u8 = 0x30;
+const SPM_READY: u8 = 0x32;
+
+const EICRA: usize = 0x69; // External Interrupt Control Register A
+const ASSR: usize = 0xb6; // Asynchronous Status Register
+const ASSR_AS2: u8 = 0x20; // Timer/Counter2 clocked from the crystal
+
+/// Sleep modes selected by SM2:0 in SMCR.
+/// Note: the clocks stopped by a mode are not simulated, only the interrupts that wake the CPU
+/// up are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepMode {
+    Idle,
+    ADCNoiseReduction,
+    PowerDown,
+    PowerSave,
+    Standby,
+    ExtendedStandby,
+}
+
+impl SleepMode {
+    /// Mode selected by SMCR, None for the reserved values
+    pub fn from_smcr(smcr: u8) -> Option<Self> {
+        match (smcr & SMCR_SM) >> 1 {
+            0 => Some(SleepMode::Idle),
+            1 => Some(SleepMode::ADCNoiseReduction),
+            2 => Some(SleepMode::PowerDown),
+            3 => Some(SleepMode::PowerSave),
+            6 => Some(SleepMode::Standby),
+            7 => Some(SleepMode::ExtendedStandby),
+            _ => None,
+        }
+    }
+
+    /// Whether the interrupt at the given hander address wakes the CPU up in this mode, given
+    /// the data space for the sense control of INT0/1 and the clock of Timer2
+    pub fn wakes_up(&self, interrupt: u8, data: &[u8]) -> bool {
+        // pin changes, TWI address match and the watchdog wake the CPU up in all modes
+        if PCINT.contains(&interrupt) || interrupt == TWI || interrupt == WDT {
+            return true;
+        }
+        if *self == SleepMode::Idle {
+            return true;
+        }
+        let adc_noise_reduction = *self == SleepMode::ADCNoiseReduction;
+        if let Some(index) = INT.iter().position(|&int| int == interrupt) {
+            // Without the I/O clock, only the level interrupts are detected
+            let low_level = (data[EICRA] >> (2 * index)) & 0x3 == 0;
+            return adc_noise_reduction || low_level;
+        }
+        if TIMER2.contains(&interrupt) {
+            // Timer2 keeps counting in asynchronous mode only
+            let asynchronous = data[ASSR] & ASSR_AS2 != 0;
+            return asynchronous
+                && matches!(
+                    self,
+                    SleepMode::ADCNoiseReduction
+                        | SleepMode::PowerSave
+                        | SleepMode::ExtendedStandby
+                );
+        }
+        adc_noise_reduction && [ADC, EE_READY, SPM_READY].contains(&interrupt)
+    }
+}
+
+impl ATMega328P {
+    /// Executes the SLEEP instruction: the CPU sleeps if SE is set in SMCR
+    pub fn sleep(&mut self) {
+        let smcr = self.cpu.data[SMCR];
+        if smcr & SMCR_SE != 0 {
+            self.sleep_mode = SleepMode::from_smcr(smcr);
+        }
+    }
+
+    /// Wakes the CPU up if it is sleeping and any pending interrupt is a wake-up source of the
+    /// sleep mode. Returns false if the interrupts have to wait.
+    pub(crate) fn wake_up(&mut self) -> bool {
+        let Some(mode) = self.sleep_mode else {
+            return true;
+        };
+        let data = &self.cpu.data;
+        let wake_up = self
+            .cpu
+            .pending_interrupts
+            .iter()
+            .flatten()
+            .any(|interrupt| mode.wakes_up(interrupt.address, data));
+        if wake_up {
+            self.sleep_mode = None;
+            self.cpu.cycles += WAKE_UP_CYCLES;
+        }
+        wake_up
+    }
+
+    /// Fast-forwards a sleeping CPU to the next clock event, but no further than `max_cycles`.
+    /// Without any clock event, the CPU sleeps for one cycle.
+    pub(crate) fn sleep_until(&mut self, max_cycles: u64) {
+        let cycles = self.cpu.cycles;
+        let target = match self.cpu.clock_events.next_cycles() {
+            Some(next_cycles) if next_cycles <= cycles => return,
+            Some(next_cycles) => next_cycles.min(max_cycles),
+            None if max_cycles == u64::MAX => cycles + 1,
+            None => max_cycles,
+        };
+        self.cpu.cycles = target.max(cycles + 1);
+    }
+}
+
+#[cfg(test)]
+mod sleep_tests {
+    use crate::{
+        atmega328p::{ATMega328P, DEFAULT_FREQ},
+        error::StepOutcome,
+        peripheral::{
+            adc::{ADC_CONFIG, ADCSRA_ADEN, ADCSRA_ADIE, ADCSRA_ADSC},
+            port::PinInput,
+            timer::{AS2, TIMER_0_CONFIG, TIMER_2_CONFIG},
+        },
+        sleep::{SMCR, SMCR_SE, SleepMode},
+    };
+
+    const SREG: usize = 95;
+    const EICRA: u16 = 0x69;
+    const EIMSK: u16 = 0x3d;
+
+    fn sleeping_atmega(mode: u8) -> ATMega328P {
+        let mut atmega = ATMega328P::new("", DEFAULT_FREQ);
+        atmega.cpu.set_prog_word(0, 0x9588); // SLEEP
+        atmega.cpu.data[SMCR] = (mode << 1) | SMCR_SE;
+        atmega.cpu.data[SREG] = 0x80; // I
+        atmega
+    }
+
+    #[test]
+    fn sleep_needs_se() {
+        // Arrange
+        let mut atmega = sleeping_atmega(0);
+        atmega.cpu.data[SMCR] = 0;
+
+        // Act
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, None);
+    }
+
+    #[test]
+    fn sleep_modes() {
+        for (sm, mode) in [
+            (0, SleepMode::Idle),
+            (1, SleepMode::ADCNoiseReduction),
+            (2, SleepMode::PowerDown),
+            (3, SleepMode::PowerSave),
+            (6, SleepMode::Standby),
+            (7, SleepMode::ExtendedStandby),
+        ] {
+            // Arrange
+            let mut atmega = sleeping_atmega(sm);
+
+            // Act
+            atmega.step(None).unwrap();
+
+            // Assert
+            assert_eq!(atmega.sleep_mode, Some(mode));
+            assert_eq!(atmega.cpu.pc, 1);
+        }
+    }
+
+    #[test]
+    fn idle_fast_forwards_to_timer_overflow() {
+        // Arrange
+        let mut atmega = sleeping_atmega(0);
+        atmega.write_data(TIMER_0_CONFIG.TIMSK as u16, 0x1); // TOIE0
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 0x3); // prescaler 64
+        atmega.step(None).unwrap();
+
+        // Act
+        let mut steps = 0;
+        while atmega.cpu.pc != TIMER_0_CONFIG.ovf_interrupt as u32 {
+            let outcome = atmega.step(None).unwrap();
+            assert_eq!(outcome, StepOutcome::Sleep);
+            steps += 1;
+        }
+
+        // Assert
+        assert!(steps <= 257); // one step per timer count, instead of one per cycle
+        assert_eq!(atmega.sleep_mode, None);
+        assert!(atmega.cpu.cycles > 64 * 256);
+    }
+
+    #[test]
+    fn power_down_ignores_timer0() {
+        // Arrange
+        let mut atmega = sleeping_atmega(2);
+        atmega.write_data(TIMER_0_CONFIG.TIMSK as u16, 0x1); // TOIE0
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 0x1); // no prescaler
+        atmega.step(None).unwrap();
+
+        // Act
+        for _ in 0..300 {
+            atmega.step(None).unwrap();
+        }
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, Some(SleepMode::PowerDown));
+        assert_eq!(atmega.cpu.pc, 1);
+        assert!(atmega.cpu.pending_interrupts[TIMER_0_CONFIG.ovf_interrupt as usize].is_some());
+    }
+
+    #[test]
+    fn power_save_wakes_on_timer2() {
+        // Arrange
+        let mut atmega = sleeping_atmega(3);
+        atmega.write_data(TIMER_2_CONFIG.ASSR as u16, AS2);
+        atmega.write_data(TIMER_2_CONFIG.TIMSK as u16, 0x1); // TOIE2
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, 0x7); // prescaler 1024
+        atmega.step(None).unwrap();
+
+        // Act
+        while atmega.sleep_mode.is_some() {
+            atmega.step(None).unwrap();
+        }
+
+        // Assert
+        assert_eq!(atmega.cpu.pc, TIMER_2_CONFIG.ovf_interrupt as u32);
+        assert!(atmega.cpu.cycles > 1024 * 256);
+    }
+
+    #[test]
+    fn power_save_ignores_synchronous_timer2() {
+        // Arrange
+        let mut atmega = sleeping_atmega(3);
+        atmega.write_data(TIMER_2_CONFIG.TIMSK as u16, 0x1); // TOIE2
+        atmega.write_data(TIMER_2_CONFIG.TCCRB as u16, 0x1); // no prescaler
+        atmega.step(None).unwrap();
+
+        // Act
+        for _ in 0..300 {
+            atmega.step(None).unwrap();
+        }
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, Some(SleepMode::PowerSave));
+        assert!(atmega.cpu.pending_interrupts[TIMER_2_CONFIG.ovf_interrupt as usize].is_some());
+    }
+
+    #[test]
+    fn power_down_wakes_on_int0_low_level() {
+        // Arrange
+        let mut atmega = sleeping_atmega(2);
+        atmega.set_pin_input("D", 2, PinInput::High);
+        atmega.write_data(EICRA, 0x0); // low level
+        atmega.write_data(EIMSK, 0x1);
+        atmega.step(None).unwrap();
+        atmega.step(None).unwrap();
+
+        // Act
+        atmega.set_pin_input("D", 2, PinInput::Low);
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, None);
+        assert_eq!(atmega.cpu.pc, 0x02);
+    }
+
+    #[test]
+    fn power_down_ignores_int0_edge() {
+        // Arrange
+        let mut atmega = sleeping_atmega(2);
+        atmega.write_data(EICRA, 0x3); // rising edge
+        atmega.write_data(EIMSK, 0x1);
+        atmega.step(None).unwrap();
+
+        // Act
+        atmega.set_pin_input("D", 2, PinInput::High);
+        atmega.step(None).unwrap();
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, Some(SleepMode::PowerDown));
+        assert!(atmega.cpu.pending_interrupts[0x02].is_some());
+    }
+
+    #[test]
+    fn adc_noise_reduction_wakes_on_adc_behind_timer0() {
+        // Arrange
+        let mut atmega = sleeping_atmega(1);
+        atmega.write_data(TIMER_0_CONFIG.TIMSK as u16, 0x1); // TOIE0
+        atmega.write_data(TIMER_0_CONFIG.TCCRB as u16, 0x1); // no prescaler
+        atmega.write_data(
+            ADC_CONFIG.ADCSRA as u16,
+            ADCSRA_ADEN | ADCSRA_ADSC | ADCSRA_ADIE | 0x7, // prescaler 128
+        );
+        atmega.step(None).unwrap();
+
+        // Act
+        let mut steps = 0;
+        while atmega.sleep_mode.is_some() {
+            atmega.step(None).unwrap();
+            steps += 1;
+            assert!(steps < 10_000);
+        }
+
+        // Assert
+        assert!(atmega.cpu.cycles > 13 * 128); // woken up by the ADC conversion
+        assert_eq!(atmega.cpu.pc, TIMER_0_CONFIG.ovf_interrupt as u32); // executed first
+        assert!(atmega.cpu.pending_interrupts[ADC_CONFIG.adc_interrupt as usize].is_some());
+    }
+
+    #[test]
+    fn interrupts_disabled_wake_up_after_sleep() {
+        // Arrange
+        let mut atmega = sleeping_atmega(2);
+        atmega.cpu.data[SREG] = 0;
+        atmega.set_pin_input("D", 2, PinInput::High);
+        atmega.write_data(EICRA, 0x0); // low level
+        atmega.write_data(EIMSK, 0x1);
+        atmega.step(None).unwrap();
+
+        // Act
+        atmega.set_pin_input("D", 2, PinInput::Low);
+        atmega.step(None).unwrap();
+        let outcome = atmega.step(None).unwrap(); // NOP after SLEEP
+
+        // Assert
+        assert_eq!(atmega.sleep_mode, None);
+        assert_eq!(outcome, StepOutcome::Executed);
+        assert_eq!(atmega.cpu.pc, 2);
+    }
+}